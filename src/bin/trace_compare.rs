@@ -0,0 +1,46 @@
+extern crate clap;
+
+use clap::{App, Arg};
+use rgb::debug::trace::{compare_trace, divergence_report};
+use rgb::util::get_rom;
+use std::fs;
+use std::process;
+
+// Reference lines printed before the divergence
+const CONTEXT_LINES: usize = 5;
+
+/// Run a rom against a gameboy doctor reference log and print where the cpu first diverges
+fn main() {
+    let matches = App::new("trace_compare")
+        .author("Siyuan Gao <rbnk@elica.io>")
+        .arg(
+            Arg::with_name("rom")
+                .required(true)
+                .value_name("ROM")
+                .help("Rom to run from the post boot state"),
+        )
+        .arg(
+            Arg::with_name("reference")
+                .required(true)
+                .value_name("LOG")
+                .help("Reference log with a trace line before every instruction"),
+        )
+        .get_matches();
+
+    let rom = get_rom(matches.value_of("rom").unwrap());
+    let reference_path = matches.value_of("reference").unwrap();
+    let reference = fs::read_to_string(reference_path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", reference_path, e));
+    let reference: Vec<&str> = reference.lines().collect();
+
+    match compare_trace(rom, &reference) {
+        Ok(steps) => println!("All {} steps match", steps),
+        Err(divergence) => {
+            print!(
+                "{}",
+                divergence_report(&reference, &divergence, CONTEXT_LINES)
+            );
+            process::exit(1);
+        }
+    }
+}
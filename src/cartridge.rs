@@ -1,14 +1,91 @@
+pub mod header;
+pub mod ips;
 mod mbc1;
 mod mbc3;
 mod rom;
-mod rtc;
+pub mod rtc;
 
+use header::{DumpHealth, HeaderInfo};
+use ips::PatchError;
 use mbc1::Mbc1;
 use rom::Rom;
 
-use super::memory::Memory;
+use super::memory::{Memory, OPEN_BUS};
 use super::save::Savable;
 use crate::cartridge::mbc3::Mbc3;
+use std::fmt;
+
+/// The logo in the header at 0x0104 - 0x0133, checked by the boot rom
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+/// The largest rom accepted, 8MB is the most a MBC5 can address
+pub const MAX_ROM_SIZE: usize = 0x80_0000;
+
+#[derive(Debug, PartialEq)]
+pub enum RomSizeError {
+    // Smaller than 32K or not made of whole 16K banks
+    InvalidLength(usize),
+    // Larger than MAX_ROM_SIZE
+    TooLarge(usize),
+}
+
+impl fmt::Display for RomSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomSizeError::InvalidLength(length) => write!(f, "Invalid length: {} bytes", length),
+            RomSizeError::TooLarge(length) => write!(
+                f,
+                "Rom too large: {} bytes, at most {} bytes are supported",
+                length, MAX_ROM_SIZE
+            ),
+        }
+    }
+}
+
+/// Why a rom can not be loaded as a cartridge
+#[derive(Debug, PartialEq)]
+pub enum CartridgeError {
+    RomSize(RomSizeError),
+    // The cartridge type byte at 0x0147 is not a known mapper
+    UnknownType(u8),
+    // The ram size byte at 0x0149 is not a known size
+    InvalidRamSize(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::RomSize(error) => error.fmt(f),
+            CartridgeError::UnknownType(cartridge_type) => {
+                write!(f, "Unknown cartridge type: 0x{:02x}", cartridge_type)
+            }
+            CartridgeError::InvalidRamSize(ram_size) => {
+                write!(f, "Incorrect RAM size 0x{:02x}", ram_size)
+            }
+        }
+    }
+}
+
+impl From<RomSizeError> for CartridgeError {
+    fn from(error: RomSizeError) -> CartridgeError {
+        CartridgeError::RomSize(error)
+    }
+}
+
+/// Roms are at least 32K, always made of whole 16K banks and at most MAX_ROM_SIZE
+pub fn check_rom_size(length: usize) -> Result<(), RomSizeError> {
+    if length > MAX_ROM_SIZE {
+        Err(RomSizeError::TooLarge(length))
+    } else if length < 0x8000 || length % 0x4000 != 0 {
+        Err(RomSizeError::InvalidLength(length))
+    } else {
+        Ok(())
+    }
+}
 
 /// ROM size in  bytes
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -63,6 +140,21 @@ impl CartridgeRamSize {
 /// The savable trait is used for all cartridge types, it implements
 /// some of the shared functionality that all cartridges has
 pub trait Cartridge: Memory + Savable {
+    /// The rom buffer of the cartridge
+    fn rom(&self) -> &[u8];
+    fn rom_mut(&mut self) -> &mut Vec<u8>;
+    /// Apply an IPS patch to the rom, this should be done before the machine starts
+    fn apply_ips(&mut self, patch: &[u8]) -> Result<(), PatchError> {
+        ips::apply_ips(self.rom_mut(), patch)
+    }
+    /// The rom bank mapped at 0x4000 - 0x7fff
+    fn rom_bank(&self) -> usize {
+        1
+    }
+    /// The ram bank mapped at 0xa000 - 0xbfff
+    fn ram_bank(&self) -> usize {
+        0
+    }
     fn title(&self) -> String {
         let mut title = String::new();
         let title_start = 0x134;
@@ -83,6 +175,14 @@ pub trait Cartridge: Memory + Savable {
         }
         title
     }
+    /// The parsed cartridge header
+    fn header_info(&self) -> HeaderInfo {
+        HeaderInfo::from_rom(self.rom(), self.title())
+    }
+    /// Check the rom for the signs of a bad dump
+    fn dump_health(&self) -> DumpHealth {
+        DumpHealth::from_rom(self.rom())
+    }
     fn get_rom_size(&self) -> CartridgeRomSize {
         let rom_size_byte = self.get(0x148);
         CartridgeRomSize::from_u8(rom_size_byte)
@@ -94,60 +194,138 @@ pub trait Cartridge: Memory + Savable {
             .expect(format!("Incorrect RAM size {:04x}", ram_size_byte).as_str())
     }
     fn get_cart_info(&self) -> String {
-        String::from(match self.get(0x147) {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
-            0x02 => "MBC1+RAM",
-            0x03 => "MBC1+RAM+BATTERY",
-            0x05 => "MBC2",
-            0x06 => "MBC2+BATTERY",
-            0x08 => "ROM+RAM",
-            0x09 => "ROM+RAM+BATTERY",
-            0x0b => "MMM01",
-            0x0c => "MMM01+RAM",
-            0x0d => "MMM01+RAM+BATTERY",
-            0x0f => "MBC3+TIMER+BATTERY",
-            0x10 => "MBC3+TIMER+RAM+BATTERY",
-            0x11 => "MBC3",
-            0x12 => "MBC3+RAM",
-            0x13 => "MBC3+RAM+BATTERY",
-            0x15 => "MBC4",
-            0x16 => "MBC4+RAM",
-            0x17 => "MBC4+RAM+BATTERY",
-            0x19 => "MBC5",
-            0x1a => "MBC5+RAM",
-            0x1b => "MBC5+RAM+BATTERY",
-            0x1c => "MBC5+RUMBLE",
-            0x1d => "MBC5+RUMBLE+RAM",
-            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
-            0xfc => "POCKET CAMERA",
-            0xfd => "BANDAI TAMA5",
-            0xfe => "HuC3",
-            0x1f => "HuC1+RAM+BATTERY",
-            n => panic!("Unknown cartridge type: 0x{:02x}", n),
-        })
-    }
-}
-
-pub fn load_cartridge(rom: Vec<u8>) -> Box<dyn Cartridge> {
-    if rom.len() < 0x8000 || rom.len() % 0x4000 != 0 {
-        panic!("Invalid length: {} bytes", rom.len());
-    }
-
-    let ram_size_byte = rom[0x149];
-    let ram_size = CartridgeRamSize::from_u8(ram_size_byte)
-        .expect(format!("Incorrect RAM size {:04x}", ram_size_byte).as_str());
+        let cartridge_type = self.get(0x147);
+        match cartridge_type_name(cartridge_type) {
+            Some(name) => String::from(name),
+            None => panic!("Unknown cartridge type: 0x{:02x}", cartridge_type),
+        }
+    }
+}
+
+/// The name of the mapper for the cartridge type byte, None for an unknown type
+fn cartridge_type_name(cartridge_type: u8) -> Option<&'static str> {
+    Some(match cartridge_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0b => "MMM01",
+        0x0c => "MMM01+RAM",
+        0x0d => "MMM01+RAM+BATTERY",
+        0x0f => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x15 => "MBC4",
+        0x16 => "MBC4+RAM",
+        0x17 => "MBC4+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1a => "MBC5+RAM",
+        0x1b => "MBC5+RAM+BATTERY",
+        0x1c => "MBC5+RUMBLE",
+        0x1d => "MBC5+RUMBLE+RAM",
+        0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+        0xfc => "POCKET CAMERA",
+        0xfd => "BANDAI TAMA5",
+        0xfe => "HuC3",
+        0xff => "HuC1+RAM+BATTERY",
+        _ => return None,
+    })
+}
+
+/// Whether the cartridge type has a battery backing the external ram, only those are persisted
+fn has_battery(cartridge_type: u8) -> bool {
+    matches!(
+        cartridge_type,
+        0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x17 | 0x1b | 0x1e | 0xff
+    )
+}
+
+/// Read the byte at the index of the banked rom. Banks past the size declared in the header wrap
+/// around as the upper bank lines are not connected, reads past the end of a truncated dump
+/// return open bus
+fn banked_rom_byte(rom: &[u8], index: usize) -> u8 {
+    let size = CartridgeRomSize::from_u8(rom[0x148])
+        .map(|size| (size as usize).max(rom.len()))
+        .unwrap_or_else(|| rom.len());
+    rom.get(index % size).copied().unwrap_or(OPEN_BUS)
+}
+
+/// Replace the ram with the content of a save file. A save of another size is from a different
+/// cart or corrupted, it is ignored and the ram stays cleared
+fn restore_ram(ram: &mut Vec<u8>, save: Vec<u8>) {
+    if save.len() == ram.len() {
+        *ram = save;
+    } else {
+        warn!(
+            "Ignoring a save of {} bytes, the cartridge has {} bytes of ram",
+            save.len(),
+            ram.len()
+        );
+    }
+}
+
+/// Mappers that support external ram always get ram allocated, some carts use the ram without
+/// declaring it in the header and would otherwise write to nothing
+fn mapper_ram_size(ram_size: CartridgeRamSize) -> usize {
+    match ram_size {
+        CartridgeRamSize::NoRam => {
+            warn!(
+                "Cartridge header declares no ram, allocating {:?} for the mapper",
+                CartridgeRamSize::Ram8K
+            );
+            CartridgeRamSize::Ram8K as usize
+        }
+        size => size as usize,
+    }
+}
+
+/// Check the size and the header fields the loader depends on, a rom passing this can be loaded
+pub fn check_cartridge(rom: &[u8]) -> Result<(), CartridgeError> {
+    check_rom_size(rom.len())?;
+    if cartridge_type_name(rom[0x147]).is_none() {
+        return Err(CartridgeError::UnknownType(rom[0x147]));
+    }
+    if CartridgeRamSize::from_u8(rom[0x149]).is_none() {
+        return Err(CartridgeError::InvalidRamSize(rom[0x149]));
+    }
+    Ok(())
+}
+
+pub fn load_cartridge(rom: Vec<u8>) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    check_cartridge(&rom)?;
+
+    let ram_size = CartridgeRamSize::from_u8(rom[0x149]).unwrap_or(CartridgeRamSize::NoRam);
+    let battery = has_battery(rom[0x147]);
     let cartridge: Box<dyn Cartridge> = match rom[0x147] {
         0x00 => Box::new(Rom::new(rom)),
-        0x01 => Box::new(Mbc1::new(rom, ram_size as usize)),
-        0x13 => Box::new(Mbc3::new(rom, ram_size as usize)),
-        _ => unimplemented!(),
+        0x08 | 0x09 => Box::new(Rom::with_ram(rom, mapper_ram_size(ram_size), battery)),
+        0x01..=0x03 => Box::new(Mbc1::new(rom, mapper_ram_size(ram_size), battery)),
+        0x0f..=0x13 => Box::new(Mbc3::new(rom, mapper_ram_size(ram_size), battery)),
+        _ => {
+            // Recognized mappers without an implementation run without banking, writes to the
+            // mapper registers are ignored
+            let cartridge = Rom::new(rom);
+            warn!(
+                "{} is not implemented, running without bank switching",
+                cartridge.get_cart_info()
+            );
+            Box::new(cartridge)
+        }
     };
 
     debug!("Loaded cartridge: {}", cartridge.title());
     debug!("Cartridge type is: {}", cartridge.get_cart_info());
-    debug!("Cartridge rom size is: {:?}", cartridge.get_rom_size());
-    debug!("Cartridge ram size is: {:?}", cartridge.get_ram_size());
+    debug!("Cartridge header:\n{}", cartridge.header_info());
+    let dump_health = cartridge.dump_health();
+    if !dump_health.is_healthy() {
+        warn!("The rom looks like a bad dump:\n{}", dump_health);
+    }
 
-    cartridge
+    Ok(cartridge)
 }
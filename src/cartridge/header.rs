@@ -0,0 +1,154 @@
+use super::{CartridgeRamSize, CartridgeRomSize, NINTENDO_LOGO};
+use std::fmt;
+
+/// The publisher of the game, older games use a single byte code and newer ones two characters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+/// The destination code at 0x014a
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Region {
+    Japan,
+    Overseas,
+}
+
+/// The fields of the cartridge header at 0x0100 - 0x014f
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderInfo {
+    pub title: String,
+    pub manufacturer_code: String,
+    pub cgb_flag: u8,
+    pub licensee: Licensee,
+    pub sgb_flag: bool,
+    pub cartridge_type: u8,
+    // None when the header has a size code that does not exist
+    pub rom_size: Option<CartridgeRomSize>,
+    pub ram_size: Option<CartridgeRamSize>,
+    pub region: Region,
+    pub version: u8,
+    pub header_checksum_ok: bool,
+    pub global_checksum_ok: bool,
+}
+
+// Read the printable characters of a header field, the field ends at the first 0
+fn header_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|c| **c != 0x00)
+        .map(|c| *c as char)
+        .collect()
+}
+
+/// The checksum of 0x0134 - 0x014c the boot rom verifies against 0x014d
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+/// The sum of every byte of the rom except the checksum itself at 0x014e - 0x014f
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(address, _)| *address != 0x14e && *address != 0x14f)
+        .fold(0u16, |sum, (_, byte)| sum.wrapping_add(u16::from(*byte)))
+}
+
+impl HeaderInfo {
+    /// Parse the header of the rom, the title is read as Cartridge::title does
+    pub fn from_rom(rom: &[u8], title: String) -> HeaderInfo {
+        let licensee = match rom[0x14b] {
+            // 0x33 means the code is in the new licensee field
+            0x33 => Licensee::New(header_string(&rom[0x144..=0x145])),
+            code => Licensee::Old(code),
+        };
+        HeaderInfo {
+            title,
+            manufacturer_code: header_string(&rom[0x13f..=0x142]),
+            cgb_flag: rom[0x143],
+            licensee,
+            sgb_flag: rom[0x146] == 0x03,
+            cartridge_type: rom[0x147],
+            rom_size: CartridgeRomSize::from_u8(rom[0x148]),
+            ram_size: CartridgeRamSize::from_u8(rom[0x149]),
+            region: match rom[0x14a] {
+                0x00 => Region::Japan,
+                _ => Region::Overseas,
+            },
+            version: rom[0x14c],
+            header_checksum_ok: header_checksum(rom) == rom[0x14d],
+            global_checksum_ok: global_checksum(rom)
+                == u16::from_be_bytes([rom[0x14e], rom[0x14f]]),
+        }
+    }
+}
+
+impl fmt::Display for HeaderInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Title: {}", self.title)?;
+        writeln!(f, "Manufacturer: {}", self.manufacturer_code)?;
+        writeln!(f, "CGB flag: {:02x}", self.cgb_flag)?;
+        writeln!(f, "Licensee: {:?}", self.licensee)?;
+        writeln!(f, "SGB: {}", self.sgb_flag)?;
+        writeln!(f, "Type: {:02x}", self.cartridge_type)?;
+        writeln!(f, "ROM size: {:?}", self.rom_size)?;
+        writeln!(f, "RAM size: {:?}", self.ram_size)?;
+        writeln!(f, "Region: {:?}", self.region)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Header checksum ok: {}", self.header_checksum_ok)?;
+        write!(f, "Global checksum ok: {}", self.global_checksum_ok)
+    }
+}
+
+/// Signs of a bad dump found in the rom, a healthy rom has none of them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DumpHealth {
+    pub header_checksum_ok: bool,
+    pub global_checksum_ok: bool,
+    pub logo_ok: bool,
+    // The length of the file matches the rom size in the header
+    pub size_ok: bool,
+    // The 16K banks filled with only 0xff or only 0x00, usually not read by the dumper
+    pub blank_banks: Vec<usize>,
+}
+
+impl DumpHealth {
+    pub fn from_rom(rom: &[u8]) -> DumpHealth {
+        let blank_banks = rom
+            .chunks(0x4000)
+            .enumerate()
+            .filter(|(_, bank)| bank.iter().all(|b| *b == 0xff) || bank.iter().all(|b| *b == 0x00))
+            .map(|(bank, _)| bank)
+            .collect();
+        DumpHealth {
+            header_checksum_ok: header_checksum(rom) == rom[0x14d],
+            global_checksum_ok: global_checksum(rom)
+                == u16::from_be_bytes([rom[0x14e], rom[0x14f]]),
+            logo_ok: rom[0x104..0x134] == NINTENDO_LOGO[..],
+            size_ok: CartridgeRomSize::from_u8(rom[0x148]).map(|size| size as usize)
+                == Some(rom.len()),
+            blank_banks,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.header_checksum_ok
+            && self.global_checksum_ok
+            && self.logo_ok
+            && self.size_ok
+            && self.blank_banks.is_empty()
+    }
+}
+
+impl fmt::Display for DumpHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Header checksum ok: {}", self.header_checksum_ok)?;
+        writeln!(f, "Global checksum ok: {}", self.global_checksum_ok)?;
+        writeln!(f, "Logo ok: {}", self.logo_ok)?;
+        writeln!(f, "Size ok: {}", self.size_ok)?;
+        write!(f, "Blank banks: {:?}", self.blank_banks)
+    }
+}
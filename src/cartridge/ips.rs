@@ -0,0 +1,59 @@
+use std::fmt;
+
+const HEADER: &[u8] = b"PATCH";
+const FOOTER: &[u8] = b"EOF";
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    // The patch does not start with "PATCH"
+    InvalidHeader,
+    // The patch ended in the middle of a record or without the "EOF" marker
+    Truncated,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::InvalidHeader => write!(f, "IPS patch is missing the PATCH header"),
+            PatchError::Truncated => write!(f, "IPS patch is truncated"),
+        }
+    }
+}
+
+/// Apply an IPS patch to the rom, records past the end of the rom grow it
+///
+/// An IPS patch is "PATCH" followed by records and "EOF". Each record is a 3 byte offset and a 2
+/// byte size followed by that many bytes. A size of 0 is a run length record, with a 2 byte count
+/// and the byte to repeat.
+pub fn apply_ips(rom: &mut Vec<u8>, patch: &[u8]) -> Result<(), PatchError> {
+    if !patch.starts_with(HEADER) {
+        return Err(PatchError::InvalidHeader);
+    }
+    let mut position = HEADER.len();
+    let mut read = |length: usize| -> Result<&[u8], PatchError> {
+        let bytes = patch
+            .get(position..position + length)
+            .ok_or(PatchError::Truncated)?;
+        position += length;
+        Ok(bytes)
+    };
+    loop {
+        let offset = read(3)?;
+        if offset == FOOTER {
+            return Ok(());
+        }
+        let offset = (offset[0] as usize) << 16 | (offset[1] as usize) << 8 | offset[2] as usize;
+        let size = read(2)?;
+        let size = (size[0] as usize) << 8 | size[1] as usize;
+        let data = if size == 0 {
+            let run = read(3)?;
+            vec![run[2]; (run[0] as usize) << 8 | run[1] as usize]
+        } else {
+            read(size)?.to_vec()
+        };
+        if rom.len() < offset + data.len() {
+            rom.resize(offset + data.len(), 0x00);
+        }
+        rom[offset..offset + data.len()].copy_from_slice(&data);
+    }
+}
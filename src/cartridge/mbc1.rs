@@ -1,6 +1,8 @@
-use super::Cartridge;
-use crate::memory::Memory;
+use super::{banked_rom_byte, restore_ram, Cartridge, NINTENDO_LOGO};
+use crate::memory::{Memory, OPEN_BUS};
 use crate::save::Savable;
+use std::fs::File;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 
 pub struct Mbc1 {
@@ -9,6 +11,18 @@ pub struct Mbc1 {
     bank: usize,
     bank_mode: BankMode,
     ram_enabled: bool,
+    battery: bool,
+    // MBC1M wires the upper bank bits to rom address bit 18 instead of 19, selecting one of the
+    // 256K games of a multicart
+    multicart: bool,
+}
+
+/// MBC1M multicarts are 1M roms where every 256K game has its own header, detected by the logo
+/// at the start of the second game
+fn is_multicart(rom: &[u8]) -> bool {
+    let second_game_logo = 0x10 * 0x4000 + 0x104;
+    rom.len() == 0x100000
+        && rom[second_game_logo..second_game_logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO[..]
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -21,44 +35,77 @@ enum BankMode {
 /// Will include ram or battery or both
 /// Bank will be selected to bank 1
 impl Mbc1 {
-    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> Mbc1 {
+        let multicart = is_multicart(&rom);
+        if multicart {
+            debug!("Detected MBC1M multicart");
+        }
         Self {
+            multicart,
             rom,
             ram: vec![0; ram_size],
             bank: 0x01,
             bank_mode: BankMode::Rom,
             ram_enabled: false,
+            battery,
         }
     }
+
+    // The rom bank mapped at 0x0000 - 0x3fff and at 0x4000 - 0x7fff on a multicart
+    fn multicart_banks(&self) -> (usize, usize) {
+        let game = (self.bank & 0x60) >> 5 << 4;
+        let zero_bank = if self.bank_mode == BankMode::Ram {
+            game
+        } else {
+            0x00
+        };
+        (zero_bank, game | (self.bank & 0x0f))
+    }
+
+    fn selected_rom_bank(&self) -> usize {
+        if self.multicart {
+            self.multicart_banks().1
+        } else if self.bank_mode == BankMode::Ram {
+            self.bank & 0x1f
+        } else {
+            self.bank & 0x7f
+        }
+    }
+
+    fn selected_ram_bank(&self) -> usize {
+        if self.bank_mode == BankMode::Ram {
+            (self.bank & 0x60) >> 5
+        } else {
+            0x00
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> usize {
+        // Carts with less than a full bank of ram (2KB) mirror it across the whole range
+        (address as usize - 0xa000 + self.selected_ram_bank() * 0x2000) % self.ram.len()
+    }
 }
 
 impl Memory for Mbc1 {
     fn get(&self, address: u16) -> u8 {
         match address {
+            0x0000..=0x3fff if self.multicart => banked_rom_byte(
+                &self.rom,
+                address as usize + self.multicart_banks().0 * 0x4000,
+            ),
             0x0000..=0x3fff => self.rom[address as usize],
             0x4000..=0x7fff => {
-                let selected_bank = if self.bank_mode == BankMode::Ram {
-                    self.bank & 0x1f
-                } else {
-                    self.bank & 0x7f
-                } as usize;
-                let offset = selected_bank * 0x4000;
-                self.rom[address as usize - 0x4000 + offset]
+                let offset = self.selected_rom_bank() * 0x4000;
+                banked_rom_byte(&self.rom, address as usize - 0x4000 + offset)
             }
             0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    let selected_bank = if self.bank_mode == BankMode::Ram {
-                        (self.bank & 0x60) >> 5
-                    } else {
-                        0x00
-                    } as usize;
-                    let offset = selected_bank * 0x2000;
-                    self.ram[address as usize - 0xa000 + offset]
+                    self.ram[self.ram_offset(address)]
                 } else {
-                    0x00
+                    OPEN_BUS
                 }
             }
-            _ => 0x00,
+            _ => OPEN_BUS,
         }
     }
 
@@ -87,24 +134,59 @@ impl Memory for Mbc1 {
             }
             // The program may freely switch between both modes, the only limitation is that
             // only RAM Bank 00h can be used during Mode 0, and only ROM Banks 00-1Fh can be used during Mode 1.
-            0x6000..=0x7fff => match value {
+            // Only bit 0 selects the mode, the other bits are ignored
+            0x6000..=0x7fff => match value & 0x01 {
                 0x00 => self.bank_mode = BankMode::Rom,
-                0x01 => self.bank_mode = BankMode::Ram,
-                n => panic!("Invalid bank mode selector type {:04x}", n),
+                _ => self.bank_mode = BankMode::Ram,
             },
+            0xa000..=0xbfff if self.ram_enabled => {
+                let offset = self.ram_offset(address);
+                self.ram[offset] = value;
+            }
             _ => {}
         }
     }
+
+    fn rom_mapping(&self) -> Option<usize> {
+        // Multicarts also bank 0x0000 - 0x3fff
+        Some(self.multicart_banks().0 << 8 | self.selected_rom_bank())
+    }
 }
 
 impl Savable for Mbc1 {
-    fn save(&self, _: PathBuf) {
-        unimplemented!()
+    fn save(&self, save_path: PathBuf) {
+        // Without a battery the ram content is lost when powered off
+        if !self.battery {
+            return;
+        }
+        File::create(save_path)
+            .and_then(|mut f| f.write_all(&self.ram))
+            .unwrap();
     }
 
-    fn load(&mut self, _: PathBuf) {
-        unimplemented!()
+    fn load(&mut self, save_path: PathBuf) {
+        if !self.battery {
+            return;
+        }
+        if let Ok(mut file) = File::open(save_path) {
+            let mut ram = Vec::new();
+            file.read_to_end(&mut ram).unwrap();
+            restore_ram(&mut self.ram, ram);
+        }
     }
 }
 
-impl Cartridge for Mbc1 {}
+impl Cartridge for Mbc1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+    fn rom_bank(&self) -> usize {
+        self.selected_rom_bank()
+    }
+    fn ram_bank(&self) -> usize {
+        self.selected_ram_bank()
+    }
+}
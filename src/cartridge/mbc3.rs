@@ -1,10 +1,11 @@
-use super::rtc::RealTimeClock;
-use super::Cartridge;
-use crate::memory::Memory;
+use super::rtc::{RealTimeClock, RTC_FOOTER_SIZE};
+use super::{banked_rom_byte, restore_ram, Cartridge};
+use crate::memory::{Memory, OPEN_BUS};
 use crate::save::Savable;
 use std::fs::File;
 use std::io::{Error, Read, Write};
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 /// MBC3 - Memory Bank Controller 3
 /// Can include additional RAM, battery, timer
@@ -16,10 +17,14 @@ pub struct Mbc3 {
     ram_bank: usize,
     rtc: RealTimeClock,
     ram_enabled: bool,
+    battery: bool,
+    // Carts with the timer store the clock as a footer after the ram in the save file
+    timer: bool,
 }
 
 impl Mbc3 {
-    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> Mbc3 {
+        let timer = matches!(rom[0x147], 0x0f | 0x10);
         Self {
             rom,
             ram: vec![0; ram_size],
@@ -27,8 +32,15 @@ impl Mbc3 {
             ram_bank: 0,
             ram_enabled: false,
             rtc: RealTimeClock::new(),
+            battery,
+            timer,
         }
     }
+
+    // Carts with less than a full bank of ram (2KB) mirror it across the whole range
+    fn ram_offset(&self, address: u16) -> usize {
+        (self.ram_bank * 0x2000 + address as usize - 0xa000) % self.ram.len()
+    }
 }
 
 impl Memory for Mbc3 {
@@ -38,22 +50,22 @@ impl Memory for Mbc3 {
             0x4000..=0x7fff => {
                 // Rom banks 01-7F (Read only)
                 let offset = self.rom_bank * 0x4000;
-                self.rom[address as usize - 0x4000 + offset]
+                banked_rom_byte(&self.rom, address as usize - 0x4000 + offset)
             }
             0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    if self.ram_bank <= 0x03 {
+                    match self.ram_bank {
                         // Ram bank 00-03 is actual ram banks
-                        self.ram[self.ram_bank * 0x2000 + address as usize - 0xa000]
-                    } else {
+                        0x00..=0x03 => self.ram[self.ram_offset(address)],
                         // Ram bank 08-0C means we are reading from RTC
-                        self.rtc.get(self.ram_bank as u16)
+                        0x08..=0x0c => self.rtc.get(self.ram_bank as u16),
+                        _ => OPEN_BUS,
                     }
                 } else {
-                    0
+                    OPEN_BUS
                 }
             }
-            _ => 0x00,
+            _ => OPEN_BUS,
         }
     }
 
@@ -90,39 +102,63 @@ impl Memory for Mbc3 {
             }
             0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    if self.ram_bank <= 0x03 {
+                    match self.ram_bank {
                         // Ram bank 00-03 is actual ram banks
-                        self.ram[self.ram_bank * 0x2000 + address as usize - 0xa000] = value;
-                    } else {
+                        0x00..=0x03 => {
+                            let offset = self.ram_offset(address);
+                            self.ram[offset] = value;
+                        }
                         // Ram bank 08-0C means we are setting from RTC
-                        self.rtc.set(self.ram_bank as u16, value);
+                        0x08..=0x0c => self.rtc.set(self.ram_bank as u16, value),
+                        _ => {}
                     }
                 }
             }
             _ => {}
         }
     }
+
+    fn rom_mapping(&self) -> Option<usize> {
+        Some(self.rom_bank)
+    }
 }
 
 impl Savable for Mbc3 {
     fn save(&self, save_path: PathBuf) {
-        let mut rtc = PathBuf::from(save_path.clone());
-        rtc.set_extension("rtc");
-        self.rtc.save(rtc);
-        File::create(save_path.clone())
-            .and_then(|mut f| f.write_all(&self.ram))
+        // Without a battery the ram content is lost when powered off
+        if !self.battery {
+            return;
+        }
+        let mut save = self.ram.clone();
+        if self.timer {
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            save.extend_from_slice(&self.rtc.to_footer(timestamp));
+        }
+        File::create(save_path)
+            .and_then(|mut f| f.write_all(&save))
             .unwrap();
     }
 
     fn load(&mut self, save_path: PathBuf) {
-        let mut rtc = PathBuf::from(save_path.clone());
-        rtc.set_extension("rtc");
-        self.rtc.load(rtc);
-        match File::open(save_path) {
+        if !self.battery {
+            return;
+        }
+        match File::open(save_path.clone()) {
             Ok(mut file) => {
                 let mut ram = Vec::new();
                 file.read_to_end(&mut ram).unwrap();
-                self.ram = ram;
+                if self.timer && ram.len() == self.ram.len() + RTC_FOOTER_SIZE {
+                    self.rtc.load_footer(&ram.split_off(self.ram.len()));
+                } else if self.timer {
+                    // Saves from before the footer kept the clock in a separate file
+                    let mut rtc = save_path;
+                    rtc.set_extension("rtc");
+                    self.rtc.load(rtc);
+                }
+                restore_ram(&mut self.ram, ram);
             }
             Err(_) => {
                 // panic!("Error reading save file");
@@ -131,4 +167,17 @@ impl Savable for Mbc3 {
     }
 }
 
-impl Cartridge for Mbc3 {}
+impl Cartridge for Mbc3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+}
@@ -1,36 +1,88 @@
-use super::Cartridge;
-use crate::memory::Memory;
+use super::{restore_ram, Cartridge};
+use crate::memory::{Memory, OPEN_BUS};
 use crate::save::Savable;
+use std::fs::File;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 
+/// A cartridge without a mapper, ROM+RAM carts have up to 8K of ram that is always enabled
 pub struct Rom {
     rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
 }
 
 impl Rom {
     pub fn new(rom: Vec<u8>) -> Rom {
-        Self { rom }
+        Rom::with_ram(rom, 0, false)
+    }
+
+    pub fn with_ram(rom: Vec<u8>, ram_size: usize, battery: bool) -> Rom {
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+            battery,
+        }
     }
 }
 
 impl Memory for Rom {
     fn get(&self, address: u16) -> u8 {
-        self.rom[address as usize]
+        match address {
+            0x0000..=0x7fff => self.rom[address as usize],
+            // Carts with less than 8K of ram mirror it across the whole range
+            0xa000..=0xbfff if !self.ram.is_empty() => {
+                self.ram[(address as usize - 0xa000) % self.ram.len()]
+            }
+            // There is no external ram on a rom only cartridge
+            _ => OPEN_BUS,
+        }
     }
 
     fn set(&mut self, address: u16, value: u8) {
-        // Rom will not allow set actions
+        match address {
+            0xa000..=0xbfff if !self.ram.is_empty() => {
+                let offset = (address as usize - 0xa000) % self.ram.len();
+                self.ram[offset] = value;
+            }
+            // Rom will not allow set actions
+            _ => trace!("Ignored write of ${:02x} to ${:04x}", value, address),
+        }
+    }
+
+    fn rom_mapping(&self) -> Option<usize> {
+        Some(0)
     }
 }
 
 impl Savable for Rom {
-    fn save(&self, _: PathBuf) {
-        // Will not save anything for the Rom, no state to persist
+    fn save(&self, save_path: PathBuf) {
+        // Only the ram of ROM+RAM+BATTERY carts is persisted
+        if !self.battery {
+            return;
+        }
+        File::create(save_path)
+            .and_then(|mut f| f.write_all(&self.ram))
+            .unwrap();
     }
 
-    fn load(&mut self, _: PathBuf) {
-        // Will not load anything for the Rom, no state to load from
+    fn load(&mut self, save_path: PathBuf) {
+        if !self.battery {
+            return;
+        }
+        if let Ok(mut file) = File::open(save_path) {
+            let mut ram = Vec::new();
+            file.read_to_end(&mut ram).unwrap();
+            restore_ram(&mut self.ram, ram);
+        }
     }
 }
 
-impl Cartridge for Rom {}
+impl Cartridge for Rom {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
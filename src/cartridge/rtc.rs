@@ -17,6 +17,25 @@ use std::time::SystemTime;
 //        Bit 6  Halt (0=Active, 1=Stop Timer)
 //        Bit 7  Day Counter Carry Bit (1=Counter Overflow)
 // The Halt Flag is supposed to be set before <writing> to the RTC Registers.
+
+// The footer other emulators (VBA, BGB) append to the cartridge ram in .sav files, all fields are
+// little endian
+//  00h  4 bytes each  Current S, M, H, DL, DH, the time when the file was saved
+//  14h  4 bytes each  Latched S, M, H, DL, DH, the values the game reads
+//  28h  8 bytes       Unix timestamp of the save
+pub const RTC_FOOTER_SIZE: usize = 48;
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// Read the 4 byte little endian field at the index of the footer, only the low byte is used
+fn footer_field(footer: &[u8], index: usize) -> u8 {
+    footer[index * 4]
+}
 #[derive(Debug)]
 struct ClockRegisters {
     S: u8,
@@ -26,18 +45,60 @@ struct ClockRegisters {
     DH: u8,
 }
 
+impl ClockRegisters {
+    // The registers after the given number of seconds, the halt and carry bits are kept from dh
+    fn from_seconds(seconds: u64, dh: u8) -> ClockRegisters {
+        let days = seconds / 3600 / 24;
+        let mut dh = (dh & 0xfe) | ((days >> 8) & 0x01) as u8;
+        if days > 0x01ff {
+            dh |= 0x80;
+        }
+        ClockRegisters {
+            S: (seconds % 60) as u8,
+            M: (seconds / 60 % 60) as u8,
+            H: (seconds / 3600 % 24) as u8,
+            DL: (days % 256) as u8,
+            DH: dh,
+        }
+    }
+
+    // The registers stored from the field index of the footer
+    fn from_footer(footer: &[u8], index: usize) -> ClockRegisters {
+        ClockRegisters {
+            S: footer_field(footer, index),
+            M: footer_field(footer, index + 1),
+            H: footer_field(footer, index + 2),
+            DL: footer_field(footer, index + 3),
+            DH: footer_field(footer, index + 4),
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 5] {
+        [self.S, self.M, self.H, self.DL, self.DH]
+    }
+
+    // The time the registers count, without the day carry
+    fn seconds(&self) -> u64 {
+        let days = u64::from(self.DL) | (u64::from(self.DH & 0x01) << 8);
+        u64::from(self.S) + u64::from(self.M) * 60 + u64::from(self.H) * 3600 + days * 86400
+    }
+}
+
 #[derive(Debug)]
 pub struct RealTimeClock {
     epoch: u64,
     reg: ClockRegisters,
 }
 
+impl Default for RealTimeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RealTimeClock {
     pub fn new() -> Self {
-        let epoch = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let epoch = unix_time();
         Self {
             epoch,
             reg: ClockRegisters {
@@ -53,27 +114,33 @@ impl RealTimeClock {
     /// not change. In our implementation, the clock is always ticking in the background (system clock)
     /// during a latch event, we will call tick() to update the registers with the data values.
     pub fn tick(&mut self) {
-        let delta = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            - self.epoch;
-
-        self.reg.S = (delta % 60) as u8;
-        self.reg.M = (delta / 60 % 60) as u8;
-        self.reg.H = (delta / 3600 % 24) as u8;
-        let days = (delta / 3600 / 24) as u16;
-        self.reg.DL = (days % 256) as u8;
-        match days {
-            0x0000..=0x00ff => {}
-            0x0100..=0x01ff => {
-                self.reg.DH |= 0x01;
-            }
-            _ => {
-                self.reg.DH |= 0x01;
-                self.reg.DH |= 0x80;
-            }
+        self.reg = ClockRegisters::from_seconds(unix_time() - self.epoch, self.reg.DH);
+    }
+
+    /// The footer in the layout used by other emulators, written at the given unix time
+    pub fn to_footer(&self, timestamp: u64) -> [u8; RTC_FOOTER_SIZE] {
+        let current = ClockRegisters::from_seconds(timestamp.saturating_sub(self.epoch), 0x00);
+        let mut footer = [0x00; RTC_FOOTER_SIZE];
+        for (index, value) in current
+            .to_bytes()
+            .iter()
+            .chain(self.reg.to_bytes().iter())
+            .enumerate()
+        {
+            footer[index * 4] = *value;
         }
+        footer[0x28..].copy_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+
+    /// Restore the clock from a footer written by to_footer or another emulator
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE, "Invalid RTC footer length");
+        let mut timestamp: [u8; 8] = Default::default();
+        timestamp.copy_from_slice(&footer[0x28..]);
+        let current = ClockRegisters::from_footer(footer, 0);
+        self.epoch = u64::from_le_bytes(timestamp).saturating_sub(current.seconds());
+        self.reg = ClockRegisters::from_footer(footer, 5);
     }
 }
 
@@ -115,10 +182,7 @@ impl Savable for RealTimeClock {
                 bytes.copy_from_slice(&content);
                 u64::from_be_bytes(bytes)
             }
-            Err(_) => SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            Err(_) => unix_time(),
         };
 
         self.epoch = epoch;
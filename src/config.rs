@@ -0,0 +1,183 @@
+use crate::input::joypad::SocdMode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The config file read at startup, created with the default values when missing
+pub const CONFIG_PATH: &str = "rgb.toml";
+
+/// The options that can be kept in rgb.toml, named like the command line flags. Options missing
+/// from the file are None and the built in defaults are used
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    pub scale: Option<u32>,
+    pub pause_on_boot: Option<bool>,
+    pub hide_debug: Option<bool>,
+    pub pause_on_unfocus: Option<bool>,
+    pub fast_boot: Option<bool>,
+    pub unthrottled: Option<bool>,
+    pub speed: Option<f64>,
+    // allow, neutral or last-wins
+    pub socd: Option<String>,
+    pub skip_idle_loops: Option<bool>,
+    pub smooth_palettes: Option<bool>,
+    pub sync_to_display: Option<u32>,
+}
+
+impl Config {
+    /// The config written when there is no config file, with the default of every option that
+    /// has one
+    pub fn defaults() -> Config {
+        let options = Options::default();
+        Config {
+            scale: Some(options.scale),
+            pause_on_boot: Some(options.pause_on_boot),
+            hide_debug: Some(options.hide_debug),
+            pause_on_unfocus: Some(options.pause_on_unfocus),
+            fast_boot: Some(options.fast_boot),
+            unthrottled: Some(options.unthrottled),
+            speed: Some(options.speed),
+            socd: Some(String::from(socd_name(options.socd))),
+            skip_idle_loops: Some(options.skip_idle_loops),
+            smooth_palettes: Some(options.smooth_palettes),
+            sync_to_display: options.sync_to_display,
+        }
+    }
+
+    /// Parse a config file, values out of range are reported with the name of their key
+    pub fn parse(source: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(source).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the values the command line validates for its flags
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(scale) = self.scale {
+            if scale == 0 {
+                return Err(String::from("scale must be at least 1"));
+            }
+        }
+        if let Some(speed) = self.speed {
+            if !(speed > 0.0 && speed.is_finite()) {
+                return Err(format!("speed must be a positive number, got {}", speed));
+            }
+        }
+        if let Some(socd) = &self.socd {
+            if socd_mode(socd).is_none() {
+                return Err(format!(
+                    "socd must be allow, neutral or last-wins, got \"{}\"",
+                    socd
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the config file, it is created with the defaults when it does not exist
+    pub fn load_or_create(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(source) => Config::parse(&source),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let config = Config::defaults();
+                let source = toml::to_string(&config).map_err(|e| e.to_string())?;
+                fs::write(path, source).map_err(|e| e.to_string())?;
+                Ok(config)
+            }
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    /// Combine with the options given on the command line, they take precedence over the file
+    pub fn merge(self, command_line: Config) -> Config {
+        Config {
+            scale: command_line.scale.or(self.scale),
+            pause_on_boot: command_line.pause_on_boot.or(self.pause_on_boot),
+            hide_debug: command_line.hide_debug.or(self.hide_debug),
+            pause_on_unfocus: command_line.pause_on_unfocus.or(self.pause_on_unfocus),
+            fast_boot: command_line.fast_boot.or(self.fast_boot),
+            unthrottled: command_line.unthrottled.or(self.unthrottled),
+            speed: command_line.speed.or(self.speed),
+            socd: command_line.socd.or(self.socd),
+            skip_idle_loops: command_line.skip_idle_loops.or(self.skip_idle_loops),
+            smooth_palettes: command_line.smooth_palettes.or(self.smooth_palettes),
+            sync_to_display: command_line.sync_to_display.or(self.sync_to_display),
+        }
+    }
+
+    /// The options to run with, the defaults fill the ones that are not set
+    pub fn options(self) -> Options {
+        let defaults = Options::default();
+        Options {
+            scale: self.scale.unwrap_or(defaults.scale),
+            pause_on_boot: self.pause_on_boot.unwrap_or(defaults.pause_on_boot),
+            hide_debug: self.hide_debug.unwrap_or(defaults.hide_debug),
+            pause_on_unfocus: self.pause_on_unfocus.unwrap_or(defaults.pause_on_unfocus),
+            fast_boot: self.fast_boot.unwrap_or(defaults.fast_boot),
+            unthrottled: self.unthrottled.unwrap_or(defaults.unthrottled),
+            speed: self.speed.unwrap_or(defaults.speed),
+            socd: self
+                .socd
+                .as_deref()
+                .and_then(socd_mode)
+                .unwrap_or(defaults.socd),
+            skip_idle_loops: self.skip_idle_loops.unwrap_or(defaults.skip_idle_loops),
+            smooth_palettes: self.smooth_palettes.unwrap_or(defaults.smooth_palettes),
+            sync_to_display: self.sync_to_display.or(defaults.sync_to_display),
+        }
+    }
+}
+
+/// The options of a config with every default applied
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub scale: u32,
+    pub pause_on_boot: bool,
+    pub hide_debug: bool,
+    pub pause_on_unfocus: bool,
+    pub fast_boot: bool,
+    pub unthrottled: bool,
+    pub speed: f64,
+    pub socd: SocdMode,
+    pub skip_idle_loops: bool,
+    pub smooth_palettes: bool,
+    // None presents at the Game Boy rate
+    pub sync_to_display: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scale: 2,
+            pause_on_boot: false,
+            hide_debug: false,
+            pause_on_unfocus: false,
+            fast_boot: false,
+            unthrottled: false,
+            speed: 1.0,
+            socd: SocdMode::Allow,
+            skip_idle_loops: false,
+            smooth_palettes: false,
+            sync_to_display: None,
+        }
+    }
+}
+
+fn socd_mode(name: &str) -> Option<SocdMode> {
+    match name {
+        "allow" => Some(SocdMode::Allow),
+        "neutral" => Some(SocdMode::Neutral),
+        "last-wins" => Some(SocdMode::LastWins),
+        _ => None,
+    }
+}
+
+fn socd_name(mode: SocdMode) -> &'static str {
+    match mode {
+        SocdMode::Allow => "allow",
+        SocdMode::Neutral => "neutral",
+        SocdMode::LastWins => "last-wins",
+    }
+}
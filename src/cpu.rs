@@ -1,3 +1,6 @@
+pub mod access_log;
+pub mod alu;
+pub mod decode_cache;
 pub mod instruction;
 pub mod interrupt;
 pub mod registers;
@@ -6,6 +9,8 @@ pub mod sm80;
 use crate::memory::Memory;
 use std::time::{Duration, Instant};
 
+use crate::cpu::access_log::{AccessLog, MemoryAccess};
+use crate::cpu::decode_cache::DecodeCache;
 use crate::cpu::instruction::InstructionSet;
 use crate::cpu::registers::Flag;
 use sm80::Core;
@@ -19,6 +24,14 @@ pub const CLOCK_FREQUENCY: u32 = 4_194_304;
 pub const STEP_TIME: u32 = 16;
 pub const STEP_CYCLES: u32 = (STEP_TIME as f64 / (1000_f64 / CLOCK_FREQUENCY as f64)) as u32;
 
+// Setting this environment variable to 1 runs every cpu unthrottled, for CI and batch runs
+pub const UNTHROTTLED_ENV: &str = "RGB_UNTHROTTLED";
+
+/// Whether the value of RGB_UNTHROTTLED disables throttling
+pub fn unthrottled_env(value: Option<&str>) -> bool {
+    value == Some("1")
+}
+
 const INTERRUPT_ENABLE_REG: u16 = 0xFFFF;
 const INTERRUPT_FLAG_REG: u16 = 0xFF0F;
 
@@ -89,6 +102,24 @@ pub struct ClockedCPU {
     step_zero: Instant,
     // Begin step
     step_flip: bool, // When this is set to true, we want to handle events
+    // When not throttled, the cpu runs as fast as the host allows
+    throttled: bool,
+    // The emulation speed when throttled, 1.0 is the Game Boy speed
+    speed: f64,
+    // Used to wait for the end of the step, can be replaced to mock the clock
+    sleep: fn(Duration),
+    // When set, the memory accesses of each instruction are recorded
+    access_log: Option<Rc<RefCell<AccessLog>>>,
+    // The number of instructions run, a halted step counts as one
+    instructions: u64,
+    // The number of clock cycles run, including the idle cycles
+    cycles: u64,
+    // The opcodes executed so far, unprefixed and 0xcb prefixed
+    coverage: ([bool; 256], [bool; 256]),
+    // When set, instructions in the rom are decoded once
+    decode_cache: Option<DecodeCache>,
+    // The address and opcode of the illegal opcode that locked up the cpu
+    illegal_opcode: Option<(u16, u8)>,
 }
 
 impl ClockedCPU {
@@ -100,15 +131,137 @@ impl ClockedCPU {
             step_cycles: 0,
             step_zero: Instant::now(),
             step_flip: false,
+            throttled: !unthrottled_env(std::env::var(UNTHROTTLED_ENV).ok().as_deref()),
+            speed: 1.0,
+            sleep: thread::sleep,
+            access_log: None,
+            instructions: 0,
+            cycles: 0,
+            coverage: ([false; 256], [false; 256]),
+            decode_cache: None,
+            illegal_opcode: None,
         }
     }
+
+    /// The number of instructions run so far, each step spent halted counts as an instruction
+    pub fn instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The number of clock cycles run so far
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The opcodes executed so far, unprefixed and 0xcb prefixed, indexed by opcode
+    pub fn opcode_coverage(&self) -> ([bool; 256], [bool; 256]) {
+        self.coverage
+    }
+
+    /// Record the memory accesses of each instruction, this slows down every memory access
+    pub fn set_access_logging(&mut self, enabled: bool) {
+        match (enabled, self.access_log.take()) {
+            (true, None) => {
+                let access_log = Rc::new(RefCell::new(AccessLog::new(self.core.memory.clone())));
+                self.core.memory = access_log.clone();
+                self.access_log = Some(access_log);
+            }
+            (false, Some(access_log)) => {
+                self.core.memory = access_log.borrow().memory.clone();
+            }
+            (_, access_log) => self.access_log = access_log,
+        }
+    }
+
+    pub fn access_logging(&self) -> bool {
+        self.access_log.is_some()
+    }
+
+    /// The memory accesses of the last instruction or interrupt dispatch, in order. Empty unless
+    /// access logging is enabled
+    pub fn last_instruction_accesses(&self) -> Vec<MemoryAccess> {
+        match &self.access_log {
+            Some(access_log) => access_log.borrow().accesses(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Decode the instructions in the rom once and reuse them, this skips the memory reads of the
+    /// decode in hot loops
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = if enabled {
+            Some(DecodeCache::new())
+        } else {
+            None
+        };
+    }
+
+    /// The cache of decoded instructions, None unless enabled
+    pub fn decode_cache(&self) -> Option<&DecodeCache> {
+        self.decode_cache.as_ref()
+    }
+
+    /// The address and opcode of the illegal opcode that locked up the cpu
+    pub fn illegal_opcode(&self) -> Option<(u16, u8)> {
+        self.illegal_opcode
+    }
+
+    pub fn set_throttled(&mut self, throttled: bool) {
+        self.throttled = throttled;
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// Scale the speed of the throttled cpu, 0.5 runs at half the Game Boy speed and 2.0 twice
+    /// as fast
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed > 0.0, "Invalid speed: {}", speed);
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Replace the function used to wait for real time, this allows tests to mock the clock
+    pub fn set_sleep(&mut self, sleep: fn(Duration)) {
+        self.sleep = sleep;
+    }
     fn execute_next_instruction(&mut self) -> u32 {
-        let executable_instruction = self
-            .instruction_set
-            .get_next_executable_instruction(&mut self.core)
-            .expect("Error decoding next instruction");
+        if let Some(access_log) = &self.access_log {
+            access_log.borrow().set_fetching(true);
+        }
+        let pc = self.core.registers.pc;
+        let executable_instruction = match self.decode_cache.as_mut() {
+            Some(decode_cache) => decode_cache.decode(&self.instruction_set, &mut self.core),
+            None => self
+                .instruction_set
+                .get_next_executable_instruction(&mut self.core),
+        };
+        if let Some(access_log) = &self.access_log {
+            access_log.borrow().set_fetching(false);
+        }
 
-        let (instruction, operand, prefixed, opcode) = executable_instruction;
+        let (instruction, operand, prefixed, opcode) = match executable_instruction {
+            Some(executable_instruction) => executable_instruction,
+            None => {
+                // Illegal opcodes lock up the cpu until it is powered off
+                let opcode = self.core.memory.borrow().get(pc);
+                warn!(
+                    "Illegal opcode ${:02x} at ${:04x}, the cpu is locked up",
+                    opcode, pc
+                );
+                self.illegal_opcode = Some((pc, opcode));
+                return OP_CYCLES[0];
+            }
+        };
+        if prefixed {
+            self.coverage.1[opcode as usize] = true;
+        } else {
+            self.coverage.0[opcode as usize] = true;
+        }
 
         // Some instructions have operands, for those we need to push the pc register and get the operand from memory
         match instruction.operand_length {
@@ -196,6 +349,24 @@ impl ClockedCPU {
 
     // Function next simulates real hardware execution speed, by limiting the frequency of the function cpu.next().
     pub fn tick(&mut self) -> u32 {
+        self.throttle();
+
+        let cycles = self.step();
+
+        // Increment the step cycles with the cpu tick cycles
+        self.step_cycles += cycles;
+        cycles
+    }
+
+    /// Let the cycles pass without running any instruction, they are throttled like the cycles of
+    /// executed instructions. Used to skip idle loops
+    pub fn idle(&mut self, cycles: u32) {
+        self.throttle();
+        self.step_cycles += cycles;
+        self.cycles += u64::from(cycles);
+    }
+
+    fn throttle(&mut self) {
         // When we ran all the cycles in this step, we enter the count and wait period
         if self.step_cycles > STEP_CYCLES {
             // Set the step flip flag so events will be handled at the end of the step
@@ -206,17 +377,19 @@ impl ClockedCPU {
             // Time passed since last run time
             let time_passed = now.duration_since(self.step_zero);
 
+            // The real time of a step, shorter when running faster than the Game Boy
+            let step_time = Duration::from_millis(u64::from(STEP_TIME)).div_f64(self.speed);
+
             // Subtract the time passed from the expected step time to get the time thread needs to sleep
-            let sleep_time = u64::from(STEP_TIME.saturating_sub(time_passed.as_millis() as u32));
+            let sleep_time = step_time.checked_sub(time_passed).unwrap_or_default();
 
-            trace!("CPU: sleep {} millis", sleep_time);
-            thread::sleep(Duration::from_millis(sleep_time));
+            if self.throttled {
+                trace!("CPU: sleep {} millis", sleep_time.as_millis());
+                (self.sleep)(sleep_time);
+            }
 
             // Update the last run zero time with the last time + step time
-            self.step_zero = self
-                .step_zero
-                .checked_add(Duration::from_millis(u64::from(STEP_TIME)))
-                .unwrap();
+            self.step_zero = self.step_zero.checked_add(step_time).unwrap();
 
             // If now is after the just updated target frame time, reset to
             // avoid drift.
@@ -224,22 +397,36 @@ impl ClockedCPU {
                 self.step_zero = now;
             }
         }
+    }
 
+    /// Run one instruction, or dispatch a pending interrupt, without throttling to real time
+    pub fn step(&mut self) -> u32 {
         // Run the CPU and get the machine cycles, handle interrupts if there is any
-        let cycles = {
+        let cycles = if self.illegal_opcode.is_some() {
+            // A locked up cpu does not run anything, not even interrupts
+            OP_CYCLES[0]
+        } else {
+            if let Some(access_log) = &self.access_log {
+                access_log.borrow().clear();
+            }
             let interrupt_cycles = self.core.handle_interrupt();
+            // Checking for pending interrupts is not a bus access, only the dispatch is recorded
+            if let Some(access_log) = &self.access_log {
+                access_log.borrow().forget_interrupt_reads();
+            }
             if interrupt_cycles != 0 {
                 interrupt_cycles
             } else if self.core.halted {
+                self.instructions += 1;
                 OP_CYCLES[0]
             } else {
+                self.instructions += 1;
                 self.execute_next_instruction()
             }
-        } * 4; // We time this by 4 since up till now, the cycles we are referring to is machine cycles. 1 machine cycle = 4 t-cycle
-
-        // Increment the step cycles with the cpu tick cycles
-        self.step_cycles += cycles;
-        cycles
+        };
+        // We time this by 4 since up till now, the cycles we are referring to is machine cycles. 1 machine cycle = 4 t-cycle
+        self.cycles += u64::from(cycles * 4);
+        cycles * 4
     }
 
     pub fn simulate_boot_rom(&mut self) {
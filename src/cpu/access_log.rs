@@ -0,0 +1,74 @@
+use crate::memory::Memory;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccessKind {
+    // Reading the opcode and operands of the instruction
+    Fetch,
+    Read,
+    Write,
+}
+
+pub type MemoryAccess = (AccessKind, u16);
+
+/// Sits between the cpu and the memory and records every access, in order
+pub struct AccessLog {
+    pub memory: Rc<RefCell<dyn Memory>>,
+    accesses: RefCell<Vec<MemoryAccess>>,
+    fetching: Cell<bool>,
+}
+
+impl AccessLog {
+    pub fn new(memory: Rc<RefCell<dyn Memory>>) -> AccessLog {
+        Self {
+            memory,
+            accesses: RefCell::new(Vec::new()),
+            fetching: Cell::new(false),
+        }
+    }
+
+    /// Reads are recorded as fetches while set
+    pub fn set_fetching(&self, fetching: bool) {
+        self.fetching.set(fetching);
+    }
+
+    pub fn clear(&self) {
+        self.accesses.borrow_mut().clear();
+    }
+
+    /// Drop the reads of IF and IE, the cpu checks the pending interrupts without a bus access
+    pub fn forget_interrupt_reads(&self) {
+        self.accesses.borrow_mut().retain(|(kind, address)| {
+            *kind != AccessKind::Read || (*address != 0xff0f && *address != 0xffff)
+        });
+    }
+
+    pub fn accesses(&self) -> Vec<MemoryAccess> {
+        self.accesses.borrow().clone()
+    }
+}
+
+impl Memory for AccessLog {
+    fn get(&self, address: u16) -> u8 {
+        let kind = if self.fetching.get() {
+            AccessKind::Fetch
+        } else {
+            AccessKind::Read
+        };
+        self.accesses.borrow_mut().push((kind, address));
+        self.memory.borrow().get(address)
+    }
+
+    fn set(&mut self, address: u16, value: u8) {
+        self.accesses
+            .borrow_mut()
+            .push((AccessKind::Write, address));
+        self.memory.borrow_mut().set(address, value);
+    }
+
+    // Keeps the decode cache working with logging on, the fetches it serves are not recorded
+    fn rom_mapping(&self) -> Option<usize> {
+        self.memory.borrow().rom_mapping()
+    }
+}
@@ -0,0 +1,199 @@
+// Pure alu operations, each returns the result and the flags it changes without touching the
+// cpu state. The Core alu_* methods apply them to the registers
+
+/// The flags changed by an operation, None leaves the flag unchanged
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Flags {
+    pub z: Option<bool>,
+    pub n: Option<bool>,
+    pub h: Option<bool>,
+    pub c: Option<bool>,
+}
+
+impl Flags {
+    fn all(z: bool, n: bool, h: bool, c: bool) -> Flags {
+        Flags {
+            z: Some(z),
+            n: Some(n),
+            h: Some(h),
+            c: Some(c),
+        }
+    }
+}
+
+// Add b and the carry to a.
+pub fn add(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let carry = u8::from(carry);
+    let result = a.wrapping_add(b).wrapping_add(carry);
+    let flags = Flags::all(
+        result == 0x00,
+        false,
+        (a & 0x0f) + (b & 0x0f) + carry > 0x0f,
+        u16::from(a) + u16::from(b) + u16::from(carry) > 0xff,
+    );
+    (result, flags)
+}
+
+// Subtract b and the carry from a.
+pub fn sub(a: u8, b: u8, carry: bool) -> (u8, Flags) {
+    let carry = u8::from(carry);
+    let result = a.wrapping_sub(b).wrapping_sub(carry);
+    let flags = Flags::all(
+        result == 0x00,
+        true,
+        (a & 0x0f) < (b & 0x0f) + carry,
+        u16::from(a) < u16::from(b) + u16::from(carry),
+    );
+    (result, flags)
+}
+
+// Add n to the 16 bit value hl, the zero flag is unchanged.
+pub fn add_hl(hl: u16, n: u16) -> (u16, Flags) {
+    let flags = Flags {
+        z: None,
+        n: Some(false),
+        h: Some((hl & 0x0fff) + (n & 0x0fff) > 0x0fff),
+        c: Some(hl > 0xffff - n),
+    };
+    (hl.wrapping_add(n), flags)
+}
+
+// Add the signed n to sp, the carries come from the low byte.
+pub fn add_sp(sp: u16, n: u8) -> (u16, Flags) {
+    let b = i16::from(n as i8) as u16;
+    let flags = Flags::all(
+        false,
+        false,
+        (sp & 0x000f) + (b & 0x000f) > 0x000f,
+        (sp & 0x00ff) + (b & 0x00ff) > 0x00ff,
+    );
+    (sp.wrapping_add(b), flags)
+}
+
+pub fn and(a: u8, b: u8) -> (u8, Flags) {
+    let result = a & b;
+    (result, Flags::all(result == 0x00, false, true, false))
+}
+
+pub fn or(a: u8, b: u8) -> (u8, Flags) {
+    let result = a | b;
+    (result, Flags::all(result == 0x00, false, false, false))
+}
+
+pub fn xor(a: u8, b: u8) -> (u8, Flags) {
+    let result = a ^ b;
+    (result, Flags::all(result == 0x00, false, false, false))
+}
+
+// Increment n, the carry flag is unchanged.
+pub fn inc(n: u8) -> (u8, Flags) {
+    let result = n.wrapping_add(1);
+    let flags = Flags {
+        z: Some(result == 0x00),
+        n: Some(false),
+        h: Some((n & 0x0f) + 0x01 > 0x0f),
+        c: None,
+    };
+    (result, flags)
+}
+
+// Decrement n, the carry flag is unchanged.
+pub fn dec(n: u8) -> (u8, Flags) {
+    let result = n.wrapping_sub(1);
+    let flags = Flags {
+        z: Some(result == 0x00),
+        n: Some(true),
+        h: Some(n.trailing_zeros() >= 4),
+        c: None,
+    };
+    (result, flags)
+}
+
+// Decimal adjust a after an addition or subtraction, using the n, h and c flags it left.
+pub fn daa(a: u8, n: bool, h: bool, c: bool) -> (u8, Flags) {
+    let mut adjust = if c { 0x60 } else { 0x00 };
+    if h {
+        adjust |= 0x06;
+    }
+    let result = if !n {
+        if a & 0x0f > 0x09 {
+            adjust |= 0x06;
+        }
+        if a > 0x99 {
+            adjust |= 0x60;
+        }
+        a.wrapping_add(adjust)
+    } else {
+        a.wrapping_sub(adjust)
+    };
+    let flags = Flags {
+        z: Some(result == 0x00),
+        n: None,
+        h: Some(false),
+        c: Some(adjust >= 0x60),
+    };
+    (result, flags)
+}
+
+// The flags of the rotate and shift operations, the carry is the bit shifted out
+fn shift_flags(result: u8, carry: bool) -> Flags {
+    Flags::all(result == 0x00, false, false, carry)
+}
+
+// Rotate n left. Old bit 7 to Carry flag.
+pub fn rlc(n: u8) -> (u8, Flags) {
+    let result = n.rotate_left(1);
+    (result, shift_flags(result, n & 0x80 != 0x00))
+}
+
+// Rotate n left through Carry flag.
+pub fn rl(n: u8, carry: bool) -> (u8, Flags) {
+    let result = (n << 1) | u8::from(carry);
+    (result, shift_flags(result, n & 0x80 != 0x00))
+}
+
+// Rotate n right. Old bit 0 to Carry flag.
+pub fn rrc(n: u8) -> (u8, Flags) {
+    let result = n.rotate_right(1);
+    (result, shift_flags(result, n & 0x01 != 0x00))
+}
+
+// Rotate n right through Carry flag.
+pub fn rr(n: u8, carry: bool) -> (u8, Flags) {
+    let result = (n >> 1) | (u8::from(carry) << 7);
+    (result, shift_flags(result, n & 0x01 != 0x00))
+}
+
+// Shift n left into Carry. LSB of n set to 0.
+pub fn sla(n: u8) -> (u8, Flags) {
+    let result = n << 1;
+    (result, shift_flags(result, n & 0x80 != 0x00))
+}
+
+// Shift n right into Carry. MSB doesn't change.
+pub fn sra(n: u8) -> (u8, Flags) {
+    let result = (n >> 1) | (n & 0x80);
+    (result, shift_flags(result, n & 0x01 != 0x00))
+}
+
+// Shift n right into Carry. MSB set to 0.
+pub fn srl(n: u8) -> (u8, Flags) {
+    let result = n >> 1;
+    (result, shift_flags(result, n & 0x01 != 0x00))
+}
+
+// Swap upper & lower nibles of n.
+pub fn swap(n: u8) -> (u8, Flags) {
+    let result = n.rotate_left(4);
+    (result, shift_flags(result, false))
+}
+
+// Test bit b of n, the carry flag is unchanged.
+pub fn bit(n: u8, b: u8) -> Flags {
+    Flags {
+        z: Some(n & (1 << b) == 0x00),
+        n: Some(false),
+        h: Some(true),
+        c: None,
+    }
+}
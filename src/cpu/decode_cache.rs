@@ -0,0 +1,109 @@
+use super::instruction::{Instruction, InstructionSet, Operand};
+use super::sm80::Core;
+use std::collections::HashMap;
+
+// An instruction decoded from the rom, everything needed to run it without reading memory
+#[derive(Copy, Clone)]
+struct Decoded {
+    opcode: u8,
+    prefixed: bool,
+    operand: Option<Operand>,
+    // The opcode, the prefix and the operand bytes
+    length: u16,
+}
+
+/// Caches the instructions decoded from the rom by the rom mapping and the pc. A bank switch
+/// changes the mapping, so instructions decoded from the previous bank are no longer used
+pub struct DecodeCache {
+    // The mapping the entries were decoded from and the instructions indexed by the pc
+    mapping: usize,
+    entries: Vec<Option<Decoded>>,
+    // The entries of the other mappings, kept for when the bank is switched back
+    banked_entries: HashMap<usize, Vec<Option<Decoded>>>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        Self {
+            mapping: 0,
+            entries: vec![None; 0x8000],
+            banked_entries: HashMap::new(),
+        }
+    }
+
+    // Make the entries of the mapping current
+    fn switch_mapping(&mut self, mapping: usize) {
+        let entries = self
+            .banked_entries
+            .remove(&mapping)
+            .unwrap_or_else(|| vec![None; 0x8000]);
+        let previous = std::mem::replace(&mut self.entries, entries);
+        self.banked_entries.insert(self.mapping, previous);
+        self.mapping = mapping;
+    }
+
+    /// Decode the instruction at the pc and advance the pc past it like
+    /// get_next_executable_instruction, instructions in the rom are only decoded once
+    pub fn decode<'a>(
+        &mut self,
+        instruction_set: &'a InstructionSet,
+        core: &mut Core,
+    ) -> Option<(&'a Instruction, Option<Operand>, bool, u8)> {
+        let pc = core.registers.pc;
+        let mapping = core.memory.borrow().rom_mapping();
+        // The halt bug reads the opcode twice, the pc is not advanced normally
+        let mapping = match mapping {
+            Some(mapping) if pc < 0x8000 && !core.halt_bug => mapping,
+            _ => return instruction_set.get_next_executable_instruction(core),
+        };
+        if mapping != self.mapping {
+            self.switch_mapping(mapping);
+        }
+        if let Some(decoded) = self.entries[pc as usize] {
+            core.registers.pc = pc.wrapping_add(decoded.length);
+            return instruction_set
+                .get(decoded.opcode, decoded.prefixed)
+                .map(|instruction| {
+                    (
+                        instruction,
+                        decoded.operand,
+                        decoded.prefixed,
+                        decoded.opcode,
+                    )
+                });
+        }
+        let executable_instruction = instruction_set.get_next_executable_instruction(core);
+        if let Some((_, operand, prefixed, opcode)) = executable_instruction {
+            let length = core.registers.pc.wrapping_sub(pc);
+            // The operand could be past the end of the rom
+            if u32::from(pc) + u32::from(length) <= 0x8000 {
+                self.entries[pc as usize] = Some(Decoded {
+                    opcode,
+                    prefixed,
+                    operand,
+                    length,
+                });
+            }
+        }
+        executable_instruction
+    }
+
+    /// The number of cached instructions across every mapping
+    pub fn len(&self) -> usize {
+        self.banked_entries
+            .values()
+            .chain(std::iter::once(&self.entries))
+            .map(|entries| entries.iter().filter(|entry| entry.is_some()).count())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -44,6 +44,15 @@ impl InstructionSet {
             cb_instructions,
         }
     }
+    /// The instruction for the opcode, prefixed selects the 0xcb prefixed instructions
+    pub fn get(&self, opcode: u8, prefixed: bool) -> Option<&Instruction> {
+        if prefixed {
+            self.cb_instructions.get(&opcode)
+        } else {
+            self.instructions.get(&opcode)
+        }
+    }
+
     pub fn get_next_executable_instruction(
         &self,
         core: &mut Core,
@@ -56,11 +65,7 @@ impl InstructionSet {
             opcode = core.get_next();
         }
 
-        let instruction = if prefixed {
-            self.cb_instructions.get(&opcode)
-        } else {
-            self.instructions.get(&opcode)
-        };
+        let instruction = self.get(opcode, prefixed);
 
         instruction.map(|instruction| match instruction.operand_length {
             0 => (instruction, None, prefixed, opcode),
@@ -3610,10 +3615,12 @@ fn load_b_l(core: &mut Core, _: Option<Operand>) {
 
 fn nop(_: &mut Core, _: Option<Operand>) {}
 
-fn stop(_: &mut Core, _: Option<Operand>) {}
+fn stop(core: &mut Core, operand: Option<Operand>) {
+    core.stop(operand.unwrap().byte);
+}
 
 fn halt(core: &mut Core, _: Option<Operand>) {
-    core.halted = true;
+    core.halt();
 }
 
 fn load_bc_d16(core: &mut Core, operand: Option<Operand>) {
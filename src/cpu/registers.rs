@@ -86,6 +86,23 @@ impl Registers {
         )
     }
 
+    /// The registers in the gameboy doctor trace format, used to compare against reference traces
+    pub fn trace_line(&self) -> String {
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X}",
+            self.a,
+            self.f,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.sp,
+            self.pc
+        )
+    }
+
     pub fn get_af(&self) -> u16 {
         (u16::from(self.a) << 8) | u16::from(u8::from(self.f))
     }
@@ -2,13 +2,48 @@ use crate::memory::Memory;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use super::alu::{self, Flags};
 use super::registers::{Flag, Registers};
 
+/// What to do when the stack pointer drops below the stack guard floor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StackGuardAction {
+    // Log a warning and keep running
+    Warn,
+    // Log a warning and pause the emulator
+    Break,
+}
+
+/// Guards against runaway recursion pushing the stack into the rom or io space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StackGuard {
+    pub floor: u16,
+    pub action: StackGuardAction,
+}
+
+/// How the last STOP was decoded, the byte after the opcode is only skipped by a proper STOP
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopPath {
+    // No interrupt pending and the second byte is 0x00, the byte is skipped
+    Stopped,
+    // No interrupt pending but the second byte is not 0x00, it runs as the next instruction
+    Corrupted,
+    // An interrupt is pending, STOP is a 1 byte instruction
+    OneByte,
+}
+
 pub struct Core {
     pub memory: Rc<RefCell<dyn Memory>>,
     pub registers: Registers,
     pub halted: bool,
+    // Set by a halt that did not halt, the next opcode fetch does not advance the pc
+    pub halt_bug: bool,
     pub ei: bool,
+    pub stack_guard: Option<StackGuard>,
+    // Set when a push dropped the stack pointer below the guard floor
+    pub stack_overflow: bool,
+    // Exposed for debugging the STOP encoding edge cases
+    pub last_stop: Option<StopPath>,
 }
 
 impl Core {
@@ -18,6 +53,10 @@ impl Core {
             registers: Registers::new(),
             ei: true,
             halted: false,
+            halt_bug: false,
+            stack_guard: None,
+            stack_overflow: false,
+            last_stop: None,
         }
     }
 
@@ -37,37 +76,77 @@ impl Core {
     /// Get the next byte in the memory location
     pub fn get_next(&mut self) -> u8 {
         let value = self.memory.borrow().get(self.registers.pc);
-        self.registers.pc += 1;
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
         value
     }
     /// Get the next work in the next memory location
     pub fn get_next_word(&mut self) -> u16 {
         let value = self.memory.borrow().get_word(self.registers.pc);
-        self.registers.pc += 2;
+        self.registers.pc = self.registers.pc.wrapping_add(2);
         value
     }
     /// Push value to the stack and update the stack pointer
     pub fn stack_push(&mut self, value: u16) {
-        self.registers.sp -= 2;
-        self.memory.borrow_mut().set_word(self.registers.sp, value);
+        // The high byte is pushed first
+        self.push_high_byte(value);
+        self.push_low_byte(value);
+    }
+    // The first half of a push
+    fn push_high_byte(&mut self, value: u16) {
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.memory
+            .borrow_mut()
+            .set(self.registers.sp, (value >> 8) as u8);
+    }
+    // The second half of a push, the stack pointer is checked against the guard
+    fn push_low_byte(&mut self, value: u16) {
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        if let Some(stack_guard) = self.stack_guard {
+            if self.registers.sp < stack_guard.floor {
+                warn!(
+                    "Stack pointer ${:04x} dropped below the floor ${:04x} at pc ${:04x}",
+                    self.registers.sp, stack_guard.floor, self.registers.pc
+                );
+                self.stack_overflow = true;
+            }
+        }
+        self.memory
+            .borrow_mut()
+            .set(self.registers.sp, (value & 0xff) as u8);
     }
     /// Pop the current value on the stack
     pub fn stack_pop(&mut self) -> u16 {
         let value = self.memory.borrow_mut().get_word(self.registers.sp);
-        self.registers.sp += 2;
+        self.registers.sp = self.registers.sp.wrapping_add(2);
         value
     }
+    // Update the registers with the flags changed by an alu operation
+    fn apply_flags(&mut self, flags: Flags) {
+        if let Some(z) = flags.z {
+            self.registers.set_flag(Flag::Z, z);
+        }
+        if let Some(n) = flags.n {
+            self.registers.set_flag(Flag::N, n);
+        }
+        if let Some(h) = flags.h {
+            self.registers.set_flag(Flag::H, h);
+        }
+        if let Some(c) = flags.c {
+            self.registers.set_flag(Flag::C, c);
+        }
+    }
+    // Apply the flags of an alu operation and return its result
+    fn alu<T>(&mut self, (result, flags): (T, Flags)) -> T {
+        self.apply_flags(flags);
+        result
+    }
     // Add n to Stack Pointer (SP).
     pub fn alu_add_sp(&mut self, n: u8) {
-        let a = self.registers.sp;
-        let b = i16::from(n as i8) as u16;
-        self.registers
-            .set_flag(Flag::C, (a & 0x00ff) + (b & 0x00ff) > 0x00ff);
-        self.registers
-            .set_flag(Flag::H, (a & 0x000f) + (b & 0x000f) > 0x000f);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, false);
-        self.registers.sp = a.wrapping_add(b);
+        self.registers.sp = self.alu(alu::add_sp(self.registers.sp, n));
     }
     // Complement carry flag. If C flag is set, then reset it. If C flag is reset, then set it.
     pub fn alu_ccf(&mut self) {
@@ -96,230 +175,96 @@ impl Core {
     }
     // Rotate n right through Carry flag.
     pub fn alu_rr(&mut self, n: u8) -> u8 {
-        let carry = n & 0x01 == 0x01;
-        let result = if self.registers.get_flag(Flag::C) {
-            0x80 | (n >> 1)
-        } else {
-            n >> 1
-        };
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::rr(n, self.registers.get_flag(Flag::C)))
     }
     // Decimal adjust register A. This instruction adjusts register A so that the correct representation of Binary
     // Coded Decimal (BCD) is obtained.
     pub fn alu_daa(&mut self) {
-        let mut a = self.registers.a;
-        let mut adjust = if self.registers.get_flag(Flag::C) {
-            0x60
-        } else {
-            0x00
-        };
-        if self.registers.get_flag(Flag::H) {
-            adjust |= 0x06;
-        };
-        if !self.registers.get_flag(Flag::N) {
-            if a & 0x0f > 0x09 {
-                adjust |= 0x06;
-            };
-            if a > 0x99 {
-                adjust |= 0x60;
-            };
-            a = a.wrapping_add(adjust);
-        } else {
-            a = a.wrapping_sub(adjust);
-        }
-        self.registers.set_flag(Flag::C, adjust >= 0x60);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::Z, a == 0x00);
-        self.registers.a = a;
+        self.registers.a = self.alu(alu::daa(
+            self.registers.a,
+            self.registers.get_flag(Flag::N),
+            self.registers.get_flag(Flag::H),
+            self.registers.get_flag(Flag::C),
+        ));
     }
     // Shift n right into Carry. MSB doesn't change.
     pub fn alu_sra(&mut self, n: u8) -> u8 {
-        let carry = n & 0x01 == 0x01;
-        let result = (n >> 1) | (n & 0x80);
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::sra(n))
     }
     // Shift n left into Carry. LSB of n set to 0.
     pub fn alu_sla(&mut self, n: u8) -> u8 {
-        let carry = (n & 0x80) >> 7 == 0x01;
-        let result = n << 1;
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::sla(n))
     }
     // Rotate n right. Old bit 0 to Carry flag.
     pub fn alu_rrc(&mut self, n: u8) -> u8 {
-        let carry = n & 0x01 == 0x01;
-        let result = if carry { 0x80 | (n >> 1) } else { n >> 1 };
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::rrc(n))
     }
     // Rotate n left through Carry flag.
     pub fn alu_rl(&mut self, n: u8) -> u8 {
-        let carry = (n & 0x80) >> 7 == 0x01;
-        let result = (n << 1) + u8::from(self.registers.get_flag(Flag::C));
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::rl(n, self.registers.get_flag(Flag::C)))
     }
     // Rotate n left. Old bit 7 to Carry flag.
     pub fn alu_rlc(&mut self, n: u8) -> u8 {
-        let carry = (n & 0x80) >> 7 == 0x01;
-        let result = (n << 1) | u8::from(carry);
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::rlc(n))
     }
     // Decrement number
     pub fn alu_dec(&mut self, n: u8) -> u8 {
-        let result = n.wrapping_sub(1);
-        self.registers.set_flag(Flag::H, n.trailing_zeros() >= 4);
-        self.registers.set_flag(Flag::N, true);
-        self.registers.set_flag(Flag::Z, result == 0);
-        result
+        self.alu(alu::dec(n))
     }
     // Increment number
     pub fn alu_inc(&mut self, n: u8) -> u8 {
-        let result = n.wrapping_add(1);
-        self.registers.set_flag(Flag::H, (n & 0x0f) + 0x01 > 0x0f);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::inc(n))
     }
     // Subtract n from A.
     pub fn alu_sub(&mut self, n: u8) {
-        let a = self.registers.a;
-        let result = a.wrapping_sub(n);
-        self.registers
-            .set_flag(Flag::C, u16::from(a) < u16::from(n));
-        self.registers.set_flag(Flag::H, (a & 0x0f) < (n & 0x0f));
-        self.registers.set_flag(Flag::N, true);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        self.registers.a = self.alu(alu::sub(self.registers.a, n, false));
     }
     // Subtract n + Carry flag from A.
     pub fn alu_sbc(&mut self, n: u8) {
-        let a = self.registers.a;
-        let carry = u8::from(self.registers.get_flag(Flag::C));
-        let result = a.wrapping_sub(n).wrapping_sub(carry);
-        self.registers
-            .set_flag(Flag::C, u16::from(a) < u16::from(n) + u16::from(carry));
-        self.registers
-            .set_flag(Flag::H, (a & 0x0f) < (n & 0x0f) + carry);
-        self.registers.set_flag(Flag::N, true);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        let carry = self.registers.get_flag(Flag::C);
+        self.registers.a = self.alu(alu::sub(self.registers.a, n, carry));
     }
     // Add n + Carry flag to A.
     pub fn alu_adc(&mut self, n: u8) {
-        let a = self.registers.a;
-        let carry = u8::from(self.registers.get_flag(Flag::C));
-        let result = a.wrapping_add(n).wrapping_add(carry);
-        self.registers.set_flag(
-            Flag::C,
-            u16::from(a) + u16::from(n) + u16::from(carry) > 0xff,
-        );
-        self.registers
-            .set_flag(Flag::H, (a & 0x0f) + (n & 0x0f) + (carry & 0x0f) > 0x0f);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        let carry = self.registers.get_flag(Flag::C);
+        self.registers.a = self.alu(alu::add(self.registers.a, n, carry));
     }
     // Add n to A.
     pub fn alu_add(&mut self, n: u8) {
-        let a = self.registers.a;
-        let result = a.wrapping_add(n);
-        self.registers
-            .set_flag(Flag::C, u16::from(a) + u16::from(n) > 0xff);
-        self.registers
-            .set_flag(Flag::H, (a & 0x0f) + (n & 0x0f) > 0x0f);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        self.registers.a = self.alu(alu::add(self.registers.a, n, false));
     }
     // Add n to HL
     pub fn alu_add_hl(&mut self, n: u16) {
-        let value = self.registers.get_hl();
-        let result = value.wrapping_add(n);
-        self.registers.set_flag(Flag::C, value > 0xffff - n);
-        self.registers
-            .set_flag(Flag::H, (value & 0x0fff) + (n & 0x0fff) > 0x0fff);
-        self.registers.set_flag(Flag::N, false);
+        let result = self.alu(alu::add_hl(self.registers.get_hl(), n));
         self.registers.set_hl(result);
     }
     // Logically AND n with A, result in A.
     pub fn alu_and(&mut self, n: u8) {
-        let result = self.registers.a & n;
-        self.registers.set_flag(Flag::C, false);
-        self.registers.set_flag(Flag::H, true);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        self.registers.a = self.alu(alu::and(self.registers.a, n));
     }
     // Logical OR n with register A, result in A.
     pub fn alu_or(&mut self, n: u8) {
-        let result = self.registers.a | n;
-        self.registers.set_flag(Flag::C, false);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        self.registers.a = self.alu(alu::or(self.registers.a, n));
     }
     // Logical exclusive OR n with register A, result in A.
     pub fn alu_xor(&mut self, n: u8) {
-        let result = self.registers.a ^ n;
-        self.registers.set_flag(Flag::C, false);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        self.registers.a = result;
+        self.registers.a = self.alu(alu::xor(self.registers.a, n));
     }
     // Compare A with n. This is basically an A - n subtraction instruction but the results are thrown away.
     pub fn alu_cp(&mut self, n: u8) {
-        let r = self.registers.a;
-        self.alu_sub(n);
-        self.registers.a = r;
+        self.alu(alu::sub(self.registers.a, n, false));
     }
     // Swap upper & lower nibles of n.
     pub fn alu_swap(&mut self, n: u8) -> u8 {
-        self.registers.set_flag(Flag::C, false);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, n == 0x00);
-        (n >> 4) | (n << 4)
+        self.alu(alu::swap(n))
     }
     // Shift n right into Carry. MSB set to 0.
     pub fn alu_srl(&mut self, n: u8) -> u8 {
-        let carry = n & 0x01 == 0x01;
-        let result = n >> 1;
-        self.registers.set_flag(Flag::C, carry);
-        self.registers.set_flag(Flag::H, false);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result == 0x00);
-        result
+        self.alu(alu::srl(n))
     }
     // Test bit b in register r.
     pub fn alu_bit(&mut self, a: u8, b: u8) {
-        let result = a & (1 << b) == 0x00;
-        self.registers.set_flag(Flag::H, true);
-        self.registers.set_flag(Flag::N, false);
-        self.registers.set_flag(Flag::Z, result);
+        self.apply_flags(alu::bit(a, b));
     }
     // Reset bit b in register r.
     pub fn alu_res(&mut self, a: u8, b: u8) -> u8 {
@@ -330,6 +275,38 @@ impl Core {
         a | (1 << b)
     }
 
+    /// Whether an enabled interrupt is requested, regardless of IME
+    pub fn interrupt_pending(&self) -> bool {
+        let memory = self.memory.borrow();
+        memory.get(0xff0f) & memory.get(0xffff) & 0x1f != 0x00
+    }
+
+    /// Halt until an interrupt is requested. With IME off the cpu wakes without servicing it,
+    /// and when one is already pending it does not halt at all and the next byte is read twice
+    pub fn halt(&mut self) {
+        if !self.ei && self.interrupt_pending() {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+
+    /// Decode a STOP whose second byte was already fetched, the low power mode is not emulated.
+    /// Only a STOP followed by 0x00 with no interrupt pending is 2 bytes long
+    pub fn stop(&mut self, second_byte: u8) {
+        let path = if self.interrupt_pending() {
+            StopPath::OneByte
+        } else if second_byte != 0x00 {
+            StopPath::Corrupted
+        } else {
+            StopPath::Stopped
+        };
+        if path != StopPath::Stopped {
+            self.registers.pc = self.registers.pc.wrapping_sub(1);
+        }
+        self.last_stop = Some(path);
+    }
+
     pub fn handle_interrupt(&mut self) -> u32 {
         if !self.halted && !self.ei {
             return 0;
@@ -337,23 +314,37 @@ impl Core {
 
         let intf = self.memory.borrow().get(0xff0f);
         let inte = self.memory.borrow().get(0xffff);
-        let ii = intf & inte;
+        let ii = intf & inte & 0x1f;
         if ii == 0x00 {
             return 0;
         }
         self.halted = false;
 
+        // With IME off a halted cpu wakes up and continues after the halt
         if !self.ei {
             return 0;
         }
         self.ei = false;
 
+        // The interrupt is picked after the high byte of the pc is pushed, the push writes to IE
+        // when sp is 0x0000 or to IF when sp is 0xff10, and can enable a different interrupt or
+        // cancel the dispatch
+        let pc = self.registers.pc;
+        self.push_high_byte(pc);
+        let intf = self.memory.borrow().get(0xff0f);
+        let ii = intf & self.memory.borrow().get(0xffff) & 0x1f;
+        self.push_low_byte(pc);
+        if ii == 0x00 {
+            // Nothing is serviced and the cpu jumps to 0x0000
+            self.registers.pc = 0x0000;
+            return 4;
+        }
+
         // Consumer an interrupter, the rest is written back to the register
         let n = ii.trailing_zeros();
         let intf = intf & !(1 << n);
         self.memory.borrow_mut().set(0xff0f, intf);
 
-        self.stack_push(self.registers.pc);
         // Set the PC to correspond interrupt process program:
         // V-Blank: 0x40
         // LCD: 0x48
@@ -2,7 +2,10 @@ pub mod command;
 pub mod debug_logger;
 pub mod debug_state;
 pub mod debuggable;
+pub mod expression;
+pub mod history;
 pub mod message;
+pub mod trace;
 
 use crate::debug::message::DebugMessage;
 use crate::emulator::Emulator;
@@ -1,4 +1,15 @@
 #[derive(Clone, Debug)]
 pub enum DebugCommand {
     PeekValue(u16),
+    // Pause when the pc reaches the address and the condition expression is true
+    ConditionalBreakpoint { pc: u16, condition: String },
+    // Restore the state from before the last instruction stepped while paused
+    StepBack,
+    // Evaluate the expression every frame and report it with DebugMessage::WatchUpdate
+    AddWatch(String),
+    ClearWatches,
+    // Pause and run the number of instructions, stopping early on a breakpoint
+    StepN(u32),
+    // Pause when the total number of clock cycles run reaches the count
+    BreakAtCycle(u64),
 }
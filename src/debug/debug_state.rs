@@ -7,6 +7,9 @@ pub struct DebugState {
     pub registers: Registers,
     pub tiles: Vec<Tile>,
     pub log_messages: Vec<String>,
+    pub watches: Vec<(String, u16)>,
+    // Whether the debug windows are drawn next to the game
+    pub debug_visible: bool,
 }
 
 /// Stores the debugger state, including register state and log messages
@@ -16,8 +19,15 @@ impl DebugState {
             tiles: vec![[[0x00; 8]; 8]; TILE_MAP_SIZE],
             registers: Registers::new(),
             log_messages: Vec::new(),
+            watches: Vec::new(),
+            debug_visible: true,
         }
     }
+    // Show or hide the debug windows, returns the new visibility
+    pub fn toggle_debug_visible(&mut self) -> bool {
+        self.debug_visible = !self.debug_visible;
+        self.debug_visible
+    }
     // Append log to the debug state, if it exceed the max log, the oldest log will be purged
     pub fn append_log(&mut self, log: String) {
         if self.log_messages.len() >= MAX_LOG {
@@ -0,0 +1,274 @@
+use crate::cpu::registers::{Flag, Registers};
+use crate::memory::Memory;
+
+/// A small expression over the registers, flags and memory, used by conditional breakpoints and
+/// watches. Examples: "A == 0x05 && Z", "HL >= $c000", "mem16(0xc100) != 0"
+///
+/// Registers are A, B, C, D, E, F, H, L, AF, BC, DE, HL, SP and PC. The flags are Z, N, HC (half
+/// carry) and CY (carry), they evaluate to 1 when set. mem(address) and mem16(address) read memory.
+/// Any non zero value is true.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Number(u16),
+    Register(String),
+    Flag(String),
+    Memory(Box<Expression>),
+    Memory16(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(u16),
+    Identifier(String),
+    Operator(Operator),
+    Not,
+    Open,
+    Close,
+}
+
+const REGISTERS: [&str; 14] = [
+    "A", "B", "C", "D", "E", "F", "H", "L", "AF", "BC", "DE", "HL", "SP", "PC",
+];
+const FLAGS: [&str; 4] = ["Z", "N", "HC", "CY"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let (token, length) = match (c, next) {
+            ('=', Some('=')) => (Token::Operator(Operator::Equal), 2),
+            ('!', Some('=')) => (Token::Operator(Operator::NotEqual), 2),
+            ('<', Some('=')) => (Token::Operator(Operator::LessEqual), 2),
+            ('>', Some('=')) => (Token::Operator(Operator::GreaterEqual), 2),
+            ('&', Some('&')) => (Token::Operator(Operator::And), 2),
+            ('|', Some('|')) => (Token::Operator(Operator::Or), 2),
+            ('<', _) => (Token::Operator(Operator::Less), 1),
+            ('>', _) => (Token::Operator(Operator::Greater), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            _ if c.is_ascii_alphanumeric() || c == '$' => {
+                let start = i;
+                let mut end = i + 1;
+                while end < chars.len() && chars[end].is_ascii_alphanumeric() {
+                    end += 1;
+                }
+                let word: String = chars[start..end].iter().collect();
+                let token = if c == '$' {
+                    Token::Number(parse_number(&word[1..], 16, &word)?)
+                } else if word.starts_with("0x") || word.starts_with("0X") {
+                    Token::Number(parse_number(&word[2..], 16, &word)?)
+                } else if c.is_ascii_digit() {
+                    Token::Number(parse_number(&word, 10, &word)?)
+                } else {
+                    Token::Identifier(word.to_ascii_uppercase())
+                };
+                (token, end - start)
+            }
+            _ => return Err(format!("Unexpected character '{}'", c)),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+fn parse_number(digits: &str, radix: u32, word: &str) -> Result<u16, String> {
+    u16::from_str_radix(digits, radix).map_err(|_| format!("Invalid number '{}'", word))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect_close(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => Err(String::from("Expected ')'")),
+        }
+    }
+
+    // or := and ("||" and)*
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Operator(Operator::Or)) {
+            self.next();
+            let right = self.and()?;
+            left = Expression::Binary(Operator::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    // and := comparison ("&&" comparison)*
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut left = self.comparison()?;
+        while self.peek() == Some(&Token::Operator(Operator::And)) {
+            self.next();
+            let right = self.comparison()?;
+            left = Expression::Binary(Operator::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    // comparison := unary (compare unary)?
+    fn comparison(&mut self) -> Result<Expression, String> {
+        let left = self.unary()?;
+        match self.peek() {
+            Some(Token::Operator(operator))
+                if *operator != Operator::And && *operator != Operator::Or =>
+            {
+                let operator = *operator;
+                self.next();
+                let right = self.unary()?;
+                Ok(Expression::Binary(
+                    operator,
+                    Box::new(left),
+                    Box::new(right),
+                ))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    // unary := "!" unary | number | register | flag | function "(" or ")" | "(" or ")"
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expression::Not(Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::Open) => {
+                let expression = self.or()?;
+                self.expect_close()?;
+                Ok(expression)
+            }
+            Some(Token::Identifier(name)) if name == "MEM" || name == "MEM16" => {
+                if self.next() != Some(Token::Open) {
+                    return Err(format!("Expected '(' after {}", name));
+                }
+                let address = Box::new(self.or()?);
+                self.expect_close()?;
+                if name == "MEM" {
+                    Ok(Expression::Memory(address))
+                } else {
+                    Ok(Expression::Memory16(address))
+                }
+            }
+            Some(Token::Identifier(name)) if REGISTERS.contains(&name.as_str()) => {
+                Ok(Expression::Register(name))
+            }
+            Some(Token::Identifier(name)) if FLAGS.contains(&name.as_str()) => {
+                Ok(Expression::Flag(name))
+            }
+            Some(Token::Identifier(name)) => Err(format!("Unknown name '{}'", name)),
+            Some(token) => Err(format!("Unexpected token {:?}", token)),
+            None => Err(String::from("Unexpected end of expression")),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expression = parser.or()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("Unexpected token {:?}", token)),
+        }
+    }
+
+    pub fn evaluate(&self, registers: &Registers, memory: &dyn Memory) -> u16 {
+        match self {
+            Expression::Number(value) => *value,
+            Expression::Register(name) => match name.as_str() {
+                "A" => u16::from(registers.a),
+                "B" => u16::from(registers.b),
+                "C" => u16::from(registers.c),
+                "D" => u16::from(registers.d),
+                "E" => u16::from(registers.e),
+                "F" => u16::from(registers.f),
+                "H" => u16::from(registers.h),
+                "L" => u16::from(registers.l),
+                "AF" => registers.get_af(),
+                "BC" => registers.get_bc(),
+                "DE" => registers.get_de(),
+                "HL" => registers.get_hl(),
+                "SP" => registers.sp,
+                "PC" => registers.pc,
+                _ => unreachable!(),
+            },
+            Expression::Flag(name) => {
+                let flag = match name.as_str() {
+                    "Z" => Flag::Z,
+                    "N" => Flag::N,
+                    "HC" => Flag::H,
+                    "CY" => Flag::C,
+                    _ => unreachable!(),
+                };
+                u16::from(registers.get_flag(flag))
+            }
+            Expression::Memory(address) => {
+                u16::from(memory.get(address.evaluate(registers, memory)))
+            }
+            Expression::Memory16(address) => {
+                let address = address.evaluate(registers, memory);
+                u16::from(memory.get(address))
+                    | (u16::from(memory.get(address.wrapping_add(1))) << 8)
+            }
+            Expression::Not(expression) => u16::from(expression.evaluate(registers, memory) == 0),
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(registers, memory);
+                let right = right.evaluate(registers, memory);
+                u16::from(match operator {
+                    Operator::Equal => left == right,
+                    Operator::NotEqual => left != right,
+                    Operator::Less => left < right,
+                    Operator::LessEqual => left <= right,
+                    Operator::Greater => left > right,
+                    Operator::GreaterEqual => left >= right,
+                    Operator::And => left != 0 && right != 0,
+                    Operator::Or => left != 0 || right != 0,
+                })
+            }
+        }
+    }
+
+    /// Evaluate as a condition, any non zero value is true
+    pub fn is_true(&self, registers: &Registers, memory: &dyn Memory) -> bool {
+        self.evaluate(registers, memory) != 0
+    }
+}
@@ -0,0 +1,102 @@
+use crate::cpu::registers::Registers;
+use crate::cpu::sm80::Core;
+use crate::memory::mmu::MMU;
+use crate::ppu::snapshot::PPUSnapshot;
+use std::collections::VecDeque;
+
+// The writable memory captured by a snapshot besides the ppu: cartridge ram, wram, IF and hram
+// with IE
+const SNAPSHOT_REGIONS: [(u16, u16); 4] = [
+    (0xa000, 0xbfff),
+    (0xc000, 0xdfff),
+    (0xff0f, 0xff0f),
+    (0xff80, 0xffff),
+];
+
+/// The cpu, ppu and memory state before an instruction, timer progress is not captured
+pub struct Snapshot {
+    pub registers: Registers,
+    halted: bool,
+    ei: bool,
+    ppu: PPUSnapshot,
+    memory: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Capture the memory as stored, the access gating and the cheats only apply to the cpu
+    pub fn capture(core: &Core, mmu: &MMU) -> Snapshot {
+        let mut bytes = Vec::new();
+        for (start, end) in SNAPSHOT_REGIONS.iter() {
+            bytes.extend((*start..=*end).map(|address| mmu.peek(address)));
+        }
+        Snapshot {
+            registers: core.registers,
+            halted: core.halted,
+            ei: core.ei,
+            ppu: mmu.ppu.borrow().capture(),
+            memory: bytes,
+        }
+    }
+
+    pub fn restore(&self, core: &mut Core, mmu: &mut MMU) {
+        core.registers = self.registers;
+        core.halted = self.halted;
+        core.ei = self.ei;
+        mmu.ppu.borrow_mut().restore(&self.ppu);
+        let mut bytes = self.memory.iter();
+        for (start, end) in SNAPSHOT_REGIONS.iter() {
+            for address in *start..=*end {
+                mmu.poke(address, *bytes.next().unwrap());
+            }
+        }
+    }
+}
+
+/// A short ring of snapshots taken while stepping, used to step back one instruction at a time
+pub struct StepHistory {
+    depth: usize,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl StepHistory {
+    pub fn new(depth: usize) -> StepHistory {
+        Self {
+            depth,
+            snapshots: VecDeque::with_capacity(depth),
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.snapshots.len() > depth {
+            self.snapshots.pop_front();
+        }
+    }
+
+    // Store a snapshot, the oldest one is dropped when the history is full
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.snapshots.len() >= self.depth {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        self.snapshots.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
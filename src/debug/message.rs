@@ -9,4 +9,5 @@ pub enum DebugMessage {
     MemoryUpdate(Vec<u8>),
     RegisterUpdate(Registers),
     TileUpdate(Vec<Tile>),
+    WatchUpdate(Vec<(String, u16)>),
 }
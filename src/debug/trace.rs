@@ -0,0 +1,85 @@
+use crate::emulator::Emulator;
+use std::fmt;
+
+/// The first step where the emulator state differs from the reference trace
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    // The index of the reference line, starting at 0
+    pub step: usize,
+    // The pc of the emulator at that step
+    pub pc: u16,
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Step {} at ${:04x}: {} is {}, expected {}",
+            self.step, self.pc, self.field, self.actual, self.expected
+        )
+    }
+}
+
+// The first field of the lines that differs, as (field, expected, actual). Fields are the
+// whitespace separated NAME:VALUE pairs, fields missing in the actual line are reported as empty
+fn first_difference(expected: &str, actual: &str) -> Option<(String, String, String)> {
+    let mut actual_fields = actual.split_whitespace();
+    for expected_field in expected.split_whitespace() {
+        let actual_field = actual_fields.next().unwrap_or("");
+        if expected_field != actual_field {
+            let (field, expected_value) = expected_field
+                .split_once(':')
+                .unwrap_or((expected_field, ""));
+            let actual_value = actual_field.split_once(':').map_or("", |(_, value)| value);
+            return Some((
+                field.to_string(),
+                expected_value.to_string(),
+                actual_value.to_string(),
+            ));
+        }
+    }
+    None
+}
+
+/// Run the rom from the post boot state and compare the registers before every instruction with
+/// the reference lines, in the Registers::trace_line format. Returns the number of steps matched
+/// or the first divergence
+pub fn compare_trace(rom: Vec<u8>, reference: &[&str]) -> Result<usize, Divergence> {
+    let mut emulator = Emulator::new(None, rom);
+    for (step, expected) in reference.iter().enumerate() {
+        let registers = emulator.cpu.core.registers;
+        if let Some((field, expected, actual)) = first_difference(expected, &registers.trace_line())
+        {
+            return Err(Divergence {
+                step,
+                pc: registers.pc,
+                field,
+                expected,
+                actual,
+            });
+        }
+        emulator.step_synchronized();
+    }
+    Ok(reference.len())
+}
+
+/// The reference lines leading to the divergence and the divergence itself, for printing. Lines
+/// are numbered from 1 like a text editor does
+pub fn divergence_report(reference: &[&str], divergence: &Divergence, context: usize) -> String {
+    let mut report = String::new();
+    let first = divergence.step.saturating_sub(context);
+    for (index, line) in reference
+        .iter()
+        .enumerate()
+        .take(divergence.step + 1)
+        .skip(first)
+    {
+        let marker = if index == divergence.step { '>' } else { ' ' };
+        report.push_str(&format!("{} {:>8}: {}\n", marker, index + 1, line));
+    }
+    report.push_str(&format!("{}\n", divergence));
+    report
+}
@@ -2,9 +2,12 @@ use crate::cpu::interrupt::Flag::Joypad;
 use crate::debug;
 use crate::debug::message::DebugMessage;
 use crate::emulator::control::ControlMessage;
+use crate::input::focus::FocusPause;
 use crate::input::input_message::InputMessage;
 use crate::input::joypad::JoyPadKey;
 use crate::ppu::{PPUFramebuffer, FB_H, FB_W};
+use crate::util::open_dropped_file;
+use crate::util::present::FramePacing;
 use debug::debug_state::DebugState;
 use flume::{Receiver, Sender, TryRecvError, TrySendError};
 use piston_window::*;
@@ -25,6 +28,9 @@ pub fn get_actual_window_size(scale: u32) -> (u32, u32) {
 pub fn start_display_thread(
     scale_factor: u32,
     rom_name: String,
+    show_debug: bool,
+    frame_pacing: FramePacing,
+    pause_on_unfocus: bool,
     control_message_sender: Sender<ControlMessage>,
     input_message_sender: Sender<InputMessage>,
     framebuffer_receiver: Receiver<PPUFramebuffer>,
@@ -41,6 +47,7 @@ pub fn start_display_thread(
 
             // Set the debug state, this is persisted
             let mut debug_state = DebugState::new();
+            debug_state.debug_visible = show_debug;
 
             // Create the piston window, this will be the window to to draw everything in our emulator
             let mut window: PistonWindow = WindowSettings::new(
@@ -49,8 +56,10 @@ pub fn start_display_thread(
             )
             .resizable(false)
             .exit_on_esc(true)
+            .vsync(frame_pacing.vsync)
             .build()
             .unwrap_or_else(|e| panic!("Failed to build window: {}", e));
+            window.set_max_fps(frame_pacing.max_fps);
 
             // The canvas to draw our emulator framebuffer
             let mut game_image = im::ImageBuffer::new(FB_W as u32, FB_H as u32);
@@ -83,6 +92,9 @@ pub fn start_display_thread(
             // Our super inaccurate FPS counter
             let mut fps_counter = fps::FPSCounter::new();
 
+            // Pauses the emulator while the window is in the background
+            let mut focus_pause = FocusPause::new(pause_on_unfocus);
+
             // Our display loop
             'display: while let Some(e) = window.next() {
                 if let Some(Button::Keyboard(key)) = e.press_args() {
@@ -120,6 +132,41 @@ pub fn start_display_thread(
                                 Err(TrySendError::Disconnected(_)) => break 'display,
                             }
                         }
+                        Key::P => {
+                            debug!("Pausing emulator");
+                            focus_pause.set_user_paused(true);
+                            match control_message_sender.try_send(ControlMessage::PAUSE) {
+                                Ok(_) => {}
+                                Err(TrySendError::Full(_)) => {}
+                                Err(TrySendError::Disconnected(_)) => break 'display,
+                            }
+                        }
+                        Key::R => {
+                            debug!("Resuming emulator");
+                            focus_pause.set_user_paused(false);
+                            match control_message_sender.try_send(ControlMessage::RESUME) {
+                                Ok(_) => {}
+                                Err(TrySendError::Full(_)) => {}
+                                Err(TrySendError::Disconnected(_)) => break 'display,
+                            }
+                        }
+                        Key::F12 => {
+                            debug!("Dumping emulator state");
+                            match control_message_sender.try_send(ControlMessage::DUMP) {
+                                Ok(_) => {}
+                                Err(TrySendError::Full(_)) => {}
+                                Err(TrySendError::Disconnected(_)) => break 'display,
+                            }
+                        }
+                        Key::F1 => {
+                            let visible = debug_state.toggle_debug_visible();
+                            debug!("Debug windows visible: {}", visible);
+                        }
+                        Key::N => match control_message_sender.try_send(ControlMessage::STEP) {
+                            Ok(_) => {}
+                            Err(TrySendError::Full(_)) => {}
+                            Err(TrySendError::Disconnected(_)) => break 'display,
+                        },
                         _ => {}
                     }
                 };
@@ -147,6 +194,30 @@ pub fn start_display_thread(
                         _ => {}
                     }
                 };
+                if let Some(focused) = e.focus_args() {
+                    debug!("Window focused: {}", focused);
+                    if let Some(message) = focus_pause.focus_changed(focused) {
+                        if let Err(TrySendError::Disconnected(_)) =
+                            control_message_sender.try_send(message)
+                        {
+                            break 'display;
+                        }
+                    }
+                }
+                if let Event::Input(Input::FileDrag(FileDrag::Drop(path)), _) = &e {
+                    debug!("Opening dropped file {}", path.display());
+                    let mut disconnected = false;
+                    open_dropped_file(path.clone(), |rom_path| {
+                        if let Err(TrySendError::Disconnected(_)) =
+                            control_message_sender.try_send(ControlMessage::OPEN(rom_path))
+                        {
+                            disconnected = true;
+                        }
+                    });
+                    if disconnected {
+                        break 'display;
+                    }
+                }
                 if let Some(_) = e.render_args() {
                     window.draw_2d(&e, |_, g, _| {
                         clear([0.03, 0.09, 0.12, 1.0], g);
@@ -160,16 +231,18 @@ pub fn start_display_thread(
                         .unwrap();
 
                     // Draw the debug info, if this function returns false, break the loop
-                    if !debug_canvas::draw_debug_info(
-                        &e,
-                        &mut window,
-                        debug_result_receiver.clone(),
-                        log_message_receiver.clone(),
-                        tile_update_receiver.clone(),
-                        &mut tile_image,
-                        &mut tile_texture,
-                        &mut debug_state,
-                    ) {
+                    if debug_state.debug_visible
+                        && !debug_canvas::draw_debug_info(
+                            &e,
+                            &mut window,
+                            debug_result_receiver.clone(),
+                            log_message_receiver.clone(),
+                            tile_update_receiver.clone(),
+                            &mut tile_image,
+                            &mut tile_texture,
+                            &mut debug_state,
+                        )
+                    {
                         break 'display;
                     }
 
@@ -7,6 +7,8 @@ use piston_window::*;
 
 const DEBUG_FONT_SIZE: usize = 13;
 const PADDING: usize = DEBUG_FONT_SIZE / 2;
+// Only the watches that fit above the logs are drawn
+const MAX_WATCHES: usize = 5;
 
 pub fn draw_debug_info(
     e: &Event,
@@ -37,6 +39,9 @@ pub fn draw_debug_info(
             DebugMessage::TileUpdate(tiles) => {
                 debug_state.tiles = tiles;
             }
+            DebugMessage::WatchUpdate(watches) => {
+                debug_state.watches = watches;
+            }
             _ => {}
         },
         Err(TryRecvError::Empty) => (),
@@ -136,6 +141,23 @@ pub fn draw_debug_info(
                 g,
             )
             .unwrap();
+        // Watches are drawn between the registers and the logs
+        for (i, (watch, value)) in debug_state.watches.iter().take(MAX_WATCHES).enumerate() {
+            text::Text::new_color([1.0; 4], DEBUG_FONT_SIZE as u32)
+                .draw(
+                    format!("{}: {:04x}", watch, value).as_str(),
+                    &mut font,
+                    &c.draw_state,
+                    c.transform.trans(
+                        10.0,
+                        ((FB_H * 2)
+                            + (DEBUG_FONT_SIZE + PADDING) * 4
+                            + i * (DEBUG_FONT_SIZE + PADDING)) as f64,
+                    ),
+                    g,
+                )
+                .unwrap();
+        }
         for (i, log_message) in debug_state.log_messages.iter().enumerate() {
             text::Text::new_color([1.0; 4], DEBUG_FONT_SIZE as u32)
                 .draw(
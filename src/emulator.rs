@@ -1,24 +1,141 @@
+pub mod accuracy;
 pub mod control;
+pub mod event_log;
 
 use super::input::input_message::InputMessage;
-use crate::cartridge::load_cartridge;
+use crate::cartridge::{check_cartridge, CartridgeError};
 use crate::cpu::instruction::InstructionSet;
+use crate::cpu::registers::Registers;
+use crate::cpu::sm80::StackGuardAction;
 use crate::cpu::ClockedCPU;
+use crate::debug::command::DebugCommand;
+use crate::debug::expression::Expression;
+use crate::debug::history::{Snapshot, StepHistory};
 use crate::debug::message::DebugMessage;
+use crate::emulator::accuracy::AccuracyProfile;
 use crate::emulator::control::ControlMessage;
+use crate::emulator::event_log::{Event, EventLog};
+use crate::input::joypad::SocdMode;
+use crate::memory::cheat::Cheat;
 use crate::memory::mmu::MMU;
-use crate::ppu::{random_framebuffer, Mode, PPUFramebuffer};
+use crate::memory::Memory;
+use crate::ppu::compat_palette::compat_palette;
+use crate::ppu::snapshot::PPUSnapshot;
+use crate::ppu::{no_rom_framebuffer, random_framebuffer, Mode, PPUFramebuffer, PPU};
 use crate::save::Savable;
+use crate::util::hash::Fnv1a;
+use crate::util::read_rom;
 use flume::{Receiver, Sender, TryRecvError, TrySendError};
-use std::cell::RefCell;
-use std::path::PathBuf;
+use std::cell::{Ref, RefCell};
+use std::collections::VecDeque;
+use std::fs;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::thread;
 use std::thread::{Builder, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub type VBlankCallback = Box<dyn FnMut(&PPU)>;
+
+/// The frame produced by Emulator::step_frame. Besides the framebuffer and the frame number, it
+/// tells whether a breakpoint stopped the frame, so the caller knows the frame is incomplete
+pub struct StepResult {
+    pub framebuffer: PPUFramebuffer,
+    // The number of frames completed since the emulator was created
+    pub frame_number: u64,
+    // Set when a breakpoint or the stack guard stopped the frame before it completed
+    pub stopped: bool,
+}
+
+/// The machine state when the boot rom hands off to the cartridge, for checking the state left
+/// by simulate_boot_rom against a real boot rom
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandoffState {
+    pub registers: Registers,
+    pub ime: bool,
+    // 0xff00 - 0xff7f as the cpu reads them
+    pub io_registers: Vec<u8>,
+    pub interrupt_enabled: u8,
+    pub ppu: PPUSnapshot,
+}
+
+// Number of instructions that can be stepped back by default
+const STEP_BACK_DEPTH: usize = 16;
+// The cycles of one iteration of the idle loops, a halted step and a taken jr
+const HALT_LOOP_CYCLES: u32 = 4;
+const JR_LOOP_CYCLES: u32 = 12;
+// An idle loop is skipped for at most a frame, so an interrupt that never comes can not hang the
+// emulator
+const FRAME_CYCLES: u32 = 70224;
+// Number of executed instruction addresses kept for the state dump
+const TRACE_DEPTH: usize = 16;
+
+/// Options for the emulator thread
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EmulatorOptions {
+    // Start paused before the first instruction
+    pub pause_on_boot: bool,
+    // Run the boot rom unthrottled until it hands off to the cartridge
+    pub fast_boot: bool,
+    // Fast forward idle loops waiting for an interrupt
+    pub idle_loop_skip: bool,
+    // Run as fast as possible instead of at the Game Boy speed
+    pub unthrottled: bool,
+    // How opposing directions held together are read by the game
+    pub socd_mode: SocdMode,
+    // Blend DMG palette changes over two frames to reduce flicker
+    pub palette_smoothing: bool,
+    // The emulation speed, 1.0 is the Game Boy speed
+    pub speed: f64,
+}
+
+/// The channel ends the emulator thread talks to the other threads with
+pub struct EmulatorChannels {
+    pub control_message_receiver: Receiver<ControlMessage>,
+    pub input_message_receiver: Receiver<InputMessage>,
+    pub framebuffer_sender: Sender<PPUFramebuffer>,
+    pub debug_result_sender: Sender<DebugMessage>,
+    pub tile_update_sender: Sender<DebugMessage>,
+}
 
 pub struct Emulator {
     pub mmu: Rc<RefCell<MMU>>,
     pub cpu: ClockedCPU,
+    // Invoked once per completed frame, when the ppu enters vblank
+    vblank_callback: Option<VBlankCallback>,
+    // When paused, tick will not execute any instruction, step can still be used
+    paused: bool,
+    // Kept so a rom loaded later boots the same way
+    boot_rom: Option<Vec<u8>>,
+    // Without a rom, the cpu is not run and the no rom test pattern is shown
+    rom_loaded: bool,
+    // Breakpoints pause the emulator when the pc reaches the address and the condition holds
+    breakpoints: Vec<(u16, Expression)>,
+    // Set while the boot rom runs unthrottled
+    fast_boot: bool,
+    // When set, the cpu is never throttled
+    unthrottled: bool,
+    // Watch expressions with their source, evaluated every frame
+    watches: Vec<(String, Expression)>,
+    // Snapshots taken before each instruction stepped while paused
+    step_history: StepHistory,
+    // When set, idle loops waiting for an interrupt are fast forwarded
+    idle_loop_skip: bool,
+    // The pc of the most recent steps, oldest first
+    trace: VecDeque<u16>,
+    // Pause once the cpu has run this many clock cycles
+    cycle_breakpoint: Option<u64>,
+    // The number of frames completed
+    frame_number: u64,
+    // Cycles of an instruction run by step_t_cycles that the ppu and timer have not caught up with
+    cpu_lead: u32,
+    // Cheats for the loaded rom, the enabled ones are copied to the mmu
+    cheats: Vec<Cheat>,
+    // The events of the session for bug reports, saving only borrows the emulator
+    event_log: RefCell<EventLog>,
+    // Set once the illegal opcode that locked up the cpu is logged
+    cpu_locked: bool,
 }
 
 impl Emulator {
@@ -27,7 +144,7 @@ impl Emulator {
             None => false,
             _ => true,
         };
-        let mmu = Rc::new(RefCell::new(MMU::new(boot_rom, rom)));
+        let mmu = Rc::new(RefCell::new(MMU::new(boot_rom.clone(), rom)));
         let mut cpu = ClockedCPU::new(mmu.clone());
 
         // If no boot rom is set, we simulate the boot rom states on the mmu and cpu
@@ -35,18 +152,691 @@ impl Emulator {
             mmu.borrow_mut().simulate_boot_rom();
             cpu.simulate_boot_rom();
         }
+        let unthrottled = !cpu.is_throttled();
+        let mut event_log = EventLog::new();
+        event_log.push(0, Event::RomLoaded(mmu.borrow().cartridge.title()));
+
+        Self {
+            cpu,
+            mmu,
+            vblank_callback: None,
+            paused: false,
+            boot_rom,
+            rom_loaded: true,
+            breakpoints: Vec::new(),
+            fast_boot: false,
+            unthrottled,
+            watches: Vec::new(),
+            step_history: StepHistory::new(STEP_BACK_DEPTH),
+            idle_loop_skip: false,
+            trace: VecDeque::with_capacity(TRACE_DEPTH),
+            cycle_breakpoint: None,
+            frame_number: 0,
+            cpu_lead: 0,
+            cheats: Vec::new(),
+            event_log: RefCell::new(event_log),
+            cpu_locked: false,
+        }
+    }
+
+    /// Create an emulator with no rom loaded, it shows the no rom screen until a rom is loaded
+    pub fn without_rom(boot_rom: Option<Vec<u8>>) -> Emulator {
+        let mut emulator = Emulator::new(boot_rom, vec![0x00; 0x8000]);
+        emulator.rom_loaded = false;
+        emulator.event_log.borrow_mut().clear();
+        {
+            let mmu = emulator.mmu.borrow();
+            let mut ppu = mmu.ppu.borrow_mut();
+            ppu.framebuffer = no_rom_framebuffer();
+            ppu.present_frame();
+        }
+        emulator
+    }
+
+    /// Create an emulator with the cpu wired to the given memory instead of the mmu, for testing
+    /// the cpu against a mock memory. The cpu starts at 0x0100 with the boot rom register values.
+    /// The mmu still runs the ppu and timers but the cpu can not see them
+    pub fn with_memory(memory: Rc<RefCell<dyn Memory>>) -> Emulator {
+        let mut emulator = Emulator::new(None, vec![0x00; 0x8000]);
+        emulator.event_log.borrow_mut().clear();
+        emulator.cpu = ClockedCPU::new(memory);
+        emulator.cpu.simulate_boot_rom();
+        emulator
+    }
+
+    /// Replace the running rom, the machine is reset and boots the new rom. A rom that can not be
+    /// loaded is rejected and the current one keeps running
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), CartridgeError> {
+        check_cartridge(&rom)?;
+        self.power_on(rom);
+        // Cheats are specific to a rom
+        self.cheats.clear();
+        let title = self.mmu.borrow().cartridge.title();
+        self.log_event(Event::RomLoaded(title));
+        Ok(())
+    }
+
+    /// Restart the loaded rom as if the power was cycled, the cheats stay enabled
+    pub fn reset(&mut self) {
+        if !self.rom_loaded {
+            return;
+        }
+        let rom = self.mmu.borrow().cartridge.rom().to_vec();
+        self.power_on(rom);
+        self.mmu.borrow_mut().set_cheats(&self.cheats);
+        self.log_event(Event::Reset);
+    }
+
+    // Replace the machine with one that boots the rom
+    fn power_on(&mut self, rom: Vec<u8>) {
+        let mut emulator = Emulator::new(self.boot_rom.clone(), rom);
+        // The options set on the emulator are kept by the new machine
+        {
+            let mmu = self.mmu.borrow();
+            let ppu = mmu.ppu.borrow();
+            let mut new_mmu = emulator.mmu.borrow_mut();
+            new_mmu.joypad.set_socd_mode(mmu.joypad.socd_mode());
+            new_mmu.set_access_gating(mmu.access_gating());
+            new_mmu.set_logo_write_diagnostic(mmu.logo_write_diagnostic());
+            new_mmu.set_serial_transfers_enabled(mmu.serial_transfers_enabled());
+            let mut new_ppu = new_mmu.ppu.borrow_mut();
+            new_ppu.set_sprite_limit(ppu.sprite_limit());
+            new_ppu.set_8x16_lsb_mask(ppu.sprite_8x16_lsb_mask());
+            new_ppu.set_palette_smoothing(ppu.palette_smoothing());
+            new_ppu.set_tile_usage_diagnostic(ppu.tile_usage_diagnostic());
+        }
+        emulator.cpu.set_speed(self.cpu.speed());
+        emulator
+            .cpu
+            .set_decode_cache(self.cpu.decode_cache().is_some());
+        emulator.cpu.set_access_logging(self.cpu.access_logging());
+        emulator.cpu.core.stack_guard = self.cpu.core.stack_guard;
+        let compat_colorization = self.mmu.borrow().ppu.borrow().compat_palette().is_some();
+        self.mmu = emulator.mmu;
+        self.cpu = emulator.cpu;
+        // The compat palette is picked from the title of the new rom
+        self.set_compat_colorization(compat_colorization);
+        self.rom_loaded = true;
+        self.fast_boot = false;
+        self.cpu_locked = false;
+        // The snapshots belong to the previous machine
+        self.step_history.clear();
+        self.cpu.set_throttled(!self.unthrottled);
+    }
+
+    /// The events of the session, timestamped with the cpu cycle count
+    pub fn event_log(&self) -> Ref<'_, EventLog> {
+        self.event_log.borrow()
+    }
 
-        Self { cpu, mmu }
+    fn log_event(&self, event: Event) {
+        self.event_log
+            .borrow_mut()
+            .push(self.cpu.cycle_count(), event);
     }
 
+    // Log the illegal opcode once when it locks up the cpu
+    fn check_cpu_locked(&mut self) {
+        if let (Some((address, opcode)), false) = (self.cpu.illegal_opcode(), self.cpu_locked) {
+            self.cpu_locked = true;
+            self.log_event(Event::IllegalOpcode(address, opcode));
+        }
+    }
+
+    /// Decode and enable a Game Genie or GameShark code
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), String> {
+        self.cheats.push(Cheat::parse(code)?);
+        self.mmu.borrow_mut().set_cheats(&self.cheats);
+        self.log_event(Event::CheatApplied(code.to_string()));
+        Ok(())
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Enable or disable the cheat at the index of cheats()
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+        let cheat = self
+            .cheats
+            .get_mut(index)
+            .ok_or_else(|| format!("No cheat at index {}", index))?;
+        cheat.enabled = enabled;
+        let code = cheat.code.clone();
+        self.mmu.borrow_mut().set_cheats(&self.cheats);
+        if enabled {
+            self.log_event(Event::CheatApplied(code));
+        }
+        Ok(())
+    }
+
+    pub fn has_rom(&self) -> bool {
+        self.rom_loaded
+    }
+
+    /// Set how many instructions can be stepped back, each step keeps a copy of the writable memory
+    pub fn set_step_back_depth(&mut self, depth: usize) {
+        self.step_history.set_depth(depth);
+    }
+
+    pub fn debug_command(&mut self, command: DebugCommand) -> Result<(), String> {
+        match command {
+            DebugCommand::PeekValue(address) => {
+                info!("${:04x} = ${:02x}", address, self.mmu.borrow().get(address));
+            }
+            DebugCommand::ConditionalBreakpoint { pc, condition } => {
+                self.breakpoints.push((pc, Expression::parse(&condition)?));
+            }
+            DebugCommand::StepBack => match self.step_history.pop() {
+                Some(snapshot) => snapshot.restore(&mut self.cpu.core, &mut self.mmu.borrow_mut()),
+                None => return Err(String::from("No instruction to step back to")),
+            },
+            DebugCommand::AddWatch(source) => {
+                let expression = Expression::parse(&source)?;
+                self.watches.push((source, expression));
+            }
+            DebugCommand::ClearWatches => self.watches.clear(),
+            DebugCommand::StepN(count) => {
+                if !self.has_rom() {
+                    return Err(String::from("No rom to step"));
+                }
+                self.pause();
+                let target = self.cpu.instruction_count() + u64::from(count);
+                while self.cpu.instruction_count() < target {
+                    self.step();
+                    if !self.breakpoints.is_empty() && self.breakpoint_hit() {
+                        break;
+                    }
+                    // A locked up cpu never runs another instruction
+                    if self.cpu.illegal_opcode().is_some() {
+                        break;
+                    }
+                }
+            }
+            DebugCommand::BreakAtCycle(cycle) => self.cycle_breakpoint = Some(cycle),
+        }
+        Ok(())
+    }
+
+    /// The current value of every watch expression
+    pub fn watch_values(&self) -> Vec<(String, u16)> {
+        let registers = &self.cpu.core.registers;
+        let mmu = self.mmu.borrow();
+        self.watches
+            .iter()
+            .map(|(source, expression)| (source.clone(), expression.evaluate(registers, &*mmu)))
+            .collect()
+    }
+
+    // Whether a breakpoint on the current pc has its condition met
+    fn breakpoint_hit(&self) -> bool {
+        let registers = &self.cpu.core.registers;
+        let mmu = self.mmu.borrow();
+        self.breakpoints
+            .iter()
+            .any(|(pc, condition)| *pc == registers.pc && condition.is_true(registers, &*mmu))
+    }
+
+    /// Set all the accuracy features of the subsystems from a single profile
+    pub fn set_accuracy_profile(&mut self, profile: AccuracyProfile) {
+        {
+            let mut mmu = self.mmu.borrow_mut();
+            mmu.set_access_gating(profile.access_gating());
+            mmu.ppu
+                .borrow_mut()
+                .set_sprite_limit(profile.sprite_limit());
+        }
+        self.idle_loop_skip = profile.idle_loop_skip();
+        self.cpu.set_decode_cache(profile.decode_cache());
+    }
+
+    /// Colorize DMG games like the CGB boot rom does, with the palette picked from the title
+    pub fn set_compat_colorization(&mut self, enabled: bool) {
+        let mmu = self.mmu.borrow();
+        let compat_palette = if enabled {
+            Some(compat_palette(&mmu.cartridge.title()))
+        } else {
+            None
+        };
+        mmu.ppu.borrow_mut().set_compat_palette(compat_palette);
+    }
+
+    /// Run the boot rom unthrottled, normal speed resumes when it hands off to the cartridge.
+    /// Does nothing without a boot rom
+    pub fn set_fast_boot(&mut self, fast_boot: bool) {
+        self.fast_boot = fast_boot && self.mmu.borrow().boot_rom_enabled();
+        self.cpu.set_throttled(!self.fast_boot && !self.unthrottled);
+    }
+
+    /// Run as fast as possible for the whole session, this is also set by RGB_UNTHROTTLED=1
+    pub fn set_unthrottled(&mut self, unthrottled: bool) {
+        self.unthrottled = unthrottled;
+        self.cpu.set_throttled(!self.fast_boot && !self.unthrottled);
+    }
+
+    /// Set the keys directly, bypassing the input channel. See JoyPad::set_state for the format
+    pub fn set_joypad_state(&mut self, buttons: u8, directions: u8) {
+        self.mmu.borrow_mut().joypad.set_state(buttons, directions);
+    }
+
+    pub fn joypad_state(&self) -> (u8, u8) {
+        self.mmu.borrow().joypad.state()
+    }
+
+    /// Select how opposing directions held together are read, kept when another rom is loaded
+    pub fn set_socd_mode(&mut self, socd_mode: SocdMode) {
+        self.mmu.borrow_mut().joypad.set_socd_mode(socd_mode);
+    }
+
+    /// Scale the emulation speed, kept when another rom is loaded
+    pub fn set_speed(&mut self, speed: f64) {
+        self.cpu.set_speed(speed);
+    }
+
+    /// Blend DMG palette changes over two frames, kept when another rom is loaded
+    pub fn set_palette_smoothing(&mut self, enabled: bool) {
+        self.mmu
+            .borrow()
+            .ppu
+            .borrow_mut()
+            .set_palette_smoothing(enabled);
+    }
+
+    /// Fast forward idle loops, a halt or a jr to itself waiting for an interrupt, to the next
+    /// interrupt instead of executing every iteration
+    pub fn set_idle_loop_skip(&mut self, idle_loop_skip: bool) {
+        self.idle_loop_skip = idle_loop_skip;
+    }
+
+    pub fn idle_loop_skip(&self) -> bool {
+        self.idle_loop_skip
+    }
+
+    // Whether an enabled interrupt is requested
+    fn interrupt_pending(&self) -> bool {
+        self.cpu.core.interrupt_pending()
+    }
+
+    // The cycles of one iteration of the idle loop the cpu is in, None when it is not idling
+    fn idle_loop_cycles(&self) -> Option<u32> {
+        if self.interrupt_pending() {
+            return None;
+        }
+        let pc = self.cpu.core.registers.pc;
+        let mmu = self.mmu.borrow();
+        if self.cpu.core.halted {
+            Some(HALT_LOOP_CYCLES)
+        } else if mmu.get(pc) == 0x18 && mmu.get(pc.wrapping_add(1)) == 0xfe {
+            Some(JR_LOOP_CYCLES)
+        } else {
+            None
+        }
+    }
+
+    // Run the idle loop iterations without the cpu until an interrupt is requested, a frame
+    // completes or the cycle breakpoint is reached, returns the cycles skipped
+    fn skip_idle_loop(&mut self, loop_cycles: u32, was_vblank: bool) -> u32 {
+        let until_breakpoint = self
+            .cycle_breakpoint
+            .map(|cycle| cycle.saturating_sub(self.cpu.cycle_count()));
+        let mut cycles = 0;
+        loop {
+            self.mmu.borrow_mut().tick(loop_cycles);
+            cycles += loop_cycles;
+            if self.interrupt_pending()
+                || (!was_vblank && self.should_refresh_screen())
+                || cycles >= FRAME_CYCLES
+                || matches!(until_breakpoint, Some(remaining) if u64::from(cycles) >= remaining)
+            {
+                break;
+            }
+        }
+        self.cpu.idle(cycles);
+        cycles
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.step_history.clear();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Register a callback that is invoked once per completed frame with the ppu, this allows
+    /// embedders to capture the framebuffer without touching the emulator loop
+    pub fn set_vblank_callback(&mut self, callback: VBlankCallback) {
+        self.vblank_callback = Some(callback);
+    }
+
+    /// Execute the next instruction unless the emulator is paused
     pub fn tick(&mut self) -> u32 {
-        // Execute one cpu cycle
-        let cycles = self.cpu.tick();
-        // Update the mmu with the cycles
-        self.mmu.borrow_mut().tick(cycles);
+        if self.paused || !self.rom_loaded {
+            return 0;
+        }
+        self.step()
+    }
+
+    /// Execute a single instruction, regardless of the pause state
+    pub fn step(&mut self) -> u32 {
+        self.catch_up();
+        if self.paused {
+            let snapshot = Snapshot::capture(&self.cpu.core, &self.mmu.borrow());
+            self.step_history.push(snapshot);
+        }
+        let was_vblank = self.should_refresh_screen();
+        let idle_loop_cycles = if self.idle_loop_skip && !self.paused {
+            self.idle_loop_cycles()
+        } else {
+            None
+        };
+        let cycles = match idle_loop_cycles {
+            Some(loop_cycles) => self.skip_idle_loop(loop_cycles, was_vblank),
+            None => {
+                self.record_trace();
+                // Execute one cpu cycle
+                let cycles = self.cpu.tick();
+                // Update the mmu with the cycles
+                self.mmu.borrow_mut().tick(cycles);
+                self.check_cpu_locked();
+                cycles
+            }
+        };
+        self.after_instruction(was_vblank);
         cycles
     }
 
+    // Keep the pc of the instruction about to run for the state dump
+    fn record_trace(&mut self) {
+        if self.trace.len() == TRACE_DEPTH {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(self.cpu.core.registers.pc);
+    }
+
+    // The bookkeeping after an instruction, shared by every way of stepping the emulator: the
+    // stack guard, the breakpoints and the frame completion. Returns true when the emulator was
+    // paused by a breakpoint or the stack guard
+    fn after_instruction(&mut self, was_vblank: bool) -> bool {
+        let mut stopped = false;
+        if self.cpu.core.stack_overflow {
+            self.cpu.core.stack_overflow = false;
+            if let Some(stack_guard) = self.cpu.core.stack_guard {
+                if stack_guard.action == StackGuardAction::Break {
+                    self.pause();
+                    stopped = true;
+                }
+            }
+        }
+        if self.fast_boot && !self.mmu.borrow().boot_rom_enabled() {
+            debug!("Boot rom handed off, resuming normal speed");
+            self.set_fast_boot(false);
+        }
+        if !self.breakpoints.is_empty() && self.breakpoint_hit() {
+            debug!("Breakpoint hit at ${:04x}", self.cpu.core.registers.pc);
+            self.pause();
+            stopped = true;
+        }
+        if let Some(cycle) = self.cycle_breakpoint {
+            if self.cpu.cycle_count() >= cycle {
+                debug!("Cycle breakpoint hit at cycle {}", self.cpu.cycle_count());
+                self.cycle_breakpoint = None;
+                self.pause();
+                stopped = true;
+            }
+        }
+        // Frame is completed when we just entered vblank
+        if !was_vblank && self.should_refresh_screen() {
+            self.frame_number += 1;
+            if let Some(callback) = self.vblank_callback.as_mut() {
+                callback(&self.mmu.borrow().ppu.borrow());
+            }
+        }
+        stopped
+    }
+
+    /// Read a byte as the cpu sees it, for extracting values like the score from game memory.
+    /// Where a game keeps them is specific to each game
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.mmu.borrow().get(address)
+    }
+
+    /// Read length bytes from the address, wrapping around at the end of the address space
+    pub fn read_memory_range(&self, address: u16, length: usize) -> Vec<u8> {
+        let mmu = self.mmu.borrow();
+        (0..length)
+            .map(|offset| mmu.get(address.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    /// The opcodes executed during the session, unprefixed and 0xcb prefixed, indexed by opcode
+    pub fn opcode_coverage(&self) -> ([bool; 256], [bool; 256]) {
+        self.cpu.opcode_coverage()
+    }
+
+    /// Execute one instruction without throttling and advance the ppu and timer by the cycles it
+    /// took, pending interrupts are dispatched before the instruction. Returns the cycles consumed
+    pub fn step_synchronized(&mut self) -> u32 {
+        self.step_synchronized_until_break().0
+    }
+
+    // Run one instruction like step_synchronized, also returns whether a breakpoint or the stack
+    // guard paused the emulator
+    fn step_synchronized_until_break(&mut self) -> (u32, bool) {
+        self.catch_up();
+        let was_vblank = self.should_refresh_screen();
+        self.record_trace();
+        let cycles = self.cpu.step();
+        self.mmu.borrow_mut().tick(cycles);
+        self.check_cpu_locked();
+        (cycles, self.after_instruction(was_vblank))
+    }
+
+    /// Advance the ppu and timer by exactly t cycles. The cpu can not stop in the middle of an
+    /// instruction, it runs each instruction whole when the machine reaches its first cycle
+    pub fn step_t_cycles(&mut self, t: u32) {
+        let mut remaining = t;
+        while remaining > 0 {
+            if self.cpu_lead == 0 {
+                self.record_trace();
+                self.cpu_lead = self.cpu.step();
+                self.check_cpu_locked();
+            }
+            let was_vblank = self.should_refresh_screen();
+            let cycles = remaining.min(self.cpu_lead);
+            self.mmu.borrow_mut().tick(cycles);
+            self.cpu_lead -= cycles;
+            remaining -= cycles;
+            self.after_instruction(was_vblank);
+        }
+    }
+
+    // Run the ppu and timer through the rest of an instruction started by step_t_cycles
+    fn catch_up(&mut self) {
+        if self.cpu_lead > 0 {
+            self.mmu.borrow_mut().tick(self.cpu_lead);
+            self.cpu_lead = 0;
+        }
+    }
+
+    /// A readable dump of the machine state for bug reports: the registers, the ppu, the interrupt
+    /// state, the mapped banks and the recent instruction trace
+    pub fn state_dump(&self) -> String {
+        let registers = &self.cpu.core.registers;
+        let mmu = self.mmu.borrow();
+        let ppu = mmu.ppu.borrow();
+        let mut dump = String::new();
+        dump.push_str("[cpu]\n");
+        dump.push_str(&format!(
+            "AF: {:04x} BC: {:04x} DE: {:04x} HL: {:04x} SP: {:04x} PC: {:04x}\n",
+            registers.get_af(),
+            registers.get_bc(),
+            registers.get_de(),
+            registers.get_hl(),
+            registers.sp,
+            registers.pc
+        ));
+        dump.push_str(&format!("halted: {}\n", self.cpu.core.halted));
+        dump.push_str("[ppu]\n");
+        dump.push_str(&format!(
+            "mode: {:?} LY: {:02x} LCDC: {:02x} STAT: {:02x}\n",
+            ppu.mode,
+            mmu.get(0xff44),
+            mmu.get(0xff40),
+            mmu.get(0xff41)
+        ));
+        dump.push_str("[interrupts]\n");
+        dump.push_str(&format!(
+            "IE: {:02x} IF: {:02x} IME: {}\n",
+            mmu.get(0xffff),
+            mmu.get(0xff0f),
+            self.cpu.core.ei
+        ));
+        dump.push_str("[banks]\n");
+        dump.push_str(&format!(
+            "rom: {:02x} ram: {:02x} wram: {:02x} vram: {:02x}\n",
+            mmu.cartridge.rom_bank(),
+            mmu.cartridge.ram_bank(),
+            mmu.work_ram_bank(),
+            ppu.vram_bank()
+        ));
+        dump.push_str("[trace]\n");
+        for pc in self.trace.iter() {
+            dump.push_str(&format!("${:04x}: {:02x}\n", pc, mmu.get(*pc)));
+        }
+        dump
+    }
+
+    /// Run unthrottled until the screen stays the same for stable_frames consecutive frames, or
+    /// max_frames frames ran. Returns the number of frames run, used to get to a title screen.
+    /// A breakpoint stops it at the frame it is hit in
+    pub fn run_until_stable(&mut self, max_frames: u32, stable_frames: u32) -> u32 {
+        let mut last_hash = None;
+        let mut unchanged = 0;
+        for frame in 1..=max_frames {
+            if self.run_frame() {
+                return frame;
+            }
+            let hash = self.front_buffer_hash();
+            if last_hash == Some(hash) {
+                unchanged += 1;
+                if unchanged >= stable_frames {
+                    return frame;
+                }
+            } else {
+                unchanged = 0;
+            }
+            last_hash = Some(hash);
+        }
+        max_frames
+    }
+
+    /// The current state in the form captured at the boot rom handoff
+    pub fn handoff_state(&self) -> HandoffState {
+        let mmu = self.mmu.borrow();
+        let ppu = mmu.ppu.borrow();
+        HandoffState {
+            registers: self.cpu.core.registers,
+            ime: self.cpu.core.ei,
+            io_registers: (0xff00..=0xff7f).map(|address| mmu.get(address)).collect(),
+            interrupt_enabled: mmu.get(0xffff),
+            ppu: ppu.capture(),
+        }
+    }
+
+    /// Run the boot rom unthrottled until the cpu is about to fetch from 0x0100 and capture the
+    /// state. Panics when the boot rom does not hand off within max_frames frames. Breakpoints
+    /// pause the emulator but the boot rom still runs to the handoff
+    pub fn run_boot_rom(&mut self, max_frames: u32) -> HandoffState {
+        let mut cycles = 0;
+        while self.cpu.core.registers.pc != 0x0100 {
+            if cycles > max_frames as u64 * FRAME_CYCLES as u64 {
+                panic!("Boot rom did not hand off in {} frames", max_frames);
+            }
+            cycles += self.step_synchronized() as u64;
+        }
+        self.handoff_state()
+    }
+
+    // Run unthrottled until the ppu enters vblank, or for a frame worth of cycles when the lcd is
+    // off. Returns true when a breakpoint or the stack guard stopped it before the end of the frame
+    fn run_frame(&mut self) -> bool {
+        let frame_number = self.frame_number;
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES && self.frame_number == frame_number {
+            let (step_cycles, stopped) = self.step_synchronized_until_break();
+            cycles += step_cycles;
+            if stopped {
+                return true;
+            }
+        }
+        // Without the lcd there is no vblank, a frame is counted for each frame worth of cycles
+        if self.frame_number == frame_number {
+            self.frame_number += 1;
+        }
+        false
+    }
+
+    /// The number of frames completed since the emulator was created
+    pub fn frame_number(&self) -> u64 {
+        self.frame_number
+    }
+
+    /// Hold the keys in the action for the next frame and run it, for driving the emulator from
+    /// an agent. The action has a bit set for each pressed key, with the JoyPadKey values. A
+    /// breakpoint stops the frame early and pauses the emulator
+    ///
+    /// This is the step(action) of the agent API. It is named step_frame because step already
+    /// runs a single instruction, and the result also reports whether the frame was stopped
+    pub fn step_frame(&mut self, action: u8) -> StepResult {
+        self.set_joypad_state(!action >> 4, !action);
+        let stopped = self.run_frame();
+        StepResult {
+            framebuffer: *self.mmu.borrow().ppu.borrow().front_buffer(),
+            frame_number: self.frame_number,
+            stopped,
+        }
+    }
+
+    fn front_buffer_hash(&self) -> u64 {
+        self.mmu.borrow().ppu.borrow().framebuffer_hash()
+    }
+
+    /// A hash of the machine state that is stable across runs: the cpu, the ppu and everything
+    /// the cpu can read. Equal states hash the same, used to check replays are deterministic
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        let core = &self.cpu.core;
+        let registers = &core.registers;
+        hasher.write(&[
+            registers.a,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.f,
+            registers.h,
+            registers.l,
+            u8::from(core.halted),
+            u8::from(core.halt_bug),
+            u8::from(core.ei),
+        ]);
+        hasher.write(&registers.pc.to_le_bytes());
+        hasher.write(&registers.sp.to_le_bytes());
+        hasher.write(&self.cpu.cycle_count().to_le_bytes());
+        let mmu = self.mmu.borrow();
+        hasher.write(&mmu.ppu.borrow().capture().to_bytes());
+        for address in 0x0000..=0xffff {
+            hasher.write_u8(mmu.get(address));
+        }
+        hasher.finish()
+    }
+
     pub fn should_refresh_screen(&self) -> bool {
         self.mmu.borrow().ppu.borrow().mode == Mode::VBlank
     }
@@ -54,28 +844,49 @@ impl Emulator {
 
 impl Savable for Emulator {
     fn save(&self, save_path: PathBuf) {
-        self.mmu.borrow().cartridge.save(save_path);
+        self.mmu.borrow().cartridge.save(save_path.clone());
+        self.log_event(Event::SaveWritten(save_path));
     }
 
     fn load(&mut self, save_path: PathBuf) {
-        self.mmu.borrow_mut().cartridge.load(save_path);
+        self.mmu.borrow_mut().cartridge.load(save_path.clone());
+        self.log_event(Event::SaveLoaded(save_path));
     }
 }
 
 pub fn start_emulator_thread(
     boot_rom: Option<Vec<u8>>,
-    rom: Vec<u8>,
-    control_message_receiver: Receiver<ControlMessage>,
-    input_message_receiver: Receiver<InputMessage>,
-    framebuffer_sender: Sender<PPUFramebuffer>,
-    debug_result_sender: Sender<DebugMessage>,
-    tile_update_sender: Sender<DebugMessage>,
+    rom: Option<Vec<u8>>,
+    options: EmulatorOptions,
+    channels: EmulatorChannels,
 ) -> JoinHandle<()> {
+    let EmulatorChannels {
+        control_message_receiver,
+        input_message_receiver,
+        framebuffer_sender,
+        debug_result_sender,
+        tile_update_sender,
+    } = channels;
     Builder::new()
         .name("emulator".to_string())
         .spawn(move || {
             debug!("Emulator Thread spawned");
-            let mut emulator = Emulator::new(boot_rom, rom);
+            let mut emulator = match rom {
+                Some(rom) => Emulator::new(boot_rom, rom),
+                None => Emulator::without_rom(boot_rom),
+            };
+            emulator.set_fast_boot(options.fast_boot);
+            emulator.set_idle_loop_skip(options.idle_loop_skip);
+            emulator.set_socd_mode(options.socd_mode);
+            emulator.set_palette_smoothing(options.palette_smoothing);
+            emulator.set_speed(options.speed);
+            if options.unthrottled {
+                emulator.set_unthrottled(true);
+            }
+            if options.pause_on_boot {
+                debug!("Emulator paused on boot");
+                emulator.pause();
+            }
             'emulator: loop {
                 // std::thread::sleep(std::time::Duration::from_millis(10));
                 emulator.tick();
@@ -103,13 +914,62 @@ pub fn start_emulator_thread(
                                 emulator.mmu.borrow().cartridge.title()
                             )));
                         }
+                        ControlMessage::DUMP => {
+                            let timestamp = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_secs();
+                            let path = format!("rgb-state-{}.log", timestamp);
+                            match fs::write(&path, emulator.state_dump()) {
+                                Ok(_) => info!("State dumped to {}", path),
+                                Err(error) => warn!("Failed to dump state to {}: {}", path, error),
+                            }
+                            let path = format!("rgb-events-{}.log", timestamp);
+                            match emulator.event_log().dump(Path::new(&path)) {
+                                Ok(_) => info!("Events dumped to {}", path),
+                                Err(error) => warn!("Failed to dump events to {}: {}", path, error),
+                            }
+                        }
+                        ControlMessage::PAUSE => emulator.pause(),
+                        ControlMessage::RESUME => emulator.resume(),
+                        ControlMessage::STEP => {
+                            if emulator.has_rom() {
+                                emulator.step();
+                            }
+                        }
+                        ControlMessage::OPEN(path) => {
+                            info!("Loading rom {}", path.display());
+                            // The current rom keeps running when the new one can not be loaded
+                            let loaded = read_rom(&path).and_then(|rom| {
+                                emulator.load_rom(rom).map_err(|error| error.to_string())
+                            });
+                            if let Err(error) = loaded {
+                                warn!("Could not load {}: {}", path.display(), error);
+                            }
+                        }
+                        ControlMessage::DEBUG(command) => {
+                            let stepped = matches!(command, DebugCommand::StepN(_));
+                            if let Err(error) = emulator.debug_command(command) {
+                                warn!("Debug command failed: {}", error);
+                            }
+                            // The screen is only refreshed at vblank, report where the steps ended
+                            if stepped {
+                                match debug_result_sender.try_send(DebugMessage::RegisterUpdate(
+                                    emulator.cpu.core.registers,
+                                )) {
+                                    Ok(_) => {}
+                                    Err(TrySendError::Full(_)) => {}
+                                    Err(TrySendError::Disconnected(_)) => break 'emulator,
+                                }
+                            }
+                        }
                     },
                     Err(TryRecvError::Empty) => {}
                     Err(TryRecvError::Disconnected) => break 'emulator,
                 }
-                if emulator.should_refresh_screen() {
+                if emulator.should_refresh_screen() || !emulator.has_rom() {
                     match framebuffer_sender
-                        .try_send(emulator.mmu.borrow().ppu.borrow().framebuffer)
+                        .try_send(*emulator.mmu.borrow().ppu.borrow().front_buffer())
                     {
                         Ok(_) => {}
                         Err(TrySendError::Full(_)) => {}
@@ -122,8 +982,17 @@ pub fn start_emulator_thread(
                         Err(TrySendError::Full(_)) => {}
                         Err(TrySendError::Disconnected(_)) => break 'emulator,
                     }
+                    if !emulator.watches.is_empty() {
+                        match debug_result_sender
+                            .try_send(DebugMessage::WatchUpdate(emulator.watch_values()))
+                        {
+                            Ok(_) => {}
+                            Err(TrySendError::Full(_)) => {}
+                            Err(TrySendError::Disconnected(_)) => break 'emulator,
+                        }
+                    }
                     match tile_update_sender.try_send(DebugMessage::TileUpdate(Vec::from(
-                        emulator.mmu.borrow().ppu.borrow().tile_set,
+                        emulator.mmu.borrow().ppu.borrow().tile_set[0],
                     ))) {
                         Ok(_) => {}
                         Err(TrySendError::Full(_)) => {}
@@ -144,6 +1013,10 @@ pub fn start_emulator_thread(
                 } else {
                     thread::yield_now();
                 }
+                if emulator.is_paused() || !emulator.has_rom() {
+                    // Nothing to run, avoid spinning while waiting for control messages
+                    thread::sleep(Duration::from_millis(1));
+                }
             }
             debug!("Emulator loop exited");
             std::process::exit(0x00);
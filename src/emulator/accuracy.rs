@@ -0,0 +1,38 @@
+use crate::ppu::MAX_SPRITES_PER_LINE;
+
+/// Presets for the accuracy features of the emulator, trading hardware accuracy for speed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccuracyProfile {
+    // No hardware quirks, as fast as possible
+    Fast,
+    // Hardware limits that games rely on, idle loops are still fast forwarded and there is no
+    // access gating
+    Balanced,
+    // All hardware quirks are emulated
+    Accurate,
+}
+
+impl AccuracyProfile {
+    /// Whether cpu access to VRAM and OAM is blocked while the ppu is using them
+    pub fn access_gating(self) -> bool {
+        self == AccuracyProfile::Accurate
+    }
+
+    /// The number of sprites the ppu draws on each line, the fast profile draws all of them
+    pub fn sprite_limit(self) -> Option<u8> {
+        match self {
+            AccuracyProfile::Fast => None,
+            _ => Some(MAX_SPRITES_PER_LINE as u8),
+        }
+    }
+
+    /// Whether idle loops waiting for an interrupt are fast forwarded instead of executed
+    pub fn idle_loop_skip(self) -> bool {
+        self != AccuracyProfile::Accurate
+    }
+
+    /// Whether the instructions in the rom are decoded once and reused
+    pub fn decode_cache(self) -> bool {
+        self == AccuracyProfile::Fast
+    }
+}
@@ -1,4 +1,15 @@
+use crate::debug::command::DebugCommand;
+use std::path::PathBuf;
+
 pub enum ControlMessage {
     SAVE,
     LOAD,
+    PAUSE,
+    RESUME,
+    STEP,
+    // Write the machine state to a timestamped log file
+    DUMP,
+    // Load the rom at the given path, replacing the running one
+    OPEN(PathBuf),
+    DEBUG(DebugCommand),
 }
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Something that happened to the emulator, recorded for bug reports
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    // The title of the rom
+    RomLoaded(String),
+    Reset,
+    SaveWritten(PathBuf),
+    SaveLoaded(PathBuf),
+    // The code of the enabled cheat
+    CheatApplied(String),
+    // The address and the opcode, the cpu locks up
+    IllegalOpcode(u16, u8),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::RomLoaded(title) => write!(f, "rom loaded: {}", title),
+            Event::Reset => write!(f, "reset"),
+            Event::SaveWritten(path) => write!(f, "save written: {}", path.display()),
+            Event::SaveLoaded(path) => write!(f, "save loaded: {}", path.display()),
+            Event::CheatApplied(code) => write!(f, "cheat applied: {}", code),
+            Event::IllegalOpcode(address, opcode) => {
+                write!(f, "illegal opcode ${:02x} at ${:04x}", opcode, address)
+            }
+        }
+    }
+}
+
+/// An event with the cpu cycle count when it happened, the count restarts on reset
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedEvent {
+    pub cycle: u64,
+    pub event: Event,
+}
+
+/// The events of the session in order
+#[derive(Default)]
+pub struct EventLog {
+    events: Vec<LoggedEvent>,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        Self { events: Vec::new() }
+    }
+
+    pub fn push(&mut self, cycle: u64, event: Event) {
+        debug!("Event at cycle {}: {}", cycle, event);
+        self.events.push(LoggedEvent { cycle, event });
+    }
+
+    pub fn events(&self) -> &[LoggedEvent] {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Write the log to a file, one event per line
+    pub fn dump(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for logged in self.events.iter() {
+            writeln!(f, "{:>12}: {}", logged.cycle, logged.event)?;
+        }
+        Ok(())
+    }
+}
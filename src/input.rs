@@ -1,3 +1,4 @@
+pub mod focus;
 pub mod input_message;
 pub mod joypad;
 
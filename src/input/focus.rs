@@ -0,0 +1,47 @@
+use crate::emulator::control::ControlMessage;
+
+/// Pauses the emulator when the window loses focus and resumes it when the focus comes back. An
+/// emulator the user paused stays paused
+pub struct FocusPause {
+    enabled: bool,
+    // Set by the user pausing with the keyboard
+    user_paused: bool,
+    // Set while the emulator is paused because of the focus loss
+    focus_paused: bool,
+}
+
+impl FocusPause {
+    pub fn new(enabled: bool) -> FocusPause {
+        Self {
+            enabled,
+            user_paused: false,
+            focus_paused: false,
+        }
+    }
+
+    /// Record the pause state the user asked for
+    pub fn set_user_paused(&mut self, paused: bool) {
+        self.user_paused = paused;
+        if !paused {
+            self.focus_paused = false;
+        }
+    }
+
+    /// The message to send to the emulator when the window focus changes, if any
+    pub fn focus_changed(&mut self, focused: bool) -> Option<ControlMessage> {
+        if !self.enabled || self.user_paused {
+            return None;
+        }
+        match (focused, self.focus_paused) {
+            (false, false) => {
+                self.focus_paused = true;
+                Some(ControlMessage::PAUSE)
+            }
+            (true, true) => {
+                self.focus_paused = false;
+                Some(ControlMessage::RESUME)
+            }
+            _ => None,
+        }
+    }
+}
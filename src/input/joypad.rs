@@ -16,10 +16,29 @@ pub enum JoyPadKey {
     Invalid = 0b1111_1111,
 }
 
+const HORIZONTAL: u8 = 0b0000_0011;
+const VERTICAL: u8 = 0b0000_1100;
+
+/// How simultaneous opposing directions (left + right, up + down) are presented to the game
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SocdMode {
+    // Both directions are released
+    Neutral,
+    // The direction pressed last wins
+    LastWins,
+    // Both directions read as pressed, like a d-pad held precisely on hardware
+    #[default]
+    Allow,
+}
+
 pub struct JoyPad {
     pub interrupt_flags: Rc<RefCell<InterruptFlags>>,
     matrix: u8,
     select: u8,
+    socd_mode: SocdMode,
+    // The last pressed direction on each axis, used by the last wins mode
+    last_horizontal: u8,
+    last_vertical: u8,
 }
 
 impl JoyPad {
@@ -28,8 +47,40 @@ impl JoyPad {
             interrupt_flags,
             matrix: 0xff,
             select: 0x00,
+            socd_mode: SocdMode::default(),
+            last_horizontal: 0x00,
+            last_vertical: 0x00,
         }
     }
+
+    pub fn set_socd_mode(&mut self, socd_mode: SocdMode) {
+        self.socd_mode = socd_mode;
+    }
+
+    pub fn socd_mode(&self) -> SocdMode {
+        self.socd_mode
+    }
+
+    /// The key matrix after opposing directions are normalized with the socd mode
+    fn normalized_matrix(&self) -> u8 {
+        let mut pressed = !self.matrix;
+        for (axis, last) in [
+            (HORIZONTAL, self.last_horizontal),
+            (VERTICAL, self.last_vertical),
+        ]
+        .iter()
+        {
+            if pressed & axis != *axis {
+                continue;
+            }
+            match self.socd_mode {
+                SocdMode::Neutral => pressed &= !axis,
+                SocdMode::LastWins => pressed &= !(axis & !last),
+                SocdMode::Allow => {}
+            }
+        }
+        !pressed
+    }
 }
 
 impl JoyPad {
@@ -37,7 +88,14 @@ impl JoyPad {
         if key == JoyPadKey::Invalid {
             return;
         }
-        self.matrix &= !(key as u8);
+        let key = key as u8;
+        if key & HORIZONTAL != 0 {
+            self.last_horizontal = key;
+        }
+        if key & VERTICAL != 0 {
+            self.last_vertical = key;
+        }
+        self.matrix &= !key;
         self.interrupt_flags.borrow_mut().hi(Flag::Joypad);
     }
 
@@ -47,22 +105,43 @@ impl JoyPad {
         }
         self.matrix |= key as u8;
     }
+
+    /// Set every key at once, the low nibbles hold the keys as P1 reports them (0 is pressed):
+    /// Start, Select, B, A for the buttons and Down, Up, Left, Right for the directions
+    pub fn set_state(&mut self, buttons: u8, directions: u8) {
+        let matrix = ((buttons & 0x0f) << 4) | (directions & 0x0f);
+        // Like key_down, a newly pressed key raises the interrupt
+        if self.matrix & !matrix != 0 {
+            self.interrupt_flags.borrow_mut().hi(Flag::Joypad);
+        }
+        self.matrix = matrix;
+    }
+
+    /// The (buttons, directions) nibbles of every key, in the format of set_state
+    pub fn state(&self) -> (u8, u8) {
+        (self.matrix >> 4, self.matrix & 0x0f)
+    }
 }
 
 impl Memory for JoyPad {
+    // Bit 7-6 are unused and read as 1, the low nibble reads 1 for every released key on the
+    // selected lines, or 0x0f when no line is selected
     fn get(&self, a: u16) -> u8 {
         assert_eq!(a, 0xff00);
+        let matrix = self.normalized_matrix();
+        let mut keys = 0x0f;
         if (self.select & 0b0001_0000) == 0x00 {
-            return self.select | (self.matrix & 0x0f);
+            keys &= matrix & 0x0f;
         }
         if (self.select & 0b0010_0000) == 0x00 {
-            return self.select | (self.matrix >> 4);
+            keys &= matrix >> 4;
         }
-        self.select
+        0b1100_0000 | self.select | keys
     }
 
     fn set(&mut self, a: u16, v: u8) {
         assert_eq!(a, 0xff00);
-        self.select = v;
+        // Only the line select bits are writable
+        self.select = v & 0b0011_0000;
     }
 }
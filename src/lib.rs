@@ -2,6 +2,7 @@
 extern crate log;
 
 pub mod cartridge;
+pub mod config;
 pub mod cpu;
 pub mod debug;
 pub mod emulator;
@@ -9,6 +9,7 @@ extern crate piston_window;
 
 mod apu;
 mod cartridge;
+mod config;
 mod cpu;
 mod debug;
 mod display;
@@ -20,18 +21,32 @@ mod save;
 mod util;
 
 use apu::start_apu_thread;
+use cartridge::ips::apply_ips;
 use cartridge::load_cartridge;
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use debug::debug_logger::DebugLogger;
 #[cfg(feature = "debug")]
 use debug::start_debug_thread;
 use display::start_display_thread;
-use emulator::start_emulator_thread;
+use emulator::{start_emulator_thread, EmulatorChannels, EmulatorOptions};
 use input::start_io_thread;
 use simplelog::*;
 use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+use util::file::buffer_from_file;
+use util::present::{frame_pacing, PresentMode};
 use util::{get_boot_rom, get_rom};
 
+// The value of an option given on the command line
+fn parsed<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid {}: {}", name, value))
+    })
+}
+
 fn main() {
     /////////////////flume sender receivers////////////////////////
     let (framebuffer_sender, framebuffer_receiver) = flume::bounded(1);
@@ -79,6 +94,13 @@ fn main() {
                 .required(false)
                 .value_name("FILE"),
         )
+        .arg(
+            Arg::with_name("ips")
+                .long("ips")
+                .required(false)
+                .value_name("FILE")
+                .help("IPS patch applied to the rom before starting"),
+        )
         .arg(
             Arg::with_name("audio")
                 .long("audio")
@@ -87,33 +109,161 @@ fn main() {
                 .takes_value(false)
                 .help("Enable audio"),
         )
+        .arg(
+            Arg::with_name("pause-on-boot")
+                .long("pause-on-boot")
+                .required(false)
+                .takes_value(false)
+                .help("Start paused before the first instruction"),
+        )
+        .arg(
+            Arg::with_name("hide-debug")
+                .long("hide-debug")
+                .required(false)
+                .takes_value(false)
+                .help("Start with the debug windows hidden, F1 toggles them"),
+        )
+        .arg(
+            Arg::with_name("pause-on-unfocus")
+                .long("pause-on-unfocus")
+                .required(false)
+                .takes_value(false)
+                .help("Pause while the window is not focused"),
+        )
+        .arg(
+            Arg::with_name("fast-boot")
+                .long("fast-boot")
+                .required(false)
+                .takes_value(false)
+                .help("Run the boot rom at full speed"),
+        )
+        .arg(
+            Arg::with_name("unthrottled")
+                .long("unthrottled")
+                .required(false)
+                .takes_value(false)
+                .help("Run as fast as possible, also enabled by RGB_UNTHROTTLED=1"),
+        )
+        .arg(
+            Arg::with_name("speed")
+                .long("speed")
+                .takes_value(true)
+                .required(false)
+                .validator(|speed| match speed.parse::<f64>() {
+                    Ok(speed) if speed > 0.0 => Ok(()),
+                    _ => Err(String::from("the speed must be a positive number")),
+                })
+                .help("Emulation speed, 0.5 runs at half the Game Boy speed and 2.0 twice as fast (default 1.0)"),
+        )
+        .arg(
+            Arg::with_name("socd")
+                .long("socd")
+                .takes_value(true)
+                .possible_values(&["allow", "neutral", "last-wins"])
+                .required(false)
+                .help("How left + right and up + down held together are read, allow reads both as pressed"),
+        )
+        .arg(
+            Arg::with_name("smooth-palettes")
+                .long("smooth-palettes")
+                .required(false)
+                .takes_value(false)
+                .help("Blend palette changes over two frames to reduce flicker"),
+        )
+        .arg(
+            Arg::with_name("skip-idle-loops")
+                .long("skip-idle-loops")
+                .required(false)
+                .takes_value(false)
+                .help("Fast forward halt and jr loops waiting for an interrupt"),
+        )
         .arg(
             Arg::with_name("scale")
                 .short("s")
                 .long("scale")
                 .takes_value(true)
                 .required(false)
-                .default_value("2")
-                .help("UI scale factor"),
+                .help("UI scale factor (default 2)"),
+        )
+        .arg(
+            Arg::with_name("sync-to-display")
+                .long("sync-to-display")
+                .takes_value(true)
+                .value_name("HZ")
+                .required(false)
+                .help("Present frames on every refresh of a display at the given rate instead of the Game Boy rate"),
         )
         .get_matches();
 
+    // The options given on the command line take precedence over the config file
+    let flag = |name| {
+        if matches.is_present(name) {
+            Some(true)
+        } else {
+            None
+        }
+    };
+    let command_line = config::Config {
+        scale: parsed(&matches, "scale"),
+        pause_on_boot: flag("pause-on-boot"),
+        hide_debug: flag("hide-debug"),
+        pause_on_unfocus: flag("pause-on-unfocus"),
+        fast_boot: flag("fast-boot"),
+        unthrottled: flag("unthrottled"),
+        speed: parsed(&matches, "speed"),
+        socd: matches.value_of("socd").map(String::from),
+        skip_idle_loops: flag("skip-idle-loops"),
+        smooth_palettes: flag("smooth-palettes"),
+        sync_to_display: parsed(&matches, "sync-to-display"),
+    };
+    let file_config = config::Config::load_or_create(Path::new(config::CONFIG_PATH))
+        .unwrap_or_else(|e| panic!("Invalid config {}: {}", config::CONFIG_PATH, e));
+    let options = file_config.merge(command_line).options();
+
     let boot_rom = matches.value_of("boot").map(|path| get_boot_rom(path));
-    let rom = get_rom(matches.value_of("rom").unwrap());
+    let mut rom = matches.value_of("rom").map(|path| get_rom(path));
+    if let (Some(rom), Some(patch_path)) = (rom.as_mut(), matches.value_of("ips")) {
+        apply_ips(rom, &buffer_from_file(patch_path))
+            .unwrap_or_else(|e| panic!("Failed to apply {}: {}", patch_path, e));
+    }
+    let rom_name = match &rom {
+        Some(rom) => load_cartridge(rom.clone())
+            .unwrap_or_else(|error| panic!("{}", error))
+            .title(),
+        None => String::from("No ROM"),
+    };
 
     let emulator_thread = start_emulator_thread(
         boot_rom,
-        rom.clone(),
-        control_message_receiver.clone(),
-        input_message_receiver.clone(),
-        framebuffer_sender.clone(),
-        debug_message_sender.clone(),
-        tile_update_sender.clone(),
+        rom,
+        EmulatorOptions {
+            pause_on_boot: options.pause_on_boot,
+            fast_boot: options.fast_boot,
+            idle_loop_skip: options.skip_idle_loops,
+            unthrottled: options.unthrottled,
+            socd_mode: options.socd,
+            palette_smoothing: options.smooth_palettes,
+            speed: options.speed,
+        },
+        EmulatorChannels {
+            control_message_receiver: control_message_receiver.clone(),
+            input_message_receiver: input_message_receiver.clone(),
+            framebuffer_sender: framebuffer_sender.clone(),
+            debug_result_sender: debug_message_sender.clone(),
+            tile_update_sender: tile_update_sender.clone(),
+        },
     );
     let io_thread = start_io_thread(input_message_sender.clone());
+    let (present_mode, display_rate) = match options.sync_to_display {
+        Some(rate) => (PresentMode::SyncToDisplay, rate),
+        None => (PresentMode::default(), 0),
+    };
     let display_thread = start_display_thread(
-        matches.value_of("scale").unwrap().parse::<u32>().unwrap(),
-        load_cartridge(rom.clone()).title(),
+        options.scale,
+        rom_name,
+        !options.hide_debug,
+        frame_pacing(present_mode, display_rate),
+        options.pause_on_unfocus,
         control_message_sender.clone(),
         input_message_sender.clone(),
         framebuffer_receiver.clone(),
@@ -1,17 +1,28 @@
+pub mod cheat;
 pub mod mmu;
+pub mod serial;
 mod timer;
 
+/// The value read from unmapped addresses, nothing drives the data bus so it floats high
+pub const OPEN_BUS: u8 = 0xff;
+
 pub trait Memory {
     fn get(&self, address: u16) -> u8;
 
     fn set(&mut self, address: u16, value: u8);
 
+    /// Identifies the banks mapped at 0x0000 - 0x7fff, reads there return the same bytes while it
+    /// is unchanged. None when the reads can not be cached
+    fn rom_mapping(&self) -> Option<usize> {
+        None
+    }
+
     fn get_word(&self, address: u16) -> u16 {
-        u16::from(self.get(address)) | (u16::from(self.get(address + 1)) << 8)
+        u16::from(self.get(address)) | (u16::from(self.get(address.wrapping_add(1))) << 8)
     }
 
     fn set_word(&mut self, address: u16, value: u16) {
         self.set(address, (value & 0xFF) as u8);
-        self.set(address + 1, (value >> 8) as u8)
+        self.set(address.wrapping_add(1), (value >> 8) as u8)
     }
 }
@@ -0,0 +1,67 @@
+/// A Game Genie or GameShark code, enabled cheats replace the value read from their address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    // The code as it was entered
+    pub code: String,
+    pub address: u16,
+    pub value: u8,
+    // Game Genie codes with a compare value only patch the rom when it holds that value, so the
+    // code does not break other banks mapped at the same address
+    pub compare: Option<u8>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Decode a code, the dashes are optional
+    ///
+    /// Game Genie codes are ABC-DEF or ABC-DEF-GHI: AB is the value, FCDE is the address with F
+    /// inverted and GI is the compare value xored with 0xba and then rotated left by 2, H is
+    /// unused.
+    /// GameShark codes are TTVVLLHH: TT is the type, VV the value and HHLL the address.
+    pub fn parse(code: &str) -> Result<Cheat, String> {
+        let digits = code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|digit| digit as u8)
+                    .ok_or_else(|| format!("Invalid cheat code {}", code))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        let byte = |index: usize| digits[index] << 4 | digits[index + 1];
+        let (address, value, compare) = match digits.len() {
+            6 | 9 => {
+                let address = u16::from(digits[5] ^ 0x0f) << 12
+                    | u16::from(digits[2]) << 8
+                    | u16::from(digits[3]) << 4
+                    | u16::from(digits[4]);
+                let compare = if digits.len() == 9 {
+                    Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xba)
+                } else {
+                    None
+                };
+                (address, byte(0), compare)
+            }
+            8 => (u16::from(byte(6)) << 8 | u16::from(byte(4)), byte(2), None),
+            _ => return Err(format!("Invalid cheat code {}", code)),
+        };
+        Ok(Cheat {
+            code: code.to_string(),
+            address,
+            value,
+            compare,
+            enabled: true,
+        })
+    }
+
+    /// The value read from the address with the cheat applied
+    pub fn patch(&self, address: u16, value: u8) -> u8 {
+        if !self.enabled || address != self.address {
+            return value;
+        }
+        match self.compare {
+            Some(compare) if compare != value => value,
+            _ => self.value,
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use super::cheat::Cheat;
+use super::serial::Serial;
 use super::timer::Timer;
-use super::Memory;
+use super::{Memory, OPEN_BUS};
 use crate::cartridge::{load_cartridge, Cartridge};
 use crate::cpu::interrupt::InterruptFlags;
 use crate::input::joypad::JoyPad;
-use crate::ppu::PPU;
+use crate::ppu::{Mode, PPU};
 use crate::util::BOOT_ROM_SIZE;
 use std::cell::RefCell;
 use std::io;
@@ -11,6 +13,43 @@ use std::io::Write;
 use std::path::PathBuf;
 use std::rc::Rc;
 
+/// How the mmu handles an io register in 0xff00 - 0xff7f
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IoRegisterHandling {
+    // Reads and writes are backed by a real component
+    Implemented,
+    // The address is routed but only a placeholder value is read or written
+    Stubbed,
+    // There is no register at this address on the DMG
+    Unmapped,
+}
+
+// The bits of the sound registers 0xff10 - 0xff3f that always read as 1, the write-only bits and
+// the unused registers read as 1. The wave ram reads back as written
+const SOUND_REGISTER_READ_MASK: [u8; 0x30] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00, 0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff,
+    0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x70, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// The io register handling map, this needs to be updated when an io register is implemented
+fn io_register_handling(address: u16) -> IoRegisterHandling {
+    match address {
+        0xff00
+        | 0xff01..=0xff02
+        | 0xff04..=0xff07
+        | 0xff0f
+        | 0xff40..=0xff4b
+        | 0xff4f
+        | 0xff50
+        | 0xff68..=0xff6c
+        | 0xff70 => IoRegisterHandling::Implemented,
+        // APU
+        0xff10..=0xff3f => IoRegisterHandling::Stubbed,
+        _ => IoRegisterHandling::Unmapped,
+    }
+}
+
 pub struct MMU {
     pub boot_rom: Option<[u8; 256]>,
     pub cartridge: Box<dyn Cartridge>,
@@ -18,12 +57,24 @@ pub struct MMU {
     pub joypad: JoyPad,
     boot_rom_enabled: bool,
     timer: Timer,
-    last_serial: u8,
+    serial: Serial,
     work_ram: [u8; 0x8000],
     high_ram: [u8; 0x7f],
     work_ram_bank: usize,
     interrupt_flags: Rc<RefCell<InterruptFlags>>,
     interrupt_enabled: u8,
+    // The sound registers are only stored until the apu is emulated
+    sound_registers: [u8; 0x30],
+    // The last source written to the DMA register
+    dma: u8,
+    // When set, the cpu can not access VRAM and OAM while the ppu is reading them. Only the cpu
+    // side is gated, here in get and set, the ppu renders from its own memories
+    access_gating: bool,
+    // The enabled cheats, applied to every read
+    cheats: Vec<Cheat>,
+    // When set, the writes to the logo in the cartridge header are recorded. The rom ignores
+    // them, so they usually come from a bug or a mapper quirk
+    logo_writes: Option<Vec<(u16, u8)>>,
 }
 
 impl MMU {
@@ -40,7 +91,7 @@ impl MMU {
             boot_rom.copy_from_slice(&boot_rom_buffer);
             boot_rom
         });
-        let mut cartridge = load_cartridge(rom);
+        let mut cartridge = load_cartridge(rom).unwrap_or_else(|error| panic!("{}", error));
         cartridge.load(PathBuf::from(format!("{}.sav", cartridge.title())));
         // The interrupt flag is shared across each component in the gameboy, any component is able
         // to raise an interrupt
@@ -50,7 +101,7 @@ impl MMU {
             joypad: JoyPad::new(interrupt_flags.clone()),
             timer: Timer::new(interrupt_flags.clone()),
             ppu: RefCell::new(PPU::new(interrupt_flags.clone())),
-            last_serial: 0x00,
+            serial: Serial::new(interrupt_flags.clone()),
             interrupt_flags: interrupt_flags.clone(),
             boot_rom_enabled: boot_rom != None,
             cartridge,
@@ -58,14 +109,197 @@ impl MMU {
             work_ram: [0x00; 0x8000],
             work_ram_bank: 0x01,
             interrupt_enabled: 0x00,
+            sound_registers: [0x00; 0x30],
+            dma: 0x00,
+            access_gating: false,
+            cheats: Vec::new(),
+            logo_writes: None,
+        }
+    }
+    /// List how each io register is handled, this is used to track which registers still need
+    /// to be implemented
+    pub fn io_registers() -> Vec<(u16, IoRegisterHandling)> {
+        (0xff00..=0xff7f)
+            .map(|address| (address, io_register_handling(address)))
+            .collect()
+    }
+
+    /// Developer report of the io registers that are still stubbed
+    pub fn io_register_report() -> String {
+        let registers = MMU::io_registers();
+        let count = |handling| registers.iter().filter(|(_, h)| *h == handling).count();
+        let mut report = format!(
+            "IO registers: {} implemented, {} stubbed, {} unmapped\n",
+            count(IoRegisterHandling::Implemented),
+            count(IoRegisterHandling::Stubbed),
+            count(IoRegisterHandling::Unmapped)
+        );
+        for (address, _) in registers
+            .iter()
+            .filter(|(_, h)| *h == IoRegisterHandling::Stubbed)
+        {
+            report.push_str(&format!("stubbed: ${:04x}\n", address));
+        }
+        report
+    }
+
+    /// Replace the cheats applied to reads, disabled cheats are dropped
+    pub fn set_cheats(&mut self, cheats: &[Cheat]) {
+        self.cheats = cheats.iter().filter(|c| c.enabled).cloned().collect();
+    }
+
+    pub fn set_access_gating(&mut self, access_gating: bool) {
+        self.access_gating = access_gating;
+    }
+
+    pub fn access_gating(&self) -> bool {
+        self.access_gating
+    }
+
+    /// Warn about the writes to the Nintendo logo region (0x0104 - 0x0133) of the cartridge
+    /// header, they are still passed to the cartridge
+    pub fn set_logo_write_diagnostic(&mut self, enabled: bool) {
+        self.logo_writes = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn logo_write_diagnostic(&self) -> bool {
+        self.logo_writes.is_some()
+    }
+
+    /// The address and value of the writes to the logo region, empty when the diagnostic is
+    /// disabled
+    pub fn logo_writes(&self) -> &[(u16, u8)] {
+        match &self.logo_writes {
+            Some(logo_writes) => logo_writes,
+            None => &[],
         }
     }
+
+    // VRAM is not accessible by the cpu while the ppu is drawing (mode 3). With the lcd off
+    // nothing is drawn and both memories are always accessible, whatever the stored mode
+    fn vram_accessible(&self) -> bool {
+        let ppu = self.ppu.borrow();
+        !self.access_gating || !ppu.lcd_enabled() || ppu.mode != Mode::VRAMRead
+    }
+
+    // OAM is not accessible by the cpu while the ppu is searching and drawing (mode 2 and 3)
+    fn oam_accessible(&self) -> bool {
+        let ppu = self.ppu.borrow();
+        !self.access_gating
+            || !ppu.lcd_enabled()
+            || !matches!(ppu.mode, Mode::OAMRead | Mode::VRAMRead)
+    }
+
+    /// The work ram bank mapped at 0xd000 - 0xdfff
+    pub fn work_ram_bank(&self) -> usize {
+        self.work_ram_bank
+    }
+
+    /// Whether the boot rom is still mapped, it is unmapped when the boot rom hands off to the cartridge
+    pub fn boot_rom_enabled(&self) -> bool {
+        self.boot_rom_enabled && self.boot_rom.is_some()
+    }
+
+    /// When disabled, serial transfers never complete and no serial interrupt is requested
+    pub fn set_serial_transfers_enabled(&mut self, transfers_enabled: bool) {
+        self.serial.set_transfers_enabled(transfers_enabled);
+    }
+
+    pub fn serial_transfers_enabled(&self) -> bool {
+        self.serial.transfers_enabled()
+    }
+
     /// Update the MMU cycles, will tick the clock
     pub fn tick(&mut self, cycles: u32) {
         self.timer.tick(cycles);
+        self.serial.tick(cycles);
         self.ppu.borrow_mut().tick(cycles);
     }
 
+    /// Write bytes directly into the backing VRAM, WRAM, OAM or HRAM arrays, bypassing mode gating
+    /// and the mapper. This is a debug API for tests and tools setting up memory, the emulation
+    /// never uses it
+    pub fn load_region(&mut self, start: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address = start as usize + offset;
+            match address {
+                0x8000..=0x9fff => self.ppu.borrow_mut().video_ram[0][address - 0x8000] = *byte,
+                0xc000..=0xcfff => self.work_ram[address - 0xc000] = *byte,
+                0xd000..=0xdfff => {
+                    self.work_ram[address - 0xd000 + 0x1000 * self.work_ram_bank] = *byte
+                }
+                0xfe00..=0xfe9f => self.ppu.borrow_mut().oam[address - 0xfe00] = *byte,
+                0xff80..=0xfffe => self.high_ram[address - 0xff80] = *byte,
+                _ => panic!("Can not load region at address: ${:04x}", address),
+            }
+        }
+        self.ppu.borrow_mut().resync_caches();
+    }
+
+    /// Read a byte as it is stored, without the access gating and the cheats. Used by the
+    /// debugger, which should not see what the cpu would read
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.ppu.borrow().get(address),
+            _ => self.read(address),
+        }
+    }
+
+    /// Write a byte without the access gating, the counterpart of peek
+    pub fn poke(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.ppu.borrow_mut().set(address, value),
+            _ => self.set(address, value),
+        }
+    }
+
+    // The value the cpu reads before the cheats are applied
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            // Last instruction is at 0xfe and its two bytes, therefore excluding 0xff from rom addressing
+            0x0000...0x7fff => {
+                if self.boot_rom_enabled && self.boot_rom != None && address < 0x100 {
+                    self.boot_rom.unwrap()[address as usize]
+                } else {
+                    self.cartridge.get(address)
+                }
+            }
+            0x8000..=0x9fff if !self.vram_accessible() => OPEN_BUS,
+            0x8000..=0x9fff => self.ppu.borrow().get(address),
+            0xa000..=0xbfff => self.cartridge.get(address),
+            0xc000..=0xcfff => self.work_ram[address as usize - 0xc000],
+            0xd000..=0xdfff => {
+                self.work_ram[address as usize - 0xd000 + 0x1000 * self.work_ram_bank]
+            }
+            0xe000..=0xefff => self.work_ram[address as usize - 0xe000],
+            0xf000..=0xfdff => {
+                self.work_ram[address as usize - 0xf000 + 0x1000 * self.work_ram_bank]
+            }
+            0xfe00..=0xfe9f if !self.oam_accessible() => OPEN_BUS,
+            0xfe00..=0xfe9f => self.ppu.borrow().get(address),
+            0xfea0..=0xfeff => 0x00, // Invalid address
+            0xff00 => self.joypad.get(address),
+            0xff01..=0xff02 => self.serial.get(address),
+            0xff04..=0xff07 => {
+                // Clock
+                self.timer.get(address)
+            }
+            // The upper 3 bits are unused and read as 1
+            0xff0f => self.interrupt_flags.borrow_mut().data | 0xe0,
+            0xff10..=0xff3f => {
+                // APU
+                let index = address as usize - 0xff10;
+                self.sound_registers[index] | SOUND_REGISTER_READ_MASK[index]
+            }
+            0xff46 => self.dma,
+            0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f => self.ppu.borrow().get(address),
+            0xff68..=0xff6c => self.ppu.borrow().get(address),
+            0xff80..=0xfffe => self.high_ram[address as usize - 0xff80],
+            0xffff => self.interrupt_enabled,
+            _ => OPEN_BUS,
+        }
+    }
+
     /// DMA oam table to ppu, in order to have sprites on the screen, cartridge will often use DMA
     /// to copy oam table to ppu memory, we use the oam start address to set oam table from the source
     fn oam_dma(&mut self, source_address: u8) {
@@ -73,9 +307,12 @@ impl MMU {
         let source_base_address = (source_address as u16) << 8;
         const OAM_START_ADDRESS: u16 = 0xfe00;
 
+        // DMA writes OAM directly, it is not affected by the cpu access gating
         for index in 0x00..0xa0 {
             let source_byte = self.get(source_base_address + index);
-            self.set(OAM_START_ADDRESS + index, source_byte);
+            self.ppu
+                .borrow_mut()
+                .set(OAM_START_ADDRESS + index, source_byte);
         }
     }
 
@@ -117,52 +354,28 @@ impl MMU {
 
 impl Memory for MMU {
     fn get(&self, address: u16) -> u8 {
-        match address {
-            // Last instruction is at 0xfe and its two bytes, therefore excluding 0xff from rom addressing
-            0x0000...0x7fff => {
-                if self.boot_rom_enabled && self.boot_rom != None && address < 0x100 {
-                    self.boot_rom.unwrap()[address as usize]
-                } else {
-                    self.cartridge.get(address)
-                }
-            }
-            0x8000..=0x9fff => self.ppu.borrow().get(address),
-            0xa000..=0xbfff => self.cartridge.get(address),
-            0xc000..=0xcfff => self.work_ram[address as usize - 0xc000],
-            0xd000..=0xdfff => {
-                self.work_ram[address as usize - 0xd000 + 0x1000 * self.work_ram_bank]
-            }
-            0xe000..=0xefff => self.work_ram[address as usize - 0xe000],
-            0xf000..=0xfdff => {
-                self.work_ram[address as usize - 0xf000 + 0x1000 * self.work_ram_bank]
-            }
-            0xfe00..=0xfe9f => self.ppu.borrow().get(address),
-            0xfea0..=0xfeff => 0x00, // Invalid address
-            0xff00 => self.joypad.get(address),
-            0xff01..=0xff02 => {
-                // Serial
-                0
-            }
-            0xff04..=0xff07 => {
-                // Clock
-                self.timer.get(address)
-            }
-            0xff0f => self.interrupt_flags.borrow_mut().data,
-            0xff10..=0xff3f => {
-                // APU
-                0
-            }
-            0xf40...0xff4b => self.ppu.borrow().get(address),
-            0xff68..=0xff6b => self.ppu.borrow().get(address),
-            0xff80..=0xfffe => self.high_ram[address as usize - 0xff80],
-            0xffff => self.interrupt_enabled,
-            _ => 0x0000,
-        }
+        let value = self.read(address);
+        self.cheats
+            .iter()
+            .fold(value, |value, cheat| cheat.patch(address, value))
     }
 
     fn set(&mut self, address: u16, value: u8) {
         match address {
+            0x0104..=0x0133 if self.logo_writes.is_some() => {
+                warn!(
+                    "Write of ${:02x} to the logo region at ${:04x}, rom bank {}",
+                    value,
+                    address,
+                    self.cartridge.rom_bank()
+                );
+                if let Some(logo_writes) = self.logo_writes.as_mut() {
+                    logo_writes.push((address, value));
+                }
+                self.cartridge.set(address, value);
+            }
             0x0000..=0x7fff => self.cartridge.set(address, value),
+            0x8000..=0x9fff if !self.vram_accessible() => {}
             0x8000..=0x9fff => self.ppu.borrow_mut().set(address, value),
             0xa000..=0xbfff => self.cartridge.set(address, value),
             0xc000..=0xcfff => self.work_ram[address as usize - 0xc000] = value,
@@ -173,32 +386,31 @@ impl Memory for MMU {
             0xf000..=0xfdff => {
                 self.work_ram[address as usize - 0xf000 + 0x1000 * self.work_ram_bank] = value
             }
+            0xfe00..=0xfe9f if !self.oam_accessible() => {}
             0xfe00..=0xfe9f => self.ppu.borrow_mut().set(address, value),
             0xfea0..=0xfeff => {
                 // Not used
             }
             0xff00 => self.joypad.set(address, value),
-            0xff01..=0xff02 => {
-                // Serial
-                // if address == 0xff01 {
-                //     self.last_serial = value;
-                // }
-                // if address == 0xff02 {
-                //     print!("{}", self.last_serial as char);
-                //     input::stdout().flush();
-                // }
-            }
+            0xff01..=0xff02 => self.serial.set(address, value),
             0xff04..=0xff07 => self.timer.set(address, value),
             0xff0f => self.interrupt_flags.borrow_mut().data = value,
             0xff10..=0xff3f => {
-                // Sound
+                // Sound, only the power bit of NR52 is writable
+                self.sound_registers[address as usize - 0xff10] = match address {
+                    0xff26 => value & 0x80,
+                    _ => value,
+                };
+            }
+            0xff46 => {
+                self.dma = value;
+                self.oam_dma(value)
             }
-            0xff46 => self.oam_dma(value),
             0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f => self.ppu.borrow_mut().set(address, value),
             0xff50 => {
                 self.boot_rom_enabled = false;
             }
-            0xff68..=0xff6b => self.ppu.borrow_mut().set(address, value),
+            0xff68..=0xff6c => self.ppu.borrow_mut().set(address, value),
             0xff70 => {
                 self.work_ram_bank = match value & 0x7 {
                     0 => 1,
@@ -210,4 +422,13 @@ impl Memory for MMU {
             _ => {}
         }
     }
+
+    fn rom_mapping(&self) -> Option<usize> {
+        // The boot rom overlay and cheats change what is read from the rom area
+        if self.boot_rom_enabled() || !self.cheats.is_empty() {
+            None
+        } else {
+            self.cartridge.rom_mapping()
+        }
+    }
 }
@@ -0,0 +1,97 @@
+use crate::cpu::interrupt::{Flag, InterruptFlags};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// With the internal clock, bits are shifted at 8192Hz, which is 512 cpu cycles per bit. The CGB
+// fast clock shifts at 262144Hz, 16 cpu cycles per bit
+const CYCLES_PER_BIT: u32 = 512;
+const FAST_CYCLES_PER_BIT: u32 = 16;
+
+/// The cpu cycles a transfer of the 8 bits takes, None with the external clock since the transfer
+/// waits for the other side to drive the clock
+pub fn serial_transfer_cycles(internal: bool, double_speed: bool) -> Option<u32> {
+    match (internal, double_speed) {
+        (false, _) => None,
+        (true, false) => Some(CYCLES_PER_BIT * 8),
+        (true, true) => Some(FAST_CYCLES_PER_BIT * 8),
+    }
+}
+
+// The serial port without a link cable. A transfer started with the internal clock shifts out the
+// 8 bits of SB and shifts in 1s, since nothing is connected, then requests the serial interrupt.
+// Transfers with the external clock never complete.
+//
+// See: https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+pub struct Serial {
+    interrupt_flag: Rc<RefCell<InterruptFlags>>,
+    // FF01 - SB - Serial transfer data (R/W)
+    sb: u8,
+    // FF02 - SC - Serial Transfer Control (R/W)
+    // Bit 7 - Transfer Start Flag (0=No transfer is in progress or requested, 1=Transfer in progress, or requested)
+    // Bit 0 - Shift Clock (0=External Clock, 1=Internal Clock)
+    sc: u8,
+    // Cycles left until the running transfer completes
+    transfer_cycles: Option<u32>,
+    // When disabled, transfers are never completed, like the serial port was not emulated
+    transfers_enabled: bool,
+}
+
+impl Serial {
+    pub fn new(interrupt_flag: Rc<RefCell<InterruptFlags>>) -> Self {
+        Serial {
+            interrupt_flag,
+            sb: 0x00,
+            sc: 0x00,
+            transfer_cycles: None,
+            transfers_enabled: true,
+        }
+    }
+
+    pub fn set_transfers_enabled(&mut self, transfers_enabled: bool) {
+        self.transfers_enabled = transfers_enabled;
+    }
+
+    pub fn transfers_enabled(&self) -> bool {
+        self.transfers_enabled
+    }
+
+    pub fn get(&self, address: u16) -> u8 {
+        match address {
+            0xff01 => self.sb,
+            // Unused bits read as 1
+            0xff02 => self.sc | 0x7e,
+            _ => panic!("Unsupported address"),
+        }
+    }
+
+    pub fn set(&mut self, address: u16, value: u8) {
+        match address {
+            0xff01 => self.sb = value,
+            0xff02 => {
+                self.sc = value;
+                // The fast clock select in bit 1 only exists on CGB, which the serial port does not
+                // emulate
+                self.transfer_cycles = if self.transfers_enabled && value & 0x80 == 0x80 {
+                    serial_transfer_cycles(value & 0x01 == 0x01, false)
+                } else {
+                    None
+                };
+            }
+            _ => panic!("Unsupported address"),
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(remaining) = self.transfer_cycles {
+            if remaining > cycles {
+                self.transfer_cycles = Some(remaining - cycles);
+            } else {
+                // Nothing is connected, so only 1s are received
+                self.transfer_cycles = None;
+                self.sb = 0xff;
+                self.sc &= 0x7f;
+                self.interrupt_flag.borrow_mut().hi(Flag::Serial);
+            }
+        }
+    }
+}
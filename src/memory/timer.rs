@@ -82,7 +82,8 @@ impl Timer {
             0xff04 => self.register.div,
             0xff05 => self.register.tima,
             0xff06 => self.register.tma,
-            0xff07 => self.register.tac,
+            // The upper 5 bits are unused and read as 1
+            0xff07 => self.register.tac | 0xf8,
             _ => panic!("Unsupported address"),
         }
     }
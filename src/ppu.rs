@@ -1,14 +1,32 @@
+pub mod compat_palette;
+pub mod palette_smoothing;
+pub mod snapshot;
+pub mod tile_usage;
+
 use crate::cpu::interrupt::{Flag, InterruptFlags};
 use crate::memory::Memory;
+use crate::util::hash::Fnv1a;
+use compat_palette::CompatPalette;
+use palette_smoothing::PaletteSmoothing;
 use rand::Rng;
+use snapshot::{PPUSnapshot, PPU_REGISTERS};
 use std::cell::RefCell;
+use std::fmt;
+use std::hash::Hasher;
 use std::rc::Rc;
+use tile_usage::TileUsage;
 
 pub const FB_W: usize = 160;
 pub const FB_H: usize = 144;
 pub const VRAM_SIZE: usize = 0x2000;
+pub const VRAM_BANKS: usize = 2;
+pub const CGB_PALETTE_SIZE: usize = 0x40;
 pub const OAM_SIZE: usize = 0xa0;
 pub const TILE_MAP_SIZE: usize = 384;
+// The hardware only draws this many sprites on each scanline
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+// The first line after the lcd is enabled is 4 dots shorter than the others
+const LCD_ENABLE_DOT_OFFSET: u32 = 4;
 
 #[derive(Debug, PartialEq, Eq)]
 struct Color {
@@ -43,6 +61,67 @@ const BLACK: Color = Color {
     a: 255,
 };
 
+// Converts a 0xRRGGBB color
+fn rgb_color(value: u32) -> Color {
+    Color {
+        r: (value >> 16) as u8,
+        g: (value >> 8) as u8,
+        b: value as u8,
+        a: 255,
+    }
+}
+
+// The color halfway between two colors
+fn blend(a: &Color, b: &Color) -> Color {
+    Color {
+        r: ((u16::from(a.r) + u16::from(b.r)) / 2) as u8,
+        g: ((u16::from(a.g) + u16::from(b.g)) / 2) as u8,
+        b: ((u16::from(a.b) + u16::from(b.b)) / 2) as u8,
+        a: 255,
+    }
+}
+
+// Converts a CGB RGB555 color from the palette memory to a color
+fn cgb_color(palette_ram: &[u8; CGB_PALETTE_SIZE], palette: u8, color_id: u8) -> Color {
+    let index = (palette as usize * 4 + color_id as usize) * 2;
+    let value = u16::from(palette_ram[index]) | (u16::from(palette_ram[index + 1]) << 8);
+    // Scale the 5 bit channels to 8 bit
+    let scale = |channel: u16| ((channel << 3) | (channel >> 2)) as u8;
+    Color {
+        r: scale(value & 0x1f),
+        g: scale((value >> 5) & 0x1f),
+        b: scale((value >> 10) & 0x1f),
+        a: 255,
+    }
+}
+
+// CGB BG Map Attributes, stored in VRAM bank 1 at the same address as the tile number
+//  Bit 0-2  Background Palette number  (BGP0-7)
+//  Bit 3    Tile VRAM Bank number      (0=Bank 0, 1=Bank 1)
+//  Bit 5    Horizontal Flip            (0=Normal, 1=Mirror horizontally)
+//  Bit 6    Vertical Flip              (0=Normal, 1=Mirror vertically)
+//  Bit 7    BG-to-OAM Priority         (0=Use OAM priority bit, 1=BG Priority)
+#[derive(Clone, Copy, Debug, Default)]
+struct TileAttributes {
+    palette: u8,
+    bank: usize,
+    x_flip: bool,
+    y_flip: bool,
+    priority: bool,
+}
+
+impl TileAttributes {
+    fn from_u8(value: u8) -> TileAttributes {
+        TileAttributes {
+            palette: value & 0x07,
+            bank: ((value & 0x08) >> 3) as usize,
+            x_flip: value & 0x20 != 0,
+            y_flip: value & 0x40 != 0,
+            priority: value & 0x80 != 0,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Mode {
     // Mode 0
@@ -90,9 +169,10 @@ pub struct Sprite {
 
 impl Sprite {
     fn new() -> Sprite {
+        // Matches the decoded position of a zeroed OAM entry
         Sprite {
-            y_pos: 0x00,
-            x_pos: 0x00,
+            y_pos: -16,
+            x_pos: -8,
             tile: 0x00,
 
             priority_behind_bg: false,
@@ -103,18 +183,117 @@ impl Sprite {
             index: 0,
         }
     }
+
+    /// The index of the sprite in OAM
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// An OAM entry decoded for the sprite debugger, with the rectangle it covers on screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OamEntry {
+    pub index: usize,
+    // The top left corner on screen, OAM stores the position offset by 16 and 8
+    pub x: i32,
+    pub y: i32,
+    pub width: u8,
+    // 16 for 8x16 sprites
+    pub height: u8,
+    pub tile: u8,
+    // The VRAM bank of the tile, only used on CGB
+    pub bank: usize,
+    // OBP0 or OBP1 on DMG, one of the 8 object palettes on CGB
+    pub palette: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    // Only drawn over background color 0
+    pub behind_bg: bool,
+}
+
+impl OamEntry {
+    /// Whether any part of the sprite is on screen
+    pub fn on_screen(&self) -> bool {
+        self.x + i32::from(self.width) > 0
+            && self.x < FB_W as i32
+            && self.y + i32::from(self.height) > 0
+            && self.y < FB_H as i32
+    }
+}
+
+impl fmt::Display for OamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{:02} ({:4}, {:4}) {}x{} tile {:02x} bank {} palette {}{}{}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+            self.tile,
+            self.bank,
+            self.palette,
+            if self.x_flip { " x-flip" } else { "" },
+            if self.y_flip { " y-flip" } else { "" },
+            if self.behind_bg { " behind-bg" } else { "" }
+        )
+    }
+}
+
+/// How overlapping sprites are prioritized
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpritePriority {
+    // The sprite with the smaller x position wins, then the one that comes first in OAM
+    Dmg,
+    // The sprite that comes first in OAM wins
+    Cgb,
 }
 
 pub struct PPU {
     pub interrupt_flags: Rc<RefCell<InterruptFlags>>,
+    // The frame being drawn, lines are rendered into it as they complete
     pub framebuffer: PPUFramebuffer,
-    pub tile_set: [Tile; TILE_MAP_SIZE],
-    pub video_ram: [u8; VRAM_SIZE],
+    // The last complete frame, updated from the framebuffer at VBlank
+    front_buffer: PPUFramebuffer,
+    pub tile_set: [[Tile; TILE_MAP_SIZE]; VRAM_BANKS],
+    pub video_ram: [[u8; VRAM_SIZE]; VRAM_BANKS],
+    // The VRAM bank selected by VBK (0xff4f) for cpu access
+    vram_bank: usize,
     pub oam: [u8; OAM_SIZE],
     pub mode: Mode,
     sprites: [Sprite; 40],
 
+    // The number of sprites drawn on each line, None draws all of them
+    sprite_limit: Option<u8>,
+    // When set, the CGB tile attributes and color palettes are used for rendering
+    cgb_mode: bool,
+    sprite_priority: SpritePriority,
+    // Whether the low bit of the tile index of 8x16 sprites is ignored, like the hardware does
+    sprite_8x16_lsb_mask: bool,
+    // When set, tiles written to VRAM but never drawn are reported at the end of each frame
+    tile_usage: Option<TileUsage>,
+    // The colors used for the DMG palettes instead of the green shades when colorizing DMG games
+    compat_palette: Option<CompatPalette>,
+    // When set, DMG palette changes are blended over two frames
+    palette_smoothing: Option<PaletteSmoothing>,
+    // The BGP, OBP0 and OBP1 of the line being drawn in the previous frame, when smoothing
+    line_previous_palettes: Option<[u8; 3]>,
+    // Background and object color palette memory, accessed through BCPS/BCPD and OCPS/OCPD
+    bg_palette_ram: [u8; CGB_PALETTE_SIZE],
+    obj_palette_ram: [u8; CGB_PALETTE_SIZE],
+    // Palette index (bit 0-5) and auto increment (bit 7) of the palette memory
+    bcps: u8,
+    ocps: u8,
+    // The background color id and CGB priority of each pixel on the current line, used when
+    // drawing sprites
+    line_color_ids: [u8; FB_W],
+    line_priority: [bool; FB_W],
+
     bgp: u8, // Background sprite
+    // The BGP sampled at the start of mode 3, a write during the drawing only applies from the
+    // next line
+    line_bgp: u8,
 
     // This register assigns gray shades for sprite palette 0. It works exactly as BGP (FF47), except that the lower
     // two bits aren't used because sprite data 00 is transparent.
@@ -145,27 +324,52 @@ pub struct PPU {
     mode_1_interrupt_enabled: bool,
     mode_2_interrupt_enabled: bool,
     horiz_blanking: bool,
+    // Set from the lcd being enabled until the end of the first line, which has no OAM search
+    lcd_enabling: bool,
     tick_counter: u64,
 }
 
 impl PPU {
     pub fn new(interrupt_flags: Rc<RefCell<InterruptFlags>>) -> PPU {
+        let mut sprites = [Sprite::new(); 40];
+        for (index, sprite) in sprites.iter_mut().enumerate() {
+            sprite.index = index;
+        }
         Self {
             interrupt_flags,
-            video_ram: [0; VRAM_SIZE],
+            video_ram: [[0; VRAM_SIZE]; VRAM_BANKS],
             oam: [0; OAM_SIZE],
-            tile_set: [[[0x00; 8]; 8]; TILE_MAP_SIZE],
+            tile_set: [[[[0x00; 8]; 8]; TILE_MAP_SIZE]; VRAM_BANKS],
+            vram_bank: 0,
             framebuffer: [[[0x00; 3]; FB_W]; FB_H],
-            sprites: [Sprite::new(); 40],
+            front_buffer: [[[0x00; 3]; FB_W]; FB_H],
+            sprites,
+            sprite_limit: Some(MAX_SPRITES_PER_LINE as u8),
+            cgb_mode: false,
+            sprite_priority: SpritePriority::Dmg,
+            sprite_8x16_lsb_mask: true,
+            compat_palette: None,
+            palette_smoothing: None,
+            line_previous_palettes: None,
+            tile_usage: None,
+            bg_palette_ram: [0x00; CGB_PALETTE_SIZE],
+            obj_palette_ram: [0x00; CGB_PALETTE_SIZE],
+            bcps: 0x00,
+            ocps: 0x00,
+            line_color_ids: [0x00; FB_W],
+            line_priority: [false; FB_W],
             mode_clock: 0,
             ly: 0,
             scroll_x: 0,
             scroll_y: 0,
 
-            bgp: 0x00,
+            // The palettes the boot rom leaves behind, so the first frames look the same when it
+            // is skipped
+            bgp: 0xfc,
+            line_bgp: 0xfc,
 
-            op0: 0x00,
-            op1: 0x01,
+            op0: 0xff,
+            op1: 0xff,
             wx: 0x00,
             wy: 0x00,
 
@@ -185,10 +389,266 @@ impl PPU {
             mode_2_interrupt_enabled: false,
 
             horiz_blanking: false,
+            lcd_enabling: false,
             mode: Mode::HBlank,
             tick_counter: 0,
         }
     }
+    /// Set the number of sprites drawn on each line, the hardware draws 10. None removes the limit,
+    /// which reduces flicker in games that multiplex sprites
+    pub fn set_sprite_limit(&mut self, sprite_limit: Option<u8>) {
+        self.sprite_limit = sprite_limit;
+    }
+
+    pub fn sprite_limit(&self) -> Option<u8> {
+        self.sprite_limit
+    }
+
+    /// The background scroll as (SCX, SCY)
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scroll_x, self.scroll_y)
+    }
+
+    pub fn set_scroll(&mut self, scroll_x: u8, scroll_y: u8) {
+        self.scroll_x = scroll_x;
+        self.scroll_y = scroll_y;
+    }
+
+    /// The window position as the (WX, WY) register values, WX is offset by 7
+    pub fn window_position(&self) -> (u8, u8) {
+        (self.wx, self.wy)
+    }
+
+    pub fn set_window_position(&mut self, wx: u8, wy: u8) {
+        self.wx = wx;
+        self.wy = wy;
+    }
+
+    /// Whether the lcd is on (LCDC bit 7), the ppu does not run while it is off
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc_display_enabled
+    }
+
+    /// The dot of the current line, from 0 to 455
+    pub fn dot(&self) -> u32 {
+        self.mode_clock
+    }
+
+    /// The VRAM bank selected for cpu access
+    pub fn vram_bank(&self) -> usize {
+        self.vram_bank
+    }
+
+    /// The last complete frame, it never contains a partially drawn frame
+    pub fn front_buffer(&self) -> &PPUFramebuffer {
+        &self.front_buffer
+    }
+
+    /// Publish the framebuffer as the complete frame, this happens at every VBlank
+    pub fn present_frame(&mut self) {
+        self.front_buffer = self.framebuffer;
+    }
+
+    /// A hash of the last complete frame that is stable across runs, for comparing frames in tests
+    pub fn framebuffer_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        for pixel in self.front_buffer.iter().flatten() {
+            hasher.write(pixel);
+        }
+        hasher.finish()
+    }
+
+    /// Also selects the sprite priority mode of the model
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+        // There is only one VRAM bank on DMG
+        if !cgb_mode {
+            self.vram_bank = 0;
+        }
+        self.sprite_priority = if cgb_mode {
+            SpritePriority::Cgb
+        } else {
+            SpritePriority::Dmg
+        };
+    }
+
+    pub fn set_sprite_priority_mode(&mut self, sprite_priority: SpritePriority) {
+        self.sprite_priority = sprite_priority;
+    }
+
+    pub fn sprite_priority_mode(&self) -> SpritePriority {
+        self.sprite_priority
+    }
+
+    /// Ignore the low bit of the tile index of 8x16 sprites, the hardware behavior. Disabling it
+    /// is for roms that were only tested on emulators using the index as it is
+    pub fn set_8x16_lsb_mask(&mut self, enabled: bool) {
+        self.sprite_8x16_lsb_mask = enabled;
+    }
+
+    /// Blend the DMG palette changes over two frames, reducing the flicker of games swapping
+    /// palettes every frame. Off by default as it does not look like the hardware
+    pub fn set_palette_smoothing(&mut self, enabled: bool) {
+        self.palette_smoothing = if enabled {
+            Some(PaletteSmoothing::new([self.bgp, self.op0, self.op1]))
+        } else {
+            None
+        };
+        self.line_previous_palettes = None;
+    }
+
+    pub fn palette_smoothing(&self) -> bool {
+        self.palette_smoothing.is_some()
+    }
+
+    pub fn sprite_8x16_lsb_mask(&self) -> bool {
+        self.sprite_8x16_lsb_mask
+    }
+
+    /// Enable the diagnostic reporting tiles that are written to VRAM but not drawn in the frame
+    pub fn set_tile_usage_diagnostic(&mut self, enabled: bool) {
+        self.tile_usage = if enabled {
+            Some(TileUsage::new())
+        } else {
+            None
+        };
+    }
+
+    pub fn tile_usage_diagnostic(&self) -> bool {
+        self.tile_usage.is_some()
+    }
+
+    /// The (bank, tile) pairs written to VRAM but not drawn during the last frame, empty when the
+    /// diagnostic is disabled
+    pub fn unused_tiles(&self) -> Vec<(usize, usize)> {
+        match &self.tile_usage {
+            Some(tile_usage) => tile_usage.unused().to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    // Record that the tile at the address is drawn, for the tile usage diagnostic
+    fn reference_tile(&mut self, bank: usize, address: u16) {
+        if let Some(tile_usage) = self.tile_usage.as_mut() {
+            tile_usage.reference(bank, address);
+        }
+    }
+
+    pub fn set_compat_palette(&mut self, compat_palette: Option<CompatPalette>) {
+        self.compat_palette = compat_palette;
+    }
+
+    pub fn compat_palette(&self) -> Option<CompatPalette> {
+        self.compat_palette
+    }
+
+    /// Snapshot the registers, VRAM, OAM and palettes
+    pub fn capture(&self) -> PPUSnapshot {
+        let mut registers = [0x00; PPU_REGISTERS];
+        for (index, register) in registers.iter_mut().enumerate() {
+            let address = 0xff40 + index as u16;
+            if address != 0xff46 {
+                *register = self.get(address);
+            }
+        }
+        PPUSnapshot {
+            video_ram: self.video_ram,
+            oam: self.oam,
+            bg_palette_ram: self.bg_palette_ram,
+            obj_palette_ram: self.obj_palette_ram,
+            registers,
+            vram_bank: self.vram_bank as u8,
+            bcps: self.bcps,
+            ocps: self.ocps,
+            cgb_mode: self.cgb_mode,
+            mode_clock: self.mode_clock,
+        }
+    }
+
+    /// Restore a snapshot taken with capture, the decoded tiles and sprites are rebuilt from it
+    pub fn restore(&mut self, snapshot: &PPUSnapshot) {
+        self.video_ram = snapshot.video_ram;
+        self.oam = snapshot.oam;
+        self.bg_palette_ram = snapshot.bg_palette_ram;
+        self.obj_palette_ram = snapshot.obj_palette_ram;
+        self.bcps = snapshot.bcps;
+        self.ocps = snapshot.ocps;
+        self.set_cgb_mode(snapshot.cgb_mode);
+        self.resync_caches();
+        self.vram_bank = snapshot.vram_bank as usize;
+
+        // Turning the lcd off first keeps the LCDC write from running the lcd off or on sequence
+        self.lcdc_display_enabled = false;
+        for (index, value) in snapshot.registers.iter().enumerate() {
+            let address = 0xff40 + index as u16;
+            if address != 0xff46 && address != 0xff44 {
+                self.set(address, *value);
+            }
+        }
+        self.ly = snapshot.registers[0x04];
+        self.mode = match snapshot.registers[0x01] & 0x03 {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OAMRead,
+            _ => Mode::VRAMRead,
+        };
+        self.mode_clock = snapshot.mode_clock;
+        self.line_bgp = self.bgp;
+        self.lcd_enabling = false;
+    }
+
+    /// Rebuild the decoded tiles from VRAM and the sprites from OAM, needed after the memories
+    /// are written directly instead of through set
+    pub fn resync_caches(&mut self) {
+        let vram_bank = self.vram_bank;
+        for bank in 0..VRAM_BANKS {
+            self.vram_bank = bank;
+            for address in (0x8000..0x9800).step_by(2) {
+                self.update_tile(address, self.vram(bank, address));
+            }
+        }
+        self.vram_bank = vram_bank;
+        for address in 0..OAM_SIZE {
+            self.update_sprite_object(address, self.oam[address]);
+        }
+    }
+
+    /// The last complete frame as an array of shape [FB_H, FB_W, 3], rows first
+    #[cfg(feature = "ndarray")]
+    pub fn framebuffer_ndarray(&self) -> ndarray::Array3<u8> {
+        let front_buffer = self.front_buffer();
+        ndarray::Array3::from_shape_fn((FB_H, FB_W, 3), |(y, x, channel)| {
+            front_buffer[y][x][channel]
+        })
+    }
+
+    /// Encode the last complete frame as a binary (P6) PPM image
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", FB_W, FB_H).into_bytes();
+        ppm.reserve(FB_W * FB_H * 3);
+        for row in self.front_buffer().iter() {
+            for pixel in row.iter() {
+                ppm.extend_from_slice(pixel);
+            }
+        }
+        ppm
+    }
+
+    /// The raw VRAM of the bank, empty for bank 1 on DMG and for banks that do not exist
+    pub fn vram_bytes(&self, bank: u8) -> &[u8] {
+        match bank {
+            0 => &self.video_ram[0],
+            1 if self.cgb_mode => &self.video_ram[1],
+            _ => &[],
+        }
+    }
+
+    // Read a byte from the given VRAM bank. Rendering reads the memories directly, the mode 3
+    // gating of cpu accesses is done by the MMU and never applies here
+    fn vram(&self, bank: usize, address: u16) -> u8 {
+        self.video_ram[bank][address as usize - 0x8000]
+    }
+
     pub fn update_tile(&mut self, address: u16, value: u8) {
         // Get the "base address" for this tile row
         let base_address = address & 0x1ffe;
@@ -198,6 +658,9 @@ impl PPU {
             trace!("VRAM:TILE {:#x}", value);
         }
 
+        // Tiles are decoded in the bank the cpu is writing to
+        let bank = self.vram_bank;
+
         // Work out which tile and row was updated
         let tile = (base_address >> 4) & 511;
         let y = (base_address >> 1) & 7;
@@ -207,17 +670,17 @@ impl PPU {
             let sx = 1 << (7 - x);
 
             // Update tile set
-            let pixel_colour = if self.video_ram[base_address as usize] & sx != 0 {
+            let pixel_colour = if self.video_ram[bank][base_address as usize] & sx != 0 {
                 1
             } else {
                 0
-            } + if self.video_ram[(base_address + 1) as usize] & sx != 0 {
+            } + if self.video_ram[bank][(base_address + 1) as usize] & sx != 0 {
                 2
             } else {
                 0
             };
 
-            self.tile_set[tile as usize][y as usize][x as usize] = pixel_colour;
+            self.tile_set[bank][tile as usize][y as usize][x as usize] = pixel_colour;
         }
     }
     pub fn tick(&mut self, cycles: u32) {
@@ -241,6 +704,7 @@ impl PPU {
 
             if self.mode_clock >= 456 {
                 self.mode_clock -= 456;
+                self.lcd_enabling = false;
                 self.ly = (self.ly + 1) % 154;
                 if self.ly_coincidence_interrupt_enabled && self.ly == self.ly_coincidence {
                     self.interrupt_flags.borrow_mut().hi(Flag::LCDStat);
@@ -253,7 +717,8 @@ impl PPU {
 
             if self.ly < 144 {
                 if self.mode_clock <= 80 {
-                    if self.mode != Mode::OAMRead {
+                    // The first line after enabling the lcd stays in mode 0 instead of searching OAM
+                    if self.mode != Mode::OAMRead && !self.lcd_enabling {
                         self.change_mode(Mode::OAMRead);
                     }
                 } else if self.mode_clock <= (80 + 172) {
@@ -320,9 +785,9 @@ impl PPU {
             let tile_address = background_mem + tile_row + tile_col;
 
             let tile_num: i16 = if unsigned {
-                self.get(tile_address) as u16 as i16
+                self.vram(0, tile_address) as u16 as i16
             } else {
-                self.get(tile_address) as i8 as i16
+                self.vram(0, tile_address) as i8 as i16
             };
 
             let tile_location: u16 = if unsigned {
@@ -331,27 +796,165 @@ impl PPU {
                 tile_data + ((tile_num + 128) * 16) as u16
             };
 
-            let line = (y_pos as u16 % 8) * 2;
-            let data1 = self.get(tile_location + line);
-            let data2 = self.get(tile_location + line + 1);
+            // The tile attributes only exist on CGB, DMG tiles always use the default attributes
+            let attributes = if self.cgb_mode {
+                TileAttributes::from_u8(self.vram(1, tile_address))
+            } else {
+                TileAttributes::default()
+            };
+
+            let line = y_pos as u16 % 8;
+            let line = if attributes.y_flip { 7 - line } else { line } * 2;
+            self.reference_tile(attributes.bank, tile_location);
+            let data1 = self.vram(attributes.bank, tile_location + line);
+            let data2 = self.vram(attributes.bank, tile_location + line + 1);
 
-            let color_bit = ((x_pos as i32 % 8) - 7) * -1;
+            let color_bit = if attributes.x_flip {
+                x_pos % 8
+            } else {
+                7 - x_pos % 8
+            };
 
             let color_num = ((data2 >> color_bit) & 0b1) << 1;
             let color_num = color_num | ((data1 >> color_bit) & 0b1);
 
-            let color = self.get_color(color_num, self.bgp);
+            let color = if self.cgb_mode {
+                cgb_color(&self.bg_palette_ram, attributes.palette, color_num)
+            } else {
+                self.dmg_color(color_num, 0)
+            };
 
+            self.line_color_ids[pixel as usize] = color_num;
+            self.line_priority[pixel as usize] = attributes.priority;
             self.framebuffer[scanline as usize][pixel as usize] = [color.r, color.g, color.b];
         }
     }
 
+    /// Compose the 32x32 tiles of the selected background tile map into a 256x256 image, ignoring
+    /// the scroll
+    #[cfg(feature = "image")]
+    pub fn render_full_background(&self) -> image::RgbImage {
+        self.render_full_tilemap(if self.lcdc_bg_tilemap_base {
+            0x9c00
+        } else {
+            0x9800
+        })
+    }
+
+    /// Compose the 32x32 tiles of the selected window tile map into a 256x256 image
+    #[cfg(feature = "image")]
+    pub fn render_full_window(&self) -> image::RgbImage {
+        self.render_full_tilemap(if self.lcdc_window_tilemap {
+            0x9c00
+        } else {
+            0x9800
+        })
+    }
+
+    #[cfg(feature = "image")]
+    fn render_full_tilemap(&self, tilemap: u16) -> image::RgbImage {
+        let mut image = image::RgbImage::new(256, 256);
+        for tile_row in 0..32u16 {
+            for tile_col in 0..32u16 {
+                let tile_address = tilemap + tile_row * 32 + tile_col;
+                let tile_location = if self.lcdc_bg_and_window_tile_base {
+                    0x8000 + u16::from(self.vram(0, tile_address)) * 16
+                } else {
+                    (0x9000 + i32::from(self.vram(0, tile_address) as i8) * 16) as u16
+                };
+                let attributes = if self.cgb_mode {
+                    TileAttributes::from_u8(self.vram(1, tile_address))
+                } else {
+                    TileAttributes::default()
+                };
+                for y in 0..8u16 {
+                    let line = if attributes.y_flip { 7 - y } else { y } * 2;
+                    let data1 = self.vram(attributes.bank, tile_location + line);
+                    let data2 = self.vram(attributes.bank, tile_location + line + 1);
+                    for x in 0..8u8 {
+                        let color_bit = if attributes.x_flip { x } else { 7 - x };
+                        let color_num = ((data2 >> color_bit) & 0b1) << 1;
+                        let color_num = color_num | ((data1 >> color_bit) & 0b1);
+                        let color = if self.cgb_mode {
+                            cgb_color(&self.bg_palette_ram, attributes.palette, color_num)
+                        } else {
+                            self.get_color(color_num, self.bgp, self.compat_palette.map(|p| p.bg))
+                        };
+                        image.put_pixel(
+                            u32::from(tile_col) * 8 + u32::from(x),
+                            u32::from(tile_row * 8 + y),
+                            image::Rgb([color.r, color.g, color.b]),
+                        );
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// The sprites drawn on the given line, the first 10 sprites (or the sprite limit) in OAM
+    /// that intersect with the line ordered by priority. With the DMG priority sprites with a smaller x position have higher
+    /// priority, then the ones that come first in OAM. With the CGB priority only the OAM order counts
+    pub fn sprites_on_line(&self, ly: u8) -> Vec<Sprite> {
+        let height = if self.lcdc_obj_sprite_size { 16 } else { 8 };
+        let line = i32::from(ly);
+        let mut sprites: Vec<Sprite> = self
+            .sprites
+            .iter()
+            .filter(|sprite| line >= sprite.y_pos && line < sprite.y_pos + height)
+            .take(match self.sprite_limit {
+                Some(sprite_limit) => sprite_limit as usize,
+                None => self.sprites.len(),
+            })
+            .cloned()
+            .collect();
+        if self.sprite_priority == SpritePriority::Dmg {
+            sprites.sort_by_key(|sprite| (sprite.x_pos, sprite.index));
+        }
+        sprites
+    }
+
+    /// Every OAM entry decoded with the current sprite size, in OAM order
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        let height = if self.lcdc_obj_sprite_size { 16 } else { 8 };
+        self.oam
+            .chunks(4)
+            .enumerate()
+            .map(|(index, entry)| {
+                let attributes = entry[3];
+                OamEntry {
+                    index,
+                    x: i32::from(entry[1]) - 8,
+                    y: i32::from(entry[0]) - 16,
+                    width: 8,
+                    height,
+                    tile: entry[2],
+                    bank: usize::from(attributes & 0x08 != 0),
+                    palette: if self.cgb_mode {
+                        attributes & 0x07
+                    } else {
+                        (attributes & 0x10) >> 4
+                    },
+                    x_flip: attributes & 0x20 != 0,
+                    y_flip: attributes & 0x40 != 0,
+                    behind_bg: attributes & 0x80 != 0,
+                }
+            })
+            .collect()
+    }
+
     fn render_sprites(&mut self) {
         let use_8x16 = self.lcdc_obj_sprite_size;
-        for sprite in self.sprites.iter() {
+        // Draw the lowest priority first so the higher priority sprites end up on top
+        for sprite in self.sprites_on_line(self.ly).iter().rev() {
             let y_pos = sprite.y_pos as u8;
             let x_pos = sprite.x_pos as u8;
-            let tile_location = sprite.tile as u16;
+            // The 8x16 sprites are made of an even tile and the one after it
+            let tile_location = if use_8x16 && self.sprite_8x16_lsb_mask {
+                (sprite.tile & 0xfe) as u16
+            } else {
+                sprite.tile as u16
+            };
             let y_flip = sprite.y_flip;
             let x_flip = sprite.x_flip;
             let scanline = self.ly;
@@ -371,8 +974,9 @@ impl PPU {
 
                 let data_address = 0x8000 + (tile_location * 16) + line as u16;
 
-                let data1 = self.get(data_address);
-                let data2 = self.get(data_address + 1);
+                self.reference_tile(0, data_address);
+                let data1 = self.vram(0, data_address);
+                let data2 = self.vram(0, data_address + 1);
 
                 for tile_pixel in (0..8).rev() {
                     let color_bit = tile_pixel as i32;
@@ -385,16 +989,10 @@ impl PPU {
                     let color_num = ((data2 >> color_bit) & 0b1) << 1;
                     let color_num = color_num | ((data1 >> color_bit) & 0b1);
 
-                    let palette_num = if sprite.use_palette_1 {
-                        self.op1
-                    } else {
-                        self.op0
-                    };
-
                     if color_num == 0 {
                         continue;
                     }
-                    let color = self.get_color(color_num, palette_num);
+                    let color = self.dmg_color(color_num, if sprite.use_palette_1 { 2 } else { 1 });
 
                     let x_pix = (0 as u8).wrapping_sub(tile_pixel as u8);
                     let x_pix = x_pix.wrapping_add(7);
@@ -405,12 +1003,25 @@ impl PPU {
                         continue;
                     }
 
-                    if self.framebuffer[scanline as usize][pixel as usize]
-                        != [WHITE.r, WHITE.g, WHITE.b]
+                    // On CGB a clear LCDC bit 0 takes the priority away from the background, the
+                    // sprites are drawn over it whatever the tile and sprite priority bits
+                    let bg_priority = !self.cgb_mode || self.lcdc_bg_enabled;
+
+                    // CGB background tiles with the priority bit are drawn over the sprites
+                    if bg_priority
+                        && self.cgb_mode
+                        && self.line_priority[pixel as usize]
+                        && self.line_color_ids[pixel as usize] != 0
+                    {
+                        continue;
+                    }
+
+                    // Sprites behind the background are only visible over background color 0
+                    if bg_priority
+                        && sprite.priority_behind_bg
+                        && self.line_color_ids[pixel as usize] != 0
                     {
-                        if sprite.priority_behind_bg {
-                            continue;
-                        }
+                        continue;
                     }
 
                     self.framebuffer[scanline as usize][pixel as usize] =
@@ -420,7 +1031,25 @@ impl PPU {
         }
     }
 
-    fn get_color(&self, color_id: u8, palette_num: u8) -> Color {
+    // The color of a DMG palette (0 for BGP, 1 for OBP0 and 2 for OBP1) on the line being drawn,
+    // blended with the palette of the line in the previous frame when smoothing is enabled
+    fn dmg_color(&self, color_id: u8, palette: usize) -> Color {
+        let (palette_num, compat_colors) = match palette {
+            0 => (self.line_bgp, self.compat_palette.map(|p| p.bg)),
+            1 => (self.op0, self.compat_palette.map(|p| p.obj0)),
+            _ => (self.op1, self.compat_palette.map(|p| p.obj1)),
+        };
+        let color = self.get_color(color_id, palette_num, compat_colors);
+        match self.line_previous_palettes {
+            Some(previous) if previous[palette] != palette_num => blend(
+                &color,
+                &self.get_color(color_id, previous[palette], compat_colors),
+            ),
+            _ => color,
+        }
+    }
+
+    fn get_color(&self, color_id: u8, palette_num: u8, compat_colors: Option<[u32; 4]>) -> Color {
         let (hi, lo) = match color_id {
             0 => (1, 0),
             1 => (3, 2),
@@ -432,6 +1061,9 @@ impl PPU {
         let color = ((palette_num >> hi) & 0b1) << 1;
         let color = color | ((palette_num >> lo) & 0b1);
 
+        if let Some(colors) = compat_colors {
+            return rgb_color(colors[color as usize]);
+        }
         match color {
             0 => WHITE,
             1 => LIGHT_GRAY,
@@ -459,8 +1091,25 @@ impl PPU {
         }
     }
 
+    /// Render one scanline into the framebuffer with the current register state, regardless of
+    /// the ppu mode and timing
+    pub fn render_line(&mut self, ly: u8) {
+        assert!((ly as usize) < FB_H, "Line {} is outside of the screen", ly);
+        let current_ly = self.ly;
+        self.ly = ly;
+        self.line_bgp = self.bgp;
+        self.render_scanline();
+        self.ly = current_ly;
+    }
+
     fn render_scanline(&mut self) {
         trace!("Rendering scanline, {:?}", self.mode);
+        let palettes = [self.line_bgp, self.op0, self.op1];
+        let ly = self.ly;
+        self.line_previous_palettes = self
+            .palette_smoothing
+            .as_mut()
+            .map(|smoothing| smoothing.line(ly, palettes));
         self.render_background();
         self.render_sprites();
     }
@@ -475,10 +1124,27 @@ impl PPU {
             }
             Mode::VBlank => {
                 self.interrupt_flags.borrow_mut().hi(Flag::VBlank);
+                self.present_frame();
+                if let Some(smoothing) = self.palette_smoothing.as_mut() {
+                    smoothing.end_frame();
+                }
+                if let Some(tile_usage) = self.tile_usage.as_mut() {
+                    let unused = tile_usage.end_frame();
+                    if !unused.is_empty() {
+                        info!(
+                            "{} tiles were written to VRAM but not drawn: {:?}",
+                            unused.len(),
+                            unused
+                        );
+                    }
+                }
                 self.mode_1_interrupt_enabled
             }
             Mode::OAMRead => self.mode_2_interrupt_enabled,
-            Mode::VRAMRead => false,
+            Mode::VRAMRead => {
+                self.line_bgp = self.bgp;
+                false
+            }
         } {
             self.interrupt_flags.borrow_mut().hi(Flag::LCDStat);
         }
@@ -488,7 +1154,7 @@ impl PPU {
 impl Memory for PPU {
     fn get(&self, address: u16) -> u8 {
         match address {
-            0x8000..=0x9fff => self.video_ram[address as usize - 0x8000],
+            0x8000..=0x9fff => self.vram(self.vram_bank, address),
             0xfe00...0xfe9f => self.oam[address as usize - 0xfe00],
             0xff40 => {
                 (if self.lcdc_display_enabled {
@@ -542,7 +1208,9 @@ impl Memory for PPU {
                     0x04
                 } else {
                     0
-                }) | self.mode as u8;
+                }) | self.mode as u8
+                    // Bit 7 is unused and reads as 1
+                    | 0x80;
 
                 ff41_val
             }
@@ -555,6 +1223,17 @@ impl Memory for PPU {
             0xff49 => self.op1,
             0xff4a => self.wy,
             0xff4b => self.wx,
+            // Only bit 0 is used, the rest reads as 1
+            // VBK only exists on CGB, like OPRI
+            0xff4f if self.cgb_mode => 0xfe | self.vram_bank as u8,
+            0xff4f => 0xff,
+            0xff68 => self.bcps,
+            0xff69 => self.bg_palette_ram[(self.bcps & 0x3f) as usize],
+            0xff6a => self.ocps,
+            0xff6b => self.obj_palette_ram[(self.ocps & 0x3f) as usize],
+            // OPRI, bit 0 selects the DMG priority by x position, it only exists on CGB
+            0xff6c if self.cgb_mode => 0xfe | u8::from(self.sprite_priority == SpritePriority::Dmg),
+            0xff6c => 0xff,
             _ => panic!("Read not implemented for address: ${:04x}", address),
         }
     }
@@ -562,11 +1241,15 @@ impl Memory for PPU {
     fn set(&mut self, address: u16, value: u8) {
         match address {
             0x8000..=0x9fff => {
-                self.video_ram[address as usize - 0x8000] = value;
+                self.video_ram[self.vram_bank][address as usize - 0x8000] = value;
 
                 // Tile Data is stored in VRAM at addresses 8000h-97FFh, this area defines the Bitmaps for 192 Tiles.
                 if address <= 0x97ff {
                     self.update_tile(address, value);
+                    let bank = self.vram_bank;
+                    if let Some(tile_usage) = self.tile_usage.as_mut() {
+                        tile_usage.write(bank, address);
+                    }
                 }
             }
             0xfe00..=0xfe9f => {
@@ -592,6 +1275,13 @@ impl Memory for PPU {
                     self.ly = 0;
                     self.change_mode(Mode::HBlank);
                 }
+                if !previous_lcdc_display_enabled && self.lcdc_display_enabled {
+                    // The lcd restarts at line 0 in mode 0, with the first line cut short
+                    self.mode_clock = LCD_ENABLE_DOT_OFFSET;
+                    self.ly = 0;
+                    self.mode = Mode::HBlank;
+                    self.lcd_enabling = true;
+                }
             }
             0xff41 => {
                 self.ly_coincidence_interrupt_enabled = value & 0x40 == 0x40;
@@ -616,11 +1306,52 @@ impl Memory for PPU {
                 self.wy = value;
             }
             0xff4b => self.wx = value,
+            0xff4f if self.cgb_mode => self.vram_bank = (value & 0x01) as usize,
+            0xff4f => {}
+            0xff68 => self.bcps = value,
+            0xff69 => {
+                self.bg_palette_ram[(self.bcps & 0x3f) as usize] = value;
+                // Bit 7 of the specification register increments the index after each write
+                if self.bcps & 0x80 != 0 {
+                    self.bcps = 0x80 | (self.bcps.wrapping_add(1) & 0x3f);
+                }
+            }
+            0xff6a => self.ocps = value,
+            0xff6b => {
+                self.obj_palette_ram[(self.ocps & 0x3f) as usize] = value;
+                if self.ocps & 0x80 != 0 {
+                    self.ocps = 0x80 | (self.ocps.wrapping_add(1) & 0x3f);
+                }
+            }
+            0xff6c if self.cgb_mode => {
+                self.sprite_priority = if value & 0x01 == 0x01 {
+                    SpritePriority::Dmg
+                } else {
+                    SpritePriority::Cgb
+                };
+            }
+            0xff6c => {}
             _ => panic!("Write not implemented for address: ${:04x}", address),
         }
     }
 }
 
+/// The test pattern shown while no rom is loaded, a checkerboard of 8x8 tiles
+pub fn no_rom_framebuffer() -> PPUFramebuffer {
+    let mut framebuffer = [[[0x00; 3]; FB_W]; FB_H];
+    for (y, row) in framebuffer.iter_mut().enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let color = if (x / 8 + y / 8) % 2 == 0 {
+                WHITE
+            } else {
+                LIGHT_GRAY
+            };
+            *pixel = [color.r, color.g, color.b];
+        }
+    }
+    framebuffer
+}
+
 pub fn random_framebuffer() -> PPUFramebuffer {
     let mut framebuffer = [[[0x00; 3]; FB_W]; FB_H];
     let mut rng = rand::thread_rng();
@@ -0,0 +1,78 @@
+// When a DMG game runs on a CGB, the boot rom colorizes it with palettes picked from the title.
+// The colors are 0xRRGGBB for the four shades, from lightest to darkest.
+//
+// See: https://tcrf.net/Notes:Game_Boy_Color_Bootstrap_ROM
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CompatPalette {
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+const WHITE: u32 = 0xffffff;
+const BLACK: u32 = 0x000000;
+
+const RED: [u32; 4] = [WHITE, 0xff8484, 0x943a3a, BLACK];
+const GREEN: [u32; 4] = [WHITE, 0x7bff31, 0x008400, BLACK];
+const BLUE: [u32; 4] = [WHITE, 0x63a5ff, 0x0000ff, BLACK];
+const YELLOW: [u32; 4] = [WHITE, 0xffff00, 0xff0000, BLACK];
+
+/// Used for games that are not in the table
+pub const DEFAULT_COMPAT_PALETTE: CompatPalette = CompatPalette {
+    bg: [WHITE, 0x7bff31, 0x0063c5, BLACK],
+    obj0: RED,
+    obj1: RED,
+};
+
+const COMPAT_PALETTES: [(&str, CompatPalette); 5] = [
+    (
+        "POKEMON RED",
+        CompatPalette {
+            bg: RED,
+            obj0: GREEN,
+            obj1: BLUE,
+        },
+    ),
+    (
+        "POKEMON BLUE",
+        CompatPalette {
+            bg: BLUE,
+            obj0: RED,
+            obj1: GREEN,
+        },
+    ),
+    (
+        "TETRIS",
+        CompatPalette {
+            bg: YELLOW,
+            obj0: YELLOW,
+            obj1: YELLOW,
+        },
+    ),
+    (
+        "ZELDA",
+        CompatPalette {
+            bg: [WHITE, 0xff8484, 0x943a3a, BLACK],
+            obj0: GREEN,
+            obj1: BLUE,
+        },
+    ),
+    (
+        "SUPER MARIOLAND",
+        CompatPalette {
+            bg: [WHITE, 0xadad84, 0x42737b, BLACK],
+            obj0: [WHITE, 0xff7300, 0x944200, BLACK],
+            obj1: [WHITE, 0x5abdff, 0xff0000, 0x0000ff],
+        },
+    ),
+];
+
+/// The colorization palette for the cartridge title, the default palette is used for unknown titles
+pub fn compat_palette(title: &str) -> CompatPalette {
+    COMPAT_PALETTES
+        .iter()
+        .find(|(known_title, _)| *known_title == title)
+        .map(|(_, palette)| *palette)
+        .unwrap_or(DEFAULT_COMPAT_PALETTE)
+}
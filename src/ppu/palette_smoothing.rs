@@ -0,0 +1,29 @@
+use super::FB_H;
+
+/// Remembers the DMG palettes (BGP, OBP0, OBP1) each line was drawn with in the previous frame,
+/// so palette changes can be blended over two frames to reduce the flicker of games swapping
+/// palettes every frame
+pub struct PaletteSmoothing {
+    previous: [[u8; 3]; FB_H],
+    current: [[u8; 3]; FB_H],
+}
+
+impl PaletteSmoothing {
+    /// Start with every line of the previous frame drawn with the given palettes
+    pub fn new(palettes: [u8; 3]) -> PaletteSmoothing {
+        Self {
+            previous: [palettes; FB_H],
+            current: [palettes; FB_H],
+        }
+    }
+
+    /// Record the palettes a line is drawn with, returns the ones of the previous frame
+    pub fn line(&mut self, ly: u8, palettes: [u8; 3]) -> [u8; 3] {
+        self.current[ly as usize] = palettes;
+        self.previous[ly as usize]
+    }
+
+    pub fn end_frame(&mut self) {
+        self.previous = self.current;
+    }
+}
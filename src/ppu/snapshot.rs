@@ -0,0 +1,88 @@
+use super::{CGB_PALETTE_SIZE, OAM_SIZE, VRAM_BANKS, VRAM_SIZE};
+
+// The lcd registers from LCDC (0xff40) to WX (0xff4b), 0xff46 is the dma register and unused
+pub const PPU_REGISTERS: usize = 0x0c;
+// The length of a snapshot as bytes, the memories, the registers, the vram bank, bcps, ocps,
+// the cgb mode and the mode clock
+pub const SNAPSHOT_SIZE: usize =
+    VRAM_SIZE * VRAM_BANKS + OAM_SIZE + CGB_PALETTE_SIZE * 2 + PPU_REGISTERS + 4 + 4;
+
+/// The registers and memories of the PPU, everything needed to render the same frame again
+#[derive(Clone, Debug, PartialEq)]
+pub struct PPUSnapshot {
+    pub video_ram: [[u8; VRAM_SIZE]; VRAM_BANKS],
+    pub oam: [u8; OAM_SIZE],
+    pub bg_palette_ram: [u8; CGB_PALETTE_SIZE],
+    pub obj_palette_ram: [u8; CGB_PALETTE_SIZE],
+    pub registers: [u8; PPU_REGISTERS],
+    pub vram_bank: u8,
+    pub bcps: u8,
+    pub ocps: u8,
+    pub cgb_mode: bool,
+    pub mode_clock: u32,
+}
+
+impl PPUSnapshot {
+    /// Serialize the snapshot, the fields are written in order and the mode clock is little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SNAPSHOT_SIZE);
+        for bank in self.video_ram.iter() {
+            bytes.extend_from_slice(bank);
+        }
+        bytes.extend_from_slice(&self.oam);
+        bytes.extend_from_slice(&self.bg_palette_ram);
+        bytes.extend_from_slice(&self.obj_palette_ram);
+        bytes.extend_from_slice(&self.registers);
+        bytes.push(self.vram_bank);
+        bytes.push(self.bcps);
+        bytes.push(self.ocps);
+        bytes.push(u8::from(self.cgb_mode));
+        bytes.extend_from_slice(&self.mode_clock.to_le_bytes());
+        bytes
+    }
+
+    /// Read a snapshot written by to_bytes, None when the length does not match
+    pub fn from_bytes(bytes: &[u8]) -> Option<PPUSnapshot> {
+        if bytes.len() != SNAPSHOT_SIZE {
+            return None;
+        }
+        let mut snapshot = PPUSnapshot {
+            video_ram: [[0x00; VRAM_SIZE]; VRAM_BANKS],
+            oam: [0x00; OAM_SIZE],
+            bg_palette_ram: [0x00; CGB_PALETTE_SIZE],
+            obj_palette_ram: [0x00; CGB_PALETTE_SIZE],
+            registers: [0x00; PPU_REGISTERS],
+            vram_bank: 0,
+            bcps: 0x00,
+            ocps: 0x00,
+            cgb_mode: false,
+            mode_clock: 0,
+        };
+        let mut offset = 0;
+        let mut take = |length: usize| {
+            let slice = &bytes[offset..offset + length];
+            offset += length;
+            slice
+        };
+        for bank in snapshot.video_ram.iter_mut() {
+            bank.copy_from_slice(take(VRAM_SIZE));
+        }
+        snapshot.oam.copy_from_slice(take(OAM_SIZE));
+        snapshot
+            .bg_palette_ram
+            .copy_from_slice(take(CGB_PALETTE_SIZE));
+        snapshot
+            .obj_palette_ram
+            .copy_from_slice(take(CGB_PALETTE_SIZE));
+        snapshot.registers.copy_from_slice(take(PPU_REGISTERS));
+        let state = take(4);
+        snapshot.vram_bank = state[0] & 0x01;
+        snapshot.bcps = state[1];
+        snapshot.ocps = state[2];
+        snapshot.cgb_mode = state[3] != 0x00;
+        let mut mode_clock = [0x00; 4];
+        mode_clock.copy_from_slice(take(4));
+        snapshot.mode_clock = u32::from_le_bytes(mode_clock);
+        Some(snapshot)
+    }
+}
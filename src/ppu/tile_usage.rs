@@ -0,0 +1,58 @@
+use super::{TILE_MAP_SIZE, VRAM_BANKS};
+
+/// Tracks the tiles written to VRAM during a frame and the ones that are drawn, to find the VRAM
+/// updates that never make it to the screen
+pub struct TileUsage {
+    written: [[bool; TILE_MAP_SIZE]; VRAM_BANKS],
+    referenced: [[bool; TILE_MAP_SIZE]; VRAM_BANKS],
+    // The (bank, tile) pairs written but not drawn during the last frame
+    unused: Vec<(usize, usize)>,
+}
+
+// The tile number of an address in the tile data area
+fn tile_index(address: u16) -> usize {
+    (address as usize - 0x8000) >> 4
+}
+
+impl Default for TileUsage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TileUsage {
+    pub fn new() -> TileUsage {
+        Self {
+            written: [[false; TILE_MAP_SIZE]; VRAM_BANKS],
+            referenced: [[false; TILE_MAP_SIZE]; VRAM_BANKS],
+            unused: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, bank: usize, address: u16) {
+        self.written[bank][tile_index(address)] = true;
+    }
+
+    pub fn reference(&mut self, bank: usize, address: u16) {
+        self.referenced[bank][tile_index(address)] = true;
+    }
+
+    /// Collect the tiles written but not drawn during the frame and start tracking the next one
+    pub fn end_frame(&mut self) -> &[(usize, usize)] {
+        self.unused.clear();
+        for bank in 0..VRAM_BANKS {
+            for tile in 0..TILE_MAP_SIZE {
+                if self.written[bank][tile] && !self.referenced[bank][tile] {
+                    self.unused.push((bank, tile));
+                }
+            }
+        }
+        self.written = [[false; TILE_MAP_SIZE]; VRAM_BANKS];
+        self.referenced = [[false; TILE_MAP_SIZE]; VRAM_BANKS];
+        &self.unused
+    }
+
+    pub fn unused(&self) -> &[(usize, usize)] {
+        &self.unused
+    }
+}
@@ -1,6 +1,10 @@
 pub mod file;
+pub mod hash;
+pub mod present;
 
+use crate::cartridge::{check_cartridge, check_rom_size, NINTENDO_LOGO};
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::str;
 
 pub const BOOT_ROM_SIZE: usize = 0x100;
@@ -12,10 +16,46 @@ pub fn get_boot_rom(path: &str) -> Vec<u8> {
     buffer
 }
 
+/// Roms are at least 32K, always made of whole 16K banks and at most MAX_ROM_SIZE
+pub fn is_valid_rom(buffer: &[u8]) -> bool {
+    check_rom_size(buffer.len()).is_ok()
+}
+
 pub fn get_rom(path: &str) -> Vec<u8> {
-    let rom_buffer = file::buffer_from_file(path);
-    if rom_buffer.len() < 0x8000 || rom_buffer.len() % 0x4000 != 0 {
-        panic!("Invalid length: {} bytes", rom_buffer.len());
+    read_rom(Path::new(path)).unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// Read a rom file, the size is checked before reading so a huge file is never loaded in memory
+pub fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let length = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    check_rom_size(length as usize).map_err(|e| e.to_string())?;
+    std::fs::read(path).map_err(|e| e.to_string())
+}
+
+/// Handle a file dropped on the window, the loader is only called when the file is a valid rom
+pub fn open_dropped_file<F: FnMut(PathBuf)>(path: PathBuf, mut load_rom: F) -> bool {
+    match read_rom(&path).and_then(|rom| check_rom_header(&rom)) {
+        Ok(()) => {
+            load_rom(path);
+            true
+        }
+        Err(error) => {
+            warn!(
+                "Dropped file {} is not a valid rom: {}",
+                path.display(),
+                error
+            );
+            false
+        }
+    }
+}
+
+// The header of a dropped file must be loadable and carry the logo, so other files of a rom size
+// are not swapped in
+fn check_rom_header(rom: &[u8]) -> Result<(), String> {
+    check_cartridge(rom).map_err(|e| e.to_string())?;
+    if rom[0x104..0x134] != NINTENDO_LOGO[..] {
+        return Err(String::from("The header has no logo"));
     }
-    rom_buffer
+    Ok(())
 }
@@ -0,0 +1,41 @@
+/// The frame rate of the Game Boy lcd in Hz
+pub const GB_FRAME_RATE: f64 = 59.7275;
+
+/// How the display paces presenting frames
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PresentMode {
+    // Present at the Game Boy frame rate, whatever the display refresh rate is
+    #[default]
+    FixedGbRate,
+    // Present on every display refresh, waiting for vsync
+    SyncToDisplay,
+}
+
+/// The frame pacing of the display window
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FramePacing {
+    // The maximum number of frames presented per second
+    pub max_fps: u64,
+    // Whether buffer swaps wait for the display vsync
+    pub vsync: bool,
+}
+
+/// The frame pacing for the present mode on a display refreshing at display_rate Hz. The window
+/// rate is a whole number of frames, the Game Boy rate is rounded up so no frame is skipped
+pub fn frame_pacing(present_mode: PresentMode, display_rate: u32) -> FramePacing {
+    match present_mode {
+        PresentMode::FixedGbRate => FramePacing {
+            max_fps: GB_FRAME_RATE.ceil() as u64,
+            vsync: false,
+        },
+        // An unknown display rate falls back to the Game Boy rate
+        PresentMode::SyncToDisplay if display_rate == 0 => FramePacing {
+            max_fps: GB_FRAME_RATE.ceil() as u64,
+            vsync: true,
+        },
+        PresentMode::SyncToDisplay => FramePacing {
+            max_fps: u64::from(display_rate),
+            vsync: true,
+        },
+    }
+}
@@ -0,0 +1,79 @@
+use rgb::cpu::alu::{self, Flags};
+
+fn flags(z: bool, n: bool, h: bool, c: bool) -> Flags {
+    Flags {
+        z: Some(z),
+        n: Some(n),
+        h: Some(h),
+        c: Some(c),
+    }
+}
+
+#[test]
+fn add_matches_wide_arithmetic() {
+    for a in 0..=0xffu16 {
+        for b in 0..=0xffu16 {
+            for carry in 0..=1u16 {
+                let sum = a + b + carry;
+                let half = (a & 0x0f) + (b & 0x0f) + carry > 0x0f;
+                let expected = flags(sum & 0xff == 0, false, half, sum > 0xff);
+                assert_eq!(
+                    alu::add(a as u8, b as u8, carry == 1),
+                    (sum as u8, expected),
+                    "{:02x} + {:02x} + {}",
+                    a,
+                    b,
+                    carry
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn sub_matches_wide_arithmetic() {
+    for a in 0..=0xffi16 {
+        for b in 0..=0xffi16 {
+            for carry in 0..=1i16 {
+                let difference = a - b - carry;
+                let half = (a & 0x0f) - (b & 0x0f) - carry < 0;
+                let expected = flags(difference & 0xff == 0, true, half, difference < 0);
+                assert_eq!(
+                    alu::sub(a as u8, b as u8, carry == 1),
+                    (difference as u8, expected),
+                    "{:02x} - {:02x} - {}",
+                    a,
+                    b,
+                    carry
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn rotates_match_truth_table() {
+    // (n, carry in, rlc, rl, rrc, rr)
+    let table = [
+        (0x00, false, 0x00, 0x00, 0x00, 0x00),
+        (0x00, true, 0x00, 0x01, 0x00, 0x80),
+        (0x80, false, 0x01, 0x00, 0x40, 0x40),
+        (0x01, false, 0x02, 0x02, 0x80, 0x00),
+        (0x01, true, 0x02, 0x03, 0x80, 0x80),
+        (0x85, true, 0x0b, 0x0b, 0xc2, 0xc2),
+    ];
+    for (n, carry, rlc, rl, rrc, rr) in table.iter() {
+        let high = n & 0x80 != 0;
+        let low = n & 0x01 != 0;
+        assert_eq!(alu::rlc(*n), (*rlc, flags(*rlc == 0, false, false, high)));
+        assert_eq!(
+            alu::rl(*n, *carry),
+            (*rl, flags(*rl == 0, false, false, high))
+        );
+        assert_eq!(alu::rrc(*n), (*rrc, flags(*rrc == 0, false, false, low)));
+        assert_eq!(
+            alu::rr(*n, *carry),
+            (*rr, flags(*rr == 0, false, false, low))
+        );
+    }
+}
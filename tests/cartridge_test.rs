@@ -0,0 +1,283 @@
+mod common;
+
+use common::cartridge_rom;
+use rgb::cartridge::header::{global_checksum, header_checksum, HeaderInfo, Licensee, Region};
+use rgb::cartridge::ips::PatchError;
+use rgb::cartridge::rtc::{RealTimeClock, RTC_FOOTER_SIZE};
+use rgb::cartridge::{
+    check_cartridge, check_rom_size, load_cartridge, CartridgeError, CartridgeRamSize,
+    CartridgeRomSize, RomSizeError, MAX_ROM_SIZE, NINTENDO_LOGO,
+};
+use rgb::memory::Memory;
+
+#[test]
+fn mbc1_without_declared_ram_uses_ram() {
+    let mut cartridge = load_cartridge(cartridge_rom(0x01, 0x00, 0x00)).unwrap();
+    cartridge.set(0x0000, 0x0a);
+    cartridge.set(0xa000, 0x42);
+    assert_eq!(cartridge.get(0xa000), 0x42);
+
+    // No battery, nothing should be persisted
+    let save_path = std::env::temp_dir().join("rgb_mbc1_no_battery.sav");
+    cartridge.save(save_path.clone());
+    assert!(!save_path.exists());
+}
+
+#[test]
+fn mbc1m_upper_bits_select_the_game() {
+    // 1M MBC1 rom with a second game header at bank 0x10
+    let mut rom = cartridge_rom(0x01, 0x05, 0x00);
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+    for bank in 0..0x40 {
+        rom[bank * 0x4000 + 0x200] = bank as u8;
+    }
+    let mut cartridge = load_cartridge(rom).unwrap();
+    // Game 1, bank 2
+    cartridge.set(0x4000, 0x01);
+    cartridge.set(0x2000, 0x02);
+    assert_eq!(cartridge.get(0x4200), 0x12);
+    // Bank 0x10 is not mirrored into the lower 4 bits
+    cartridge.set(0x2000, 0x12);
+    assert_eq!(cartridge.get(0x4200), 0x12);
+    // In mode 1 the game's first bank is mapped at 0x0000
+    assert_eq!(cartridge.get(0x0200), 0x00);
+    cartridge.set(0x6000, 0x01);
+    assert_eq!(cartridge.get(0x0200), 0x10);
+}
+
+#[test]
+fn ips_patch_rewrites_rom_bytes() {
+    let mut cartridge = load_cartridge(cartridge_rom(0x00, 0x00, 0x00)).unwrap();
+    let mut patch = b"PATCH".to_vec();
+    // Two bytes at 0x0150 and a run of three 0xaa at 0x0200
+    patch.extend_from_slice(&[0x00, 0x01, 0x50, 0x00, 0x02, 0x12, 0x34]);
+    patch.extend_from_slice(&[0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x03, 0xaa]);
+    patch.extend_from_slice(b"EOF");
+    cartridge.apply_ips(&patch).unwrap();
+    assert_eq!(cartridge.get(0x0150), 0x12);
+    assert_eq!(cartridge.get(0x0151), 0x34);
+    assert_eq!(cartridge.get(0x0202), 0xaa);
+    assert_eq!(cartridge.get(0x0203), 0x00);
+    assert_eq!(
+        cartridge.apply_ips(b"PATCH\x00\x01"),
+        Err(PatchError::Truncated)
+    );
+    assert_eq!(cartridge.apply_ips(b"NOPE"), Err(PatchError::InvalidHeader));
+}
+
+#[test]
+fn mbc1_2k_ram_mirrors_across_the_range() {
+    // MBC1+RAM with a 2KB ram
+    let mut cartridge = load_cartridge(cartridge_rom(0x02, 0x00, 0x01)).unwrap();
+    cartridge.set(0x0000, 0x0a);
+    cartridge.set(0xa000, 0x12);
+    assert_eq!(cartridge.get(0xa800), 0x12);
+    assert_eq!(cartridge.get(0xbfff), 0x00);
+    cartridge.set(0xbfff, 0x34);
+    assert_eq!(cartridge.get(0xa7ff), 0x34);
+}
+
+#[test]
+fn unimplemented_mapper_ignores_rom_writes() {
+    // HuC1+RAM+BATTERY
+    let mut rom = cartridge_rom(0xff, 0x01, 0x02);
+    rom[0x4000] = 0x11;
+    rom[0x8000] = 0x22;
+    let mut cartridge = load_cartridge(rom).unwrap();
+    cartridge.set(0x0000, 0x0a);
+    cartridge.set(0x2000, 0x02);
+    cartridge.set(0x6000, 0x01);
+    assert_eq!(cartridge.get(0x4000), 0x11);
+}
+
+#[test]
+fn rtc_footer_round_trips_the_shared_layout() {
+    // Current 1d 03:20:10, latched 00:00:05 with the halt bit, saved at 1600000000
+    let mut footer = [0x00; RTC_FOOTER_SIZE];
+    for (index, value) in [10, 20, 3, 1, 0, 5, 0, 0, 0, 0x40].iter().enumerate() {
+        footer[index * 4] = *value;
+    }
+    footer[0x28..].copy_from_slice(&1_600_000_000u64.to_le_bytes());
+
+    let mut rtc = RealTimeClock::new();
+    rtc.load_footer(&footer);
+    assert_eq!(rtc.get(0x08), 5);
+    assert_eq!(rtc.get(0x0c), 0x40);
+    assert_eq!(rtc.to_footer(1_600_000_000).to_vec(), footer.to_vec());
+
+    // The footer follows the ram in the save file of a MBC3+TIMER cart
+    let mut save = vec![0x00; 0x2000];
+    save.extend_from_slice(&footer);
+    let save_path = std::env::temp_dir().join("rgb_mbc3_rtc_footer.sav");
+    std::fs::write(&save_path, &save).unwrap();
+    let mut cartridge = load_cartridge(cartridge_rom(0x10, 0x00, 0x02)).unwrap();
+    cartridge.load(save_path.clone());
+    cartridge.set(0x0000, 0x0a);
+    cartridge.set(0x4000, 0x08);
+    assert_eq!(cartridge.get(0xa000), 5);
+    std::fs::remove_file(save_path).unwrap();
+}
+
+#[test]
+fn header_info_parses_the_header_fields() {
+    let mut rom = cartridge_rom(0x03, 0x01, 0x02);
+    rom[0x134..0x13c].copy_from_slice(b"TESTGAME");
+    rom[0x13f..0x143].copy_from_slice(b"ABCD");
+    rom[0x143] = 0x80;
+    rom[0x144..0x146].copy_from_slice(b"01");
+    rom[0x146] = 0x03;
+    rom[0x14a] = 0x01;
+    rom[0x14b] = 0x33;
+    rom[0x14c] = 0x02;
+    rom[0x14d] = header_checksum(&rom);
+    let checksum = global_checksum(&rom);
+    rom[0x14e..0x150].copy_from_slice(&checksum.to_be_bytes());
+
+    let cartridge = load_cartridge(rom).unwrap();
+    let info = cartridge.header_info();
+    assert_eq!(
+        info,
+        HeaderInfo {
+            title: String::from("TESTGAME"),
+            manufacturer_code: String::from("ABCD"),
+            cgb_flag: 0x80,
+            licensee: Licensee::New(String::from("01")),
+            sgb_flag: true,
+            cartridge_type: 0x03,
+            rom_size: Some(CartridgeRomSize::Rom64K),
+            ram_size: Some(CartridgeRamSize::Ram8K),
+            region: Region::Overseas,
+            version: 0x02,
+            header_checksum_ok: true,
+            global_checksum_ok: true,
+        }
+    );
+
+    let mut rom = cartridge_rom(0x00, 0x00, 0x00);
+    rom[0x14b] = 0x01;
+    let info = load_cartridge(rom).unwrap().header_info();
+    assert_eq!(info.licensee, Licensee::Old(0x01));
+    assert_eq!(info.region, Region::Japan);
+    assert!(!info.header_checksum_ok);
+}
+
+// A 64K rom with a valid header and checksums, every bank holds data
+fn clean_rom() -> Vec<u8> {
+    let mut rom = cartridge_rom(0x01, 0x01, 0x00);
+    for (address, byte) in rom.iter_mut().enumerate().skip(0x150) {
+        *byte = address as u8;
+    }
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x14d] = header_checksum(&rom);
+    let checksum = global_checksum(&rom);
+    rom[0x14e..0x150].copy_from_slice(&checksum.to_be_bytes());
+    rom
+}
+
+#[test]
+fn dump_health_of_a_clean_rom() {
+    let health = load_cartridge(clean_rom()).unwrap().dump_health();
+    assert!(health.is_healthy(), "{}", health);
+}
+
+#[test]
+fn dump_health_reports_blank_banks() {
+    let mut rom = clean_rom();
+    for byte in rom[0xc000..0x10000].iter_mut() {
+        *byte = 0xff;
+    }
+    let health = load_cartridge(rom).unwrap().dump_health();
+    assert!(!health.is_healthy());
+    assert_eq!(health.blank_banks, vec![3]);
+    assert!(!health.global_checksum_ok);
+    assert!(health.header_checksum_ok && health.logo_ok && health.size_ok);
+}
+
+#[test]
+fn truncated_rom_reads_open_bus_past_its_end() {
+    // MBC3 declaring 128K with only 64K dumped
+    let mut rom = cartridge_rom(0x11, 0x02, 0x00);
+    rom.truncate(0x10000);
+    for bank in 0..4 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    let mut cartridge = load_cartridge(rom).unwrap();
+    cartridge.set(0x2000, 0x03);
+    assert_eq!(cartridge.get(0x4000), 0x03);
+    cartridge.set(0x2000, 0x05);
+    assert_eq!(cartridge.get(0x4000), 0xff);
+    // Banks past the declared 8 banks still wrap around
+    cartridge.set(0x2000, 0x0a);
+    assert_eq!(cartridge.get(0x4000), 0x02);
+}
+
+#[test]
+fn rom_ram_battery_without_ram_size_persists_ram() {
+    let mut cartridge = load_cartridge(cartridge_rom(0x09, 0x00, 0x00)).unwrap();
+    // The ram is always enabled, there is no mapper
+    cartridge.set(0xa000, 0x42);
+    cartridge.set(0xbfff, 0x24);
+    assert_eq!(cartridge.get(0xa000), 0x42);
+
+    let save_path = std::env::temp_dir().join("rgb_rom_ram_battery.sav");
+    cartridge.save(save_path.clone());
+    let mut cartridge = load_cartridge(cartridge_rom(0x09, 0x00, 0x00)).unwrap();
+    cartridge.load(save_path.clone());
+    assert_eq!(cartridge.get(0xa000), 0x42);
+    assert_eq!(cartridge.get(0xbfff), 0x24);
+    std::fs::remove_file(save_path).unwrap();
+}
+
+#[test]
+fn truncated_saves_are_ignored() {
+    for (name, save) in [("empty", vec![]), ("short", vec![0x42; 0x2000])].iter() {
+        let save_path = std::env::temp_dir().join(format!("rgb_mbc1_{}.sav", name));
+        std::fs::write(&save_path, save).unwrap();
+        // MBC1+RAM+BATTERY with 4 banks of ram
+        let mut cartridge = load_cartridge(cartridge_rom(0x03, 0x00, 0x03)).unwrap();
+        cartridge.load(save_path.clone());
+        std::fs::remove_file(save_path).unwrap();
+
+        // The ram stays cleared and every bank is still usable
+        cartridge.set(0x0000, 0x0a);
+        cartridge.set(0x6000, 0x01);
+        assert_eq!(cartridge.get(0xa000), 0x00);
+        cartridge.set(0x4000, 0x03);
+        cartridge.set(0xbfff, 0x24);
+        assert_eq!(cartridge.get(0xbfff), 0x24);
+    }
+}
+
+#[test]
+fn roms_larger_than_the_limit_are_rejected() {
+    let rom = vec![0x00; MAX_ROM_SIZE + 0x4000];
+    assert_eq!(
+        check_rom_size(rom.len()),
+        Err(RomSizeError::TooLarge(MAX_ROM_SIZE + 0x4000))
+    );
+    assert_eq!(check_rom_size(MAX_ROM_SIZE), Ok(()));
+    assert_eq!(
+        check_rom_size(0x4000),
+        Err(RomSizeError::InvalidLength(0x4000))
+    );
+    assert_eq!(
+        load_cartridge(rom).err(),
+        Some(CartridgeError::RomSize(RomSizeError::TooLarge(
+            MAX_ROM_SIZE + 0x4000
+        )))
+    );
+}
+
+#[test]
+fn unknown_header_bytes_are_rejected() {
+    assert_eq!(
+        load_cartridge(cartridge_rom(0x42, 0x00, 0x00)).err(),
+        Some(CartridgeError::UnknownType(0x42))
+    );
+    assert_eq!(
+        load_cartridge(cartridge_rom(0x01, 0x00, 0x09)).err(),
+        Some(CartridgeError::InvalidRamSize(0x09))
+    );
+    assert_eq!(check_cartridge(&cartridge_rom(0x13, 0x00, 0x03)), Ok(()));
+}
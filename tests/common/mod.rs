@@ -0,0 +1,32 @@
+#![allow(dead_code)]
+
+use rgb::memory::Memory;
+
+/// A plain 64K memory without any mapping
+pub struct FlatMemory(pub [u8; 0x10000]);
+
+impl Memory for FlatMemory {
+    fn get(&self, address: u16) -> u8 {
+        self.0[address as usize]
+    }
+
+    fn set(&mut self, address: u16, value: u8) {
+        self.0[address as usize] = value;
+    }
+}
+
+/// Build a 32K rom only cartridge with the program placed at the entry point 0x0100
+pub fn rom_with_program(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    rom
+}
+
+/// Build an empty cartridge with the given header cartridge type, rom size and ram size bytes
+pub fn cartridge_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![0x00; 0x8000 << rom_size];
+    rom[0x147] = cartridge_type;
+    rom[0x148] = rom_size;
+    rom[0x149] = ram_size;
+    rom
+}
@@ -0,0 +1,67 @@
+use rgb::config::Config;
+use rgb::input::joypad::SocdMode;
+
+#[test]
+fn command_line_options_override_the_config_file() {
+    let file = Config::parse(
+        r#"
+        scale = 4
+        speed = 0.5
+        socd = "neutral"
+        smooth-palettes = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(file.scale, Some(4));
+    assert_eq!(file.fast_boot, None);
+
+    let command_line = Config {
+        scale: Some(3),
+        fast_boot: Some(true),
+        ..Config::default()
+    };
+    let options = Config::defaults().merge(file).merge(command_line);
+    assert_eq!(options.scale, Some(3));
+    assert_eq!(options.fast_boot, Some(true));
+    assert_eq!(options.speed, Some(0.5));
+    assert_eq!(options.socd.as_deref(), Some("neutral"));
+    assert_eq!(options.smooth_palettes, Some(true));
+    // Options in neither keep their default
+    assert_eq!(options.unthrottled, Some(false));
+
+    assert!(Config::parse("scale = \"big\"").is_err());
+}
+
+#[test]
+fn file_options_fill_in_the_defaults() {
+    let options = Config::parse("socd = \"last-wins\"").unwrap().options();
+    assert_eq!(options.socd, SocdMode::LastWins);
+    assert_eq!(options.speed, 1.0);
+    assert_eq!(options.scale, 2);
+    assert_eq!(options.sync_to_display, None);
+}
+
+#[test]
+fn invalid_values_name_their_key() {
+    for source in [
+        "speed = 0.0",
+        "speed = -2.0",
+        "socd = \"netural\"",
+        "scale = 0",
+    ]
+    .iter()
+    {
+        let error = Config::parse(source).unwrap_err();
+        let key = source.split(' ').next().unwrap();
+        assert!(error.starts_with(key), "{}: {}", source, error);
+    }
+}
+
+#[test]
+fn missing_config_file_is_created_with_the_defaults() {
+    let path = std::env::temp_dir().join("rgb_default_config.toml");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(Config::load_or_create(&path), Ok(Config::defaults()));
+    assert_eq!(Config::load_or_create(&path), Ok(Config::defaults()));
+    std::fs::remove_file(path).unwrap();
+}
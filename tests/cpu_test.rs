@@ -1,4 +1,10 @@
+mod common;
+
+use common::{cartridge_rom, rom_with_program};
 use rgb;
+use rgb::cpu::access_log::AccessKind;
+use rgb::cpu::registers::Flag;
+use rgb::cpu::sm80::StopPath;
 use rgb::cpu::ClockedCPU;
 use rgb::emulator::Emulator;
 use rgb::memory::Memory;
@@ -84,3 +90,267 @@ fn run_cpu_tests() {
         cpu.tick();
     }
 }
+
+#[test]
+fn access_log_records_instruction_accesses() {
+    // ld hl, $c000; ld a, (hl); push bc
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x21, 0x00, 0xc0, 0x7e, 0xc5]));
+    emulator.cpu.set_access_logging(true);
+    emulator.step_synchronized();
+    assert_eq!(
+        emulator.cpu.last_instruction_accesses(),
+        vec![
+            (AccessKind::Fetch, 0x0100),
+            (AccessKind::Fetch, 0x0101),
+            (AccessKind::Fetch, 0x0102)
+        ]
+    );
+    emulator.step_synchronized();
+    assert_eq!(
+        emulator.cpu.last_instruction_accesses(),
+        vec![(AccessKind::Fetch, 0x0103), (AccessKind::Read, 0xc000)]
+    );
+    let sp = emulator.cpu.core.registers.sp;
+    emulator.step_synchronized();
+    assert_eq!(
+        emulator.cpu.last_instruction_accesses(),
+        vec![
+            (AccessKind::Fetch, 0x0104),
+            (AccessKind::Write, sp - 1),
+            (AccessKind::Write, sp - 2)
+        ]
+    );
+    emulator.cpu.set_access_logging(false);
+    emulator.step_synchronized();
+    assert!(emulator.cpu.last_instruction_accesses().is_empty());
+}
+
+#[test]
+fn access_log_keeps_the_rom_mapping() {
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    let rom_mapping = emulator.cpu.core.memory.borrow().rom_mapping();
+    assert!(rom_mapping.is_some());
+    emulator.cpu.set_access_logging(true);
+    assert_eq!(emulator.cpu.core.memory.borrow().rom_mapping(), rom_mapping);
+}
+
+#[test]
+fn access_log_records_only_the_interrupt_dispatch() {
+    // The rom is filled with nops
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    emulator.cpu.set_access_logging(true);
+    emulator.step_synchronized();
+    {
+        let mut mmu = emulator.mmu.borrow_mut();
+        mmu.set(0xffff, 0x01);
+        mmu.set(0xff0f, 0x01);
+    }
+    emulator.cpu.core.ei = true;
+    let sp = emulator.cpu.core.registers.sp;
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0040);
+    // The pc is pushed and the serviced interrupt is cleared from IF
+    assert_eq!(
+        emulator.cpu.last_instruction_accesses(),
+        vec![
+            (AccessKind::Write, sp - 1),
+            (AccessKind::Write, sp - 2),
+            (AccessKind::Write, 0xff0f)
+        ]
+    );
+}
+
+#[test]
+fn conditional_call_consumes_operand() {
+    // call nz, $0200; call z, $0200
+    let mut emulator = Emulator::new(
+        None,
+        rom_with_program(&[0xc4, 0x00, 0x02, 0xcc, 0x00, 0x02]),
+    );
+    emulator.cpu.core.registers.set_flag(Flag::Z, true);
+    let sp = emulator.cpu.core.registers.sp;
+    // Not taken, the operand is skipped
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0103);
+    assert_eq!(emulator.cpu.core.registers.sp, sp);
+    // Taken, the return address is the instruction after the call
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0200);
+    assert_eq!(emulator.cpu.core.registers.sp, sp - 2);
+    let mmu = emulator.mmu.borrow();
+    assert_eq!(mmu.get(sp - 2), 0x06);
+    assert_eq!(mmu.get(sp - 1), 0x01);
+}
+
+// Run `di` or `ei`, then halt followed by inc a, with the timer interrupt enabled
+fn halt_emulator(enable_interrupts: bool, pending: bool) -> Emulator {
+    let ime = if enable_interrupts { 0xfb } else { 0xf3 };
+    let mut emulator = Emulator::new(None, rom_with_program(&[ime, 0x76, 0x3c, 0x00]));
+    emulator.cpu.core.registers.a = 0x00;
+    emulator.mmu.borrow_mut().set(0xffff, 0x04);
+    emulator.step_synchronized();
+    if pending {
+        emulator.mmu.borrow_mut().set(0xff0f, 0x04);
+    }
+    emulator.step_synchronized();
+    emulator
+}
+
+#[test]
+fn halt_with_ime_off_wakes_without_servicing() {
+    let mut emulator = halt_emulator(false, false);
+    let sp = emulator.cpu.core.registers.sp;
+    for _ in 0..10 {
+        emulator.step_synchronized();
+    }
+    assert!(emulator.cpu.core.halted);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0102);
+
+    // The request wakes the cpu, it continues after the halt and the request stays pending
+    emulator.mmu.borrow_mut().set(0xff0f, 0x04);
+    emulator.step_synchronized();
+    assert!(!emulator.cpu.core.halted);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0103);
+    assert_eq!(emulator.cpu.core.registers.a, 0x01);
+    assert_eq!(emulator.cpu.core.registers.sp, sp);
+    assert_eq!(emulator.mmu.borrow().get(0xff0f) & 0x04, 0x04);
+
+    // An already pending interrupt does not halt, the byte after the halt is read twice
+    let mut emulator = halt_emulator(false, true);
+    assert!(!emulator.cpu.core.halted);
+    emulator.step_synchronized();
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.a, 0x02);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0103);
+
+    // With IME on the interrupt is serviced
+    let mut emulator = halt_emulator(true, false);
+    assert!(emulator.cpu.core.halted);
+    emulator.mmu.borrow_mut().set(0xff0f, 0x04);
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0050);
+}
+
+#[test]
+fn opcode_coverage_marks_executed_opcodes() {
+    // ld a, $01; swap a; jr -2 loops on the jr
+    let mut emulator = Emulator::new(
+        None,
+        rom_with_program(&[0x3e, 0x01, 0xcb, 0x37, 0x18, 0xfe]),
+    );
+    for _ in 0..5 {
+        emulator.step_synchronized();
+    }
+    let (opcodes, cb_opcodes) = emulator.opcode_coverage();
+    let executed: Vec<usize> = (0..256).filter(|opcode| opcodes[*opcode]).collect();
+    let cb_executed: Vec<usize> = (0..256).filter(|opcode| cb_opcodes[*opcode]).collect();
+    assert_eq!(executed, vec![0x18, 0x3e]);
+    assert_eq!(cb_executed, vec![0x37]);
+}
+
+#[test]
+fn interrupt_vector_is_picked_after_the_high_byte_push() {
+    // The pc high byte 0x01 lands in IE and enables vblank in place of the timer
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    emulator.cpu.core.registers.sp = 0x0000;
+    emulator.mmu.borrow_mut().set(0xffff, 0x04);
+    emulator.mmu.borrow_mut().set(0xff0f, 0x05);
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0040);
+    assert_eq!(emulator.mmu.borrow().get(0xffff), 0x01);
+    assert_eq!(emulator.mmu.borrow().get(0xff0f) & 0x1f, 0x04);
+
+    // The high byte 0x02 enables only the stat interrupt, which is not requested
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    emulator.cpu.core.registers.pc = 0x0200;
+    emulator.cpu.core.registers.sp = 0x0000;
+    emulator.mmu.borrow_mut().set(0xffff, 0x04);
+    emulator.mmu.borrow_mut().set(0xff0f, 0x05);
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0000);
+    assert_eq!(emulator.mmu.borrow().get(0xff0f) & 0x1f, 0x05);
+}
+
+#[test]
+fn if_cleared_by_the_high_byte_push_cancels_the_dispatch() {
+    // The pc high byte 0x01 lands in IF and replaces the timer request with vblank, which is not
+    // enabled, so nothing is serviced
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    emulator.cpu.core.registers.sp = 0xff10;
+    emulator.mmu.borrow_mut().set(0xffff, 0x04);
+    emulator.mmu.borrow_mut().set(0xff0f, 0x04);
+    emulator.step_synchronized();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0000);
+    assert_eq!(emulator.mmu.borrow().get(0xff0f) & 0x1f, 0x01);
+}
+
+#[test]
+fn stop_length_depends_on_the_second_byte() {
+    // di; stop; then the second byte
+    let stop_emulator = |second_byte: u8| {
+        let mut emulator = Emulator::new(None, rom_with_program(&[0xf3, 0x10, second_byte]));
+        emulator.mmu.borrow_mut().set(0xff0f, 0x00);
+        emulator.step_synchronized();
+        emulator.step_synchronized();
+        emulator
+    };
+    let emulator = stop_emulator(0x00);
+    assert_eq!(emulator.cpu.core.last_stop, Some(StopPath::Stopped));
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0103);
+
+    // The second byte is executed next
+    let emulator = stop_emulator(0x3c);
+    assert_eq!(emulator.cpu.core.last_stop, Some(StopPath::Corrupted));
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0102);
+}
+
+#[test]
+fn decode_cache_follows_rom_bank_switches() {
+    // Call 0x4000 in bank 1 then bank 2, each loads a different value into a
+    let mut rom = cartridge_rom(0x01, 0x01, 0x00);
+    let program = [
+        0x3e, 0x01, 0xea, 0x00, 0x20, 0xcd, 0x00, 0x40, 0x47, 0x3e, 0x02, 0xea, 0x00, 0x20, 0xcd,
+        0x00, 0x40, 0x18, 0xfe,
+    ];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    rom[0x4000..0x4003].copy_from_slice(&[0x3e, 0x11, 0xc9]);
+    rom[0x8000..0x8003].copy_from_slice(&[0x3e, 0x22, 0xc9]);
+    let mut cached = Emulator::new(None, rom.clone());
+    cached.cpu.set_decode_cache(true);
+    let mut uncached = Emulator::new(None, rom);
+    for _ in 0..20 {
+        cached.step_synchronized();
+        uncached.step_synchronized();
+        assert_eq!(cached.cpu.core.registers, uncached.cpu.core.registers);
+    }
+    assert_eq!(cached.cpu.core.registers.b, 0x11);
+    assert_eq!(cached.cpu.core.registers.a, 0x22);
+    assert!(!cached.cpu.decode_cache().unwrap().is_empty());
+}
+
+#[test]
+fn immediate_fetches_wrap_and_straddle_banks() {
+    let mut rom = rom_with_program(&[]);
+    rom[0x7fff] = 0x34;
+    rom[0x0000] = 0x56;
+    let mut emulator = Emulator::new(None, rom);
+    emulator.mmu.borrow_mut().set(0x8000, 0x12);
+    emulator.mmu.borrow_mut().set(0xfffe, 0x78);
+    emulator.mmu.borrow_mut().set(0xffff, 0x9a);
+    let core = &mut emulator.cpu.core;
+
+    // The high byte of a word at the end of the rom comes from VRAM
+    core.registers.pc = 0x7fff;
+    assert_eq!(core.get_next_word(), 0x1234);
+    assert_eq!(core.registers.pc, 0x8001);
+
+    core.registers.pc = 0xfffe;
+    assert_eq!(core.get_next_word(), 0x9a78);
+    assert_eq!(core.registers.pc, 0x0000);
+    core.registers.pc = 0xffff;
+    assert_eq!(core.get_next_word(), 0x569a);
+    assert_eq!(core.registers.pc, 0x0001);
+    core.registers.pc = 0xffff;
+    assert_eq!(core.get_next(), 0x9a);
+    assert_eq!(core.registers.pc, 0x0000);
+}
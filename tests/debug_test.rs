@@ -0,0 +1,215 @@
+mod common;
+
+use common::{rom_with_program, FlatMemory};
+use rgb::cpu::registers::{Flag, Registers};
+use rgb::debug::command::DebugCommand;
+use rgb::debug::debug_state::DebugState;
+use rgb::debug::expression::Expression;
+use rgb::emulator::Emulator;
+use rgb::memory::cheat::Cheat;
+use rgb::memory::Memory;
+use rgb::ppu::Mode;
+
+#[test]
+fn debug_windows_toggle_visibility() {
+    let mut debug_state = DebugState::new();
+    assert!(debug_state.debug_visible);
+    assert!(!debug_state.toggle_debug_visible());
+    assert!(!debug_state.debug_visible);
+    assert!(debug_state.toggle_debug_visible());
+}
+
+fn evaluate(source: &str, registers: &Registers) -> u16 {
+    Expression::parse(source)
+        .unwrap()
+        .evaluate(registers, &FlatMemory([0; 0x10000]))
+}
+
+#[test]
+fn expressions_compare_registers_and_flags() {
+    let mut registers = Registers::new();
+    registers.a = 0x05;
+    registers.set_hl(0xc123);
+    registers.set_flag(Flag::Z, true);
+    assert_eq!(evaluate("A == 0x05 && Z", &registers), 1);
+    assert_eq!(evaluate("a == 5 && !z", &registers), 0);
+    assert_eq!(evaluate("HL >= $c000 && (CY || A < 6)", &registers), 1);
+    assert_eq!(evaluate("BC", &registers), 0);
+    assert!(Expression::parse("A == ").is_err());
+    assert!(Expression::parse("Q").is_err());
+}
+
+#[test]
+fn conditional_breakpoint_fires_on_register_value() {
+    // inc a, jr -3
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x3c, 0x18, 0xfd]));
+    emulator
+        .debug_command(DebugCommand::ConditionalBreakpoint {
+            pc: 0x0100,
+            condition: String::from("A == 0x05"),
+        })
+        .unwrap();
+    for _ in 0..100 {
+        if emulator.is_paused() {
+            break;
+        }
+        emulator.tick();
+    }
+    assert!(emulator.is_paused());
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0100);
+    assert_eq!(emulator.cpu.core.registers.a, 0x05);
+}
+
+#[test]
+fn step_back_restores_registers_and_memory() {
+    // inc a, inc b, ld ($c000), a, jr -2
+    let program = [0x3c, 0x04, 0xea, 0x00, 0xc0, 0x18, 0xfe];
+    let mut emulator = Emulator::new(None, rom_with_program(&program));
+    emulator.pause();
+    let before = emulator.cpu.core.registers;
+    for _ in 0..3 {
+        emulator.step();
+    }
+    assert_eq!(emulator.mmu.borrow().get(0xc000), before.a + 1);
+    for _ in 0..3 {
+        emulator.debug_command(DebugCommand::StepBack).unwrap();
+    }
+    assert_eq!(emulator.cpu.core.registers, before);
+    assert_eq!(emulator.mmu.borrow().get(0xc000), 0x00);
+    assert!(emulator.debug_command(DebugCommand::StepBack).is_err());
+}
+
+#[test]
+fn step_back_history_is_cleared_by_a_new_rom() {
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x3c, 0x18, 0xfd]));
+    emulator.pause();
+    emulator.step();
+    emulator.load_rom(rom_with_program(&[])).unwrap();
+    assert!(emulator.debug_command(DebugCommand::StepBack).is_err());
+
+    emulator.step();
+    emulator.reset();
+    assert!(emulator.debug_command(DebugCommand::StepBack).is_err());
+}
+
+#[test]
+fn step_back_during_mode_3_restores_the_stored_memory() {
+    // The rom is filled with nops
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    {
+        let mut mmu = emulator.mmu.borrow_mut();
+        mmu.set_access_gating(true);
+        mmu.load_region(0x8000, &[0x42]);
+        mmu.load_region(0xfe00, &[0x24]);
+        mmu.set_cheats(&[Cheat::parse("01990cc0").unwrap()]);
+    }
+    emulator.pause();
+    let mode = |emulator: &Emulator| emulator.mmu.borrow().ppu.borrow().mode;
+    while mode(&emulator) != Mode::VRAMRead {
+        emulator.step();
+    }
+    // The last snapshot is taken in mode 3, the step back runs in mode 0
+    while mode(&emulator) == Mode::VRAMRead {
+        emulator.step();
+    }
+    emulator.debug_command(DebugCommand::StepBack).unwrap();
+    assert_eq!(mode(&emulator), Mode::VRAMRead);
+
+    let mmu = emulator.mmu.borrow();
+    assert_eq!(mmu.ppu.borrow().video_ram[0][0], 0x42);
+    assert_eq!(mmu.ppu.borrow().oam[0], 0x24);
+    assert_eq!(mmu.peek(0xc00c), 0x00);
+    assert_eq!(mmu.get(0xc00c), 0x99);
+}
+
+#[test]
+fn memory_watch_reports_updated_value() {
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x18, 0xfe]));
+    emulator
+        .debug_command(DebugCommand::AddWatch(String::from("mem16(0xc100)")))
+        .unwrap();
+    assert_eq!(
+        emulator.watch_values(),
+        vec![(String::from("mem16(0xc100)"), 0x0000)]
+    );
+    emulator.mmu.borrow_mut().set_word(0xc100, 0x1234);
+    assert_eq!(
+        emulator.watch_values(),
+        vec![(String::from("mem16(0xc100)"), 0x1234)]
+    );
+}
+
+#[test]
+fn step_n_runs_exact_instruction_count() {
+    // The rom is filled with nops
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    let start = emulator.cpu.instruction_count();
+    emulator.debug_command(DebugCommand::StepN(100)).unwrap();
+    assert!(emulator.is_paused());
+    assert_eq!(emulator.cpu.instruction_count() - start, 100);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0100 + 100);
+
+    // A breakpoint stops the steps early
+    emulator
+        .debug_command(DebugCommand::ConditionalBreakpoint {
+            pc: 0x0180,
+            condition: String::from("1"),
+        })
+        .unwrap();
+    emulator.debug_command(DebugCommand::StepN(100)).unwrap();
+    assert_eq!(emulator.cpu.instruction_count() - start, 128);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0180);
+}
+
+#[test]
+fn step_n_stops_on_a_locked_cpu() {
+    // nop, then an illegal opcode locks up the cpu
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x00, 0xd3]));
+    emulator.debug_command(DebugCommand::StepN(10)).unwrap();
+    assert!(emulator.cpu.illegal_opcode().is_some());
+    // Stepping again returns instead of waiting for an instruction that never runs
+    emulator.debug_command(DebugCommand::StepN(10)).unwrap();
+    assert!(emulator.is_paused());
+
+    let mut emulator = Emulator::without_rom(None);
+    assert!(emulator.debug_command(DebugCommand::StepN(10)).is_err());
+}
+
+#[test]
+fn break_at_cycle_pauses_at_the_cycle() {
+    // Nops take 4 cycles, so the machine stops exactly on the target
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    emulator.cpu.set_throttled(false);
+    let target = emulator.cpu.cycle_count() + 400;
+    emulator
+        .debug_command(DebugCommand::BreakAtCycle(target))
+        .unwrap();
+    while !emulator.is_paused() {
+        emulator.tick();
+    }
+    assert_eq!(emulator.cpu.cycle_count(), target);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0100 + 100);
+
+    // The breakpoint only fires once
+    emulator.resume();
+    emulator.tick();
+    assert!(!emulator.is_paused());
+}
+
+#[test]
+fn break_at_cycle_stops_an_idle_loop_skip() {
+    // jr -2
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x18, 0xfe]));
+    emulator.cpu.set_throttled(false);
+    emulator.set_idle_loop_skip(true);
+    let target = emulator.cpu.cycle_count() + 1000;
+    emulator
+        .debug_command(DebugCommand::BreakAtCycle(target))
+        .unwrap();
+    while !emulator.is_paused() {
+        emulator.tick();
+    }
+    // The skip stops within one iteration of the loop
+    assert!(emulator.cpu.cycle_count() >= target);
+    assert!(emulator.cpu.cycle_count() < target + 12);
+}
@@ -0,0 +1,573 @@
+mod common;
+
+use common::{rom_with_program, FlatMemory};
+use rgb::cartridge::header::header_checksum;
+use rgb::cartridge::{CartridgeError, NINTENDO_LOGO};
+use rgb::cpu::sm80::{StackGuard, StackGuardAction};
+use rgb::cpu::{unthrottled_env, STEP_CYCLES};
+use rgb::debug::command::DebugCommand;
+use rgb::emulator::accuracy::AccuracyProfile;
+use rgb::emulator::event_log::Event;
+use rgb::emulator::Emulator;
+use rgb::input::joypad::{JoyPadKey, SocdMode};
+use rgb::memory::Memory;
+use rgb::ppu::compat_palette::{compat_palette, DEFAULT_COMPAT_PALETTE};
+use rgb::ppu::no_rom_framebuffer;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// jr -2, spin forever at the entry point
+const SPIN: [u8; 2] = [0x18, 0xfe];
+
+#[test]
+fn vblank_callback_fires_once_per_frame() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    let frames = Rc::new(Cell::new(0));
+    let callback_frames = frames.clone();
+    emulator.set_vblank_callback(Box::new(move |_| {
+        callback_frames.set(callback_frames.get() + 1)
+    }));
+
+    let mut vblank_entered = 0;
+    let mut was_vblank = emulator.should_refresh_screen();
+    while vblank_entered < 3 {
+        emulator.tick();
+        let is_vblank = emulator.should_refresh_screen();
+        if is_vblank && !was_vblank {
+            vblank_entered += 1;
+            assert_eq!(frames.get(), vblank_entered);
+        }
+        was_vblank = is_vblank;
+    }
+    assert_eq!(frames.get(), 3);
+}
+
+#[test]
+fn paused_emulator_does_not_advance_until_stepped() {
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x00, 0x00, 0x00]));
+    emulator.pause();
+    for _ in 0..100 {
+        emulator.tick();
+    }
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0100);
+
+    emulator.step();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0101);
+
+    emulator.resume();
+    emulator.tick();
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0102);
+}
+
+#[test]
+fn accuracy_profile_sets_subsystem_flags() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    for (profile, access_gating, sprite_limit, idle_loop_skip, decode_cache) in [
+        (AccuracyProfile::Fast, false, None, true, true),
+        (AccuracyProfile::Balanced, false, Some(10), true, false),
+        (AccuracyProfile::Accurate, true, Some(10), false, false),
+    ]
+    .iter()
+    {
+        emulator.set_accuracy_profile(*profile);
+        assert_eq!(emulator.idle_loop_skip(), *idle_loop_skip, "{:?}", profile);
+        assert_eq!(
+            emulator.cpu.decode_cache().is_some(),
+            *decode_cache,
+            "{:?}",
+            profile
+        );
+        let mmu = emulator.mmu.borrow();
+        assert_eq!(mmu.access_gating(), *access_gating, "{:?}", profile);
+        assert_eq!(
+            mmu.ppu.borrow().sprite_limit(),
+            *sprite_limit,
+            "{:?}",
+            profile
+        );
+    }
+}
+
+#[test]
+fn options_are_kept_when_a_rom_is_loaded() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    emulator.set_accuracy_profile(AccuracyProfile::Fast);
+    emulator.mmu.borrow_mut().set_access_gating(true);
+    emulator.set_compat_colorization(true);
+    emulator.cpu.set_access_logging(true);
+    emulator.cpu.core.stack_guard = Some(StackGuard {
+        floor: 0xc000,
+        action: StackGuardAction::Break,
+    });
+    emulator.load_rom(rom_with_program(&SPIN)).unwrap();
+    emulator.reset();
+
+    assert!(emulator.cpu.access_logging());
+    assert!(emulator.cpu.decode_cache().is_some());
+    assert_eq!(emulator.cpu.core.stack_guard.unwrap().floor, 0xc000);
+    assert!(emulator.idle_loop_skip());
+    let mmu = emulator.mmu.borrow();
+    assert!(mmu.access_gating());
+    assert_eq!(mmu.ppu.borrow().sprite_limit(), None);
+    assert!(mmu.ppu.borrow().compat_palette().is_some());
+}
+
+#[test]
+fn stack_guard_breaks_below_floor() {
+    // push bc, jr -3
+    let mut emulator = Emulator::new(None, rom_with_program(&[0xc5, 0x18, 0xfd]));
+    emulator.cpu.core.stack_guard = Some(StackGuard {
+        floor: 0xff80,
+        action: StackGuardAction::Break,
+    });
+    for _ in 0..1000 {
+        if emulator.is_paused() {
+            break;
+        }
+        emulator.tick();
+    }
+    assert!(emulator.is_paused());
+    assert_eq!(emulator.cpu.core.registers.sp, 0xff7e);
+}
+
+#[test]
+fn emulator_without_rom_shows_no_rom_screen() {
+    let mut emulator = Emulator::without_rom(None);
+    assert!(!emulator.has_rom());
+    assert_eq!(emulator.tick(), 0);
+    assert!(emulator.mmu.borrow().ppu.borrow().framebuffer[..] == no_rom_framebuffer()[..]);
+}
+
+#[test]
+fn compat_colorization_picks_palette_by_title() {
+    let mut rom = rom_with_program(&SPIN);
+    rom[0x134..0x13a].copy_from_slice(b"TETRIS");
+    let mut emulator = Emulator::new(None, rom);
+    emulator.set_compat_colorization(true);
+    let palette = emulator.mmu.borrow().ppu.borrow().compat_palette().unwrap();
+    assert_eq!(palette, compat_palette("TETRIS"));
+    assert_ne!(palette, DEFAULT_COMPAT_PALETTE);
+    assert_eq!(compat_palette("UNKNOWN GAME"), DEFAULT_COMPAT_PALETTE);
+    emulator.set_compat_colorization(false);
+    assert!(emulator
+        .mmu
+        .borrow()
+        .ppu
+        .borrow()
+        .compat_palette()
+        .is_none());
+}
+
+#[test]
+fn step_synchronized_advances_ppu_by_consumed_cycles() {
+    // A rom full of nops
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    let ly = |emulator: &Emulator| emulator.mmu.borrow().get(0xff44) as u32;
+    let start_ly = ly(&emulator);
+    let mut cycles = 0;
+    for _ in 0..1000 {
+        let step_cycles = emulator.step_synchronized();
+        assert_eq!(step_cycles, 4);
+        cycles += step_cycles;
+        // The first line after the lcd is enabled is 4 dots short
+        assert_eq!(ly(&emulator), start_ly + (cycles + 4) / 456);
+    }
+}
+
+static SLEEPS: AtomicU64 = AtomicU64::new(0);
+
+fn mock_sleep(_: Duration) {
+    SLEEPS.fetch_add(1, Ordering::SeqCst);
+}
+
+// Number of times the cpu waited for real time until the boot rom handed off to the cartridge
+fn boot_sleeps(fast_boot: bool) -> u64 {
+    // ld bc, $2000; dec bc; ld a, b; or c; jr nz, -5; ld a, 1; ldh ($50), a
+    let mut boot_rom = vec![
+        0x01, 0x00, 0x20, 0x0b, 0x78, 0xb1, 0x20, 0xfb, 0x3e, 0x01, 0xe0, 0x50,
+    ];
+    boot_rom.resize(0x100, 0x00);
+    let mut emulator = Emulator::new(Some(boot_rom), rom_with_program(&SPIN));
+    emulator.cpu.set_sleep(mock_sleep);
+    emulator.set_fast_boot(fast_boot);
+    SLEEPS.store(0, Ordering::SeqCst);
+    while emulator.cpu.core.registers.pc != 0x0100 {
+        emulator.tick();
+    }
+    assert!(emulator.cpu.is_throttled());
+    SLEEPS.load(Ordering::SeqCst)
+}
+
+#[test]
+fn fast_boot_skips_throttling_until_hand_off() {
+    // The sleeps are counted rather than timed, a slow host sleeps for 0ms
+    let throttled = boot_sleeps(false);
+    let fast = boot_sleeps(true);
+    assert!(throttled >= 1, "throttled boot slept {} times", throttled);
+    assert_eq!(fast, 0);
+}
+
+#[test]
+fn joypad_state_is_read_through_p1() {
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x00]));
+    // Left and Down pressed
+    emulator.set_joypad_state(0x0f, 0x05);
+    assert_eq!(emulator.joypad_state(), (0x0f, 0x05));
+    let mut mmu = emulator.mmu.borrow_mut();
+    mmu.set(0xff00, 0x20);
+    assert_eq!(mmu.get(0xff00), 0xe5);
+}
+
+// Run until the vblank interrupt is dispatched, returns the steps and cycles it took
+fn run_to_vblank_interrupt(emulator: &mut Emulator) -> (u32, u32) {
+    let (mut steps, mut cycles) = (0, 0);
+    while emulator.cpu.core.registers.pc != 0x0040 {
+        cycles += emulator.step();
+        steps += 1;
+    }
+    (steps, cycles)
+}
+
+#[test]
+fn idle_loop_skip_jumps_to_vblank() {
+    // ld a, $01; ldh ($ff), a; ei; jr -2
+    let mut rom = rom_with_program(&[0x3e, 0x01, 0xe0, 0xff, 0xfb, 0x18, 0xfe]);
+    // reti
+    rom[0x40] = 0xd9;
+    let mut emulators = [Emulator::new(None, rom.clone()), Emulator::new(None, rom)];
+    emulators[1].set_idle_loop_skip(true);
+    let mut results = Vec::new();
+    for emulator in emulators.iter_mut() {
+        emulator.cpu.set_throttled(false);
+        let (steps, cycles) = run_to_vblank_interrupt(emulator);
+        let mmu = emulator.mmu.borrow();
+        let ly = mmu.get(0xff44);
+        let div = mmu.get(0xff04);
+        results.push((steps, cycles, emulator.cpu.core.registers, ly, div));
+    }
+    let (skipped, executed) = (&results[1], &results[0]);
+    assert_eq!(
+        (skipped.1, skipped.2, skipped.3, skipped.4),
+        (executed.1, executed.2, executed.3, executed.4)
+    );
+    assert_eq!(skipped.3, 144);
+    assert!(skipped.0 < 10);
+    assert!(executed.0 > 1000);
+}
+
+#[test]
+fn state_dump_has_every_section() {
+    // ld a, $42; nop
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x3e, 0x42, 0x00]));
+    emulator.step();
+    emulator.step();
+    let dump = emulator.state_dump();
+    let sections: Vec<&str> = dump.lines().filter(|line| line.starts_with('[')).collect();
+    assert_eq!(
+        sections,
+        vec!["[cpu]", "[ppu]", "[interrupts]", "[banks]", "[trace]"]
+    );
+    assert!(dump.contains("AF: 42b0"));
+    assert!(dump.contains("PC: 0103"));
+    assert!(dump.contains("rom: 01 ram: 00 wram: 01 vram: 00"));
+    assert!(dump.ends_with("[trace]\n$0100: 3e\n$0102: 00\n"));
+}
+
+#[test]
+fn run_until_stable_stops_on_unchanged_screen() {
+    // jr -2, the screen never changes
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x18, 0xfe]));
+    assert_eq!(emulator.run_until_stable(60, 3), 4);
+    assert!(emulator.should_refresh_screen());
+}
+
+#[test]
+fn skipped_boot_rom_leaves_the_post_boot_palettes() {
+    let emulator = Emulator::new(None, rom_with_program(&SPIN));
+    let mmu = emulator.mmu.borrow();
+    assert_eq!(mmu.get(0xff47), 0xfc);
+    assert_eq!(mmu.get(0xff48), 0xff);
+    assert_eq!(mmu.get(0xff49), 0xff);
+
+    // 0xfc maps color 0 to white and the other colors to black
+    let mut ppu = mmu.ppu.borrow_mut();
+    ppu.set(0x8000, 0b1010_0000);
+    ppu.set(0x8001, 0b0110_0000);
+    ppu.render_line(0);
+    let white = ppu.framebuffer[0][3];
+    let black = ppu.framebuffer[0][0];
+    assert_ne!(white, black);
+    assert_eq!(ppu.framebuffer[0][1], black);
+    assert_eq!(ppu.framebuffer[0][2], black);
+}
+
+#[test]
+fn step_frame_holds_the_action_for_one_frame() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    let result = emulator.step_frame(JoyPadKey::A as u8 | JoyPadKey::Right as u8);
+    assert_eq!(result.frame_number, 1);
+    assert_eq!(emulator.joypad_state(), (0x0e, 0x0e));
+    assert_eq!(emulator.mmu.borrow().get(0xff44), 144);
+    assert!(result.framebuffer[..] == emulator.mmu.borrow().ppu.borrow().front_buffer()[..]);
+
+    let result = emulator.step_frame(0x00);
+    assert_eq!(result.frame_number, 2);
+    assert_eq!(emulator.joypad_state(), (0x0f, 0x0f));
+    assert_eq!(emulator.mmu.borrow().get(0xff44), 144);
+}
+
+#[test]
+fn read_memory_sees_work_and_high_ram() {
+    // ld a, $2a; ld ($c123), a; ldh ($90), a
+    let mut emulator = Emulator::new(
+        None,
+        rom_with_program(&[0x3e, 0x2a, 0xea, 0x23, 0xc1, 0xe0, 0x90]),
+    );
+    for _ in 0..3 {
+        emulator.step_synchronized();
+    }
+    assert_eq!(emulator.read_memory(0xc123), 0x2a);
+    assert_eq!(emulator.read_memory(0xff90), 0x2a);
+    assert_eq!(
+        emulator.read_memory_range(0xc122, 3),
+        vec![0x00, 0x2a, 0x00]
+    );
+}
+
+static UNTHROTTLED_SLEEPS: AtomicU64 = AtomicU64::new(0);
+
+fn count_unthrottled_sleep(_: Duration) {
+    UNTHROTTLED_SLEEPS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn unthrottled_cpu_never_waits() {
+    assert!(unthrottled_env(Some("1")));
+    assert!(!unthrottled_env(Some("0")));
+    assert!(!unthrottled_env(None));
+
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    emulator.cpu.set_sleep(count_unthrottled_sleep);
+    emulator.set_unthrottled(true);
+    // Fast boot ending does not bring the throttling back
+    emulator.set_fast_boot(false);
+    assert!(!emulator.cpu.is_throttled());
+    let mut cycles = 0;
+    while cycles < STEP_CYCLES * 4 {
+        cycles += emulator.tick();
+    }
+    assert_eq!(UNTHROTTLED_SLEEPS.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn step_t_cycles_advances_the_ppu_by_the_cycles() {
+    // The rom is filled with nops
+    let mut emulator = Emulator::new(None, rom_with_program(&[]));
+    let dot = emulator.mmu.borrow().ppu.borrow().dot();
+    emulator.step_t_cycles(4);
+    assert_eq!(emulator.mmu.borrow().ppu.borrow().dot(), dot + 4);
+    assert_eq!(emulator.cpu.instruction_count(), 1);
+
+    // Half of a nop runs the whole instruction, the other half only advances the machine
+    emulator.step_t_cycles(2);
+    assert_eq!(emulator.mmu.borrow().ppu.borrow().dot(), dot + 6);
+    assert_eq!(emulator.cpu.instruction_count(), 2);
+    emulator.step_t_cycles(2);
+    assert_eq!(emulator.mmu.borrow().ppu.borrow().dot(), dot + 8);
+    assert_eq!(emulator.cpu.instruction_count(), 2);
+}
+
+#[test]
+fn only_enabled_cheats_patch_reads() {
+    let mut program = vec![0x00; 0x60];
+    program[0x50] = 0x11;
+    program[0x51] = 0x22;
+    let mut emulator = Emulator::new(None, rom_with_program(&program));
+    // Game Genie codes replacing 0x0150 and 0x0151
+    emulator.add_cheat("421-50F").unwrap();
+    emulator.add_cheat("431-51F-6E2").unwrap();
+    let cheats = emulator.cheats();
+    assert_eq!((cheats[0].address, cheats[0].value), (0x0150, 0x42));
+    assert_eq!(cheats[1].compare, Some(0x22));
+    assert_eq!(emulator.read_memory(0x0150), 0x42);
+
+    emulator.set_cheat_enabled(0, false).unwrap();
+    assert!(!emulator.cheats()[0].enabled);
+    assert!(emulator.set_cheat_enabled(2, true).is_err());
+    assert_eq!(emulator.read_memory(0x0150), 0x11);
+    assert_eq!(emulator.read_memory(0x0151), 0x43);
+    assert!(emulator.add_cheat("xyz").is_err());
+}
+
+#[test]
+fn state_hash_is_deterministic() {
+    let mut first = Emulator::new(None, rom_with_program(&SPIN));
+    let mut second = Emulator::new(None, rom_with_program(&SPIN));
+    assert_eq!(first.state_hash(), second.state_hash());
+    first.step_frame(0);
+    assert_ne!(first.state_hash(), second.state_hash());
+    second.step_frame(0);
+    assert_eq!(first.state_hash(), second.state_hash());
+}
+
+#[test]
+#[ignore]
+fn simulated_boot_matches_the_boot_rom_handoff() {
+    // Needs a dmg boot rom dump, run with cargo test -- --ignored
+    let boot_rom = match std::fs::read("res/dmg_boot.bin") {
+        Ok(boot_rom) => boot_rom,
+        Err(_) => return,
+    };
+    // The boot rom locks up unless the logo and the header checksum are valid
+    let mut rom = rom_with_program(&SPIN);
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x14d] = header_checksum(&rom);
+    let booted = Emulator::new(Some(boot_rom), rom.clone()).run_boot_rom(600);
+    let simulated = Emulator::new(None, rom).handoff_state();
+    assert_eq!(booted.registers, simulated.registers);
+    assert_eq!(booted.ime, simulated.ime);
+    assert_eq!(booted.io_registers, simulated.io_registers);
+    assert_eq!(booted.interrupt_enabled, simulated.interrupt_enabled);
+    assert_eq!(booted.ppu, simulated.ppu);
+}
+
+#[test]
+fn invalid_roms_keep_the_current_rom_running() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    emulator.step_frame(0);
+    let pc = emulator.cpu.core.registers.pc;
+    let events = emulator.event_log().events().len();
+    let mut garbage = vec![0x5a; 0x8000];
+    garbage[0x149] = 0x00;
+    assert_eq!(
+        emulator.load_rom(garbage),
+        Err(CartridgeError::UnknownType(0x5a))
+    );
+    assert!(emulator.has_rom());
+    assert_eq!(emulator.cpu.core.registers.pc, pc);
+    assert_eq!(emulator.event_log().events().len(), events);
+}
+
+#[test]
+fn event_log_records_loads_and_resets_in_order() {
+    let mut rom = rom_with_program(&SPIN);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    let mut emulator = Emulator::without_rom(None);
+    assert!(emulator.event_log().events().is_empty());
+    emulator.load_rom(rom).unwrap();
+    emulator.step_frame(0);
+    emulator.reset();
+    // An illegal opcode locks up the cpu
+    emulator.cpu.core.registers.pc = 0xc000;
+    emulator.mmu.borrow_mut().set(0xc000, 0xd3);
+    emulator.step_synchronized();
+    emulator.step_synchronized();
+
+    let events: Vec<Event> = emulator
+        .event_log()
+        .events()
+        .iter()
+        .map(|logged| logged.event.clone())
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            Event::RomLoaded(String::from("TEST")),
+            Event::Reset,
+            Event::IllegalOpcode(0xc000, 0xd3)
+        ]
+    );
+    assert_eq!(emulator.event_log().events()[1].cycle, 0);
+    assert_eq!(emulator.cpu.core.registers.pc, 0xc001);
+}
+
+#[test]
+fn allow_socd_reads_both_opposing_directions() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    emulator.mmu.borrow_mut().set(0xff00, 0x20);
+    // Left and right pressed
+    emulator.set_joypad_state(0x0f, 0x0c);
+    assert_eq!(emulator.read_memory(0xff00) & 0x0f, 0x0c);
+
+    emulator.set_socd_mode(SocdMode::Neutral);
+    assert_eq!(emulator.read_memory(0xff00) & 0x0f, 0x0f);
+    // The mode is kept for the next rom
+    emulator.load_rom(rom_with_program(&SPIN)).unwrap();
+    assert_eq!(emulator.mmu.borrow().joypad.socd_mode(), SocdMode::Neutral);
+}
+
+static SPEED_SLEEPS: AtomicU64 = AtomicU64::new(0);
+static LONGEST_SLEEP: AtomicU64 = AtomicU64::new(0);
+
+fn record_longest_sleep(duration: Duration) {
+    SPEED_SLEEPS.fetch_add(1, Ordering::SeqCst);
+    LONGEST_SLEEP.fetch_max(duration.as_micros() as u64, Ordering::SeqCst);
+}
+
+#[test]
+fn speed_scales_the_throttled_step_time() {
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    emulator.set_speed(2.0);
+    assert_eq!(emulator.cpu.speed(), 2.0);
+    emulator.cpu.set_sleep(record_longest_sleep);
+    emulator.cpu.set_throttled(true);
+    // A 16ms step of the Game Boy is waited for in 8ms at twice the speed
+    while emulator.cpu.cycle_count() < 4 * u64::from(STEP_CYCLES) {
+        emulator.tick();
+    }
+    assert!(SPEED_SLEEPS.load(Ordering::SeqCst) > 0);
+    let longest = LONGEST_SLEEP.load(Ordering::SeqCst);
+    assert!(longest <= 8_000, "{}", longest);
+
+    emulator.load_rom(rom_with_program(&SPIN)).unwrap();
+    assert_eq!(emulator.cpu.speed(), 2.0);
+}
+
+#[test]
+fn with_memory_runs_the_cpu_over_the_given_memory() {
+    let memory = Rc::new(RefCell::new(FlatMemory([0x00; 0x10000])));
+    // ld a, $42; ld ($c000), a; inc a
+    let program = [0x3e, 0x42, 0xea, 0x00, 0xc0, 0x3c];
+    memory.borrow_mut().0[0x100..0x106].copy_from_slice(&program);
+    let mut emulator = Emulator::with_memory(memory.clone());
+    for _ in 0..3 {
+        emulator.step();
+    }
+    assert_eq!(memory.borrow().0[0xc000], 0x42);
+    assert_eq!(emulator.cpu.core.registers.a, 0x43);
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0106);
+}
+
+#[test]
+fn breakpoint_stops_step_frame() {
+    // inc b; jr -3
+    let mut emulator = Emulator::new(None, rom_with_program(&[0x04, 0x18, 0xfd]));
+    let frames = Rc::new(Cell::new(0));
+    let counter = frames.clone();
+    emulator.set_vblank_callback(Box::new(move |_| counter.set(counter.get() + 1)));
+    emulator
+        .debug_command(DebugCommand::ConditionalBreakpoint {
+            pc: 0x0101,
+            condition: String::from("B == 0x10"),
+        })
+        .unwrap();
+    let result = emulator.step_frame(0x00);
+    assert!(result.stopped);
+    assert!(emulator.is_paused());
+    assert_eq!(emulator.cpu.core.registers.pc, 0x0101);
+    assert_eq!(emulator.cpu.core.registers.b, 0x10);
+    assert_eq!(result.frame_number, 0);
+
+    assert_eq!(frames.get(), 0);
+
+    // The vblank callback runs when frame stepping too
+    let mut emulator = Emulator::new(None, rom_with_program(&SPIN));
+    let counter = frames.clone();
+    emulator.set_vblank_callback(Box::new(move |_| counter.set(counter.get() + 1)));
+    let result = emulator.step_frame(0x00);
+    assert!(!result.stopped);
+    assert_eq!(result.frame_number, 1);
+    assert_eq!(frames.get(), 1);
+}
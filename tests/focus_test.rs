@@ -0,0 +1,25 @@
+use rgb::emulator::control::ControlMessage;
+use rgb::input::focus::FocusPause;
+
+#[test]
+fn focus_loss_pauses_until_focus_returns() {
+    let mut focus_pause = FocusPause::new(true);
+    assert!(matches!(
+        focus_pause.focus_changed(false),
+        Some(ControlMessage::PAUSE)
+    ));
+    assert!(focus_pause.focus_changed(false).is_none());
+    assert!(matches!(
+        focus_pause.focus_changed(true),
+        Some(ControlMessage::RESUME)
+    ));
+    assert!(focus_pause.focus_changed(true).is_none());
+
+    // A pause by the user is not undone by the focus
+    focus_pause.set_user_paused(true);
+    assert!(focus_pause.focus_changed(false).is_none());
+    assert!(focus_pause.focus_changed(true).is_none());
+
+    let mut disabled = FocusPause::new(false);
+    assert!(disabled.focus_changed(false).is_none());
+}
@@ -0,0 +1,48 @@
+use rgb::cpu::interrupt::InterruptFlags;
+use rgb::input::joypad::{JoyPad, JoyPadKey, SocdMode};
+use rgb::memory::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn joypad_with_directions() -> JoyPad {
+    let mut joypad = JoyPad::new(Rc::new(RefCell::new(InterruptFlags::new())));
+    // Select the direction line
+    joypad.set(0xff00, 0x20);
+    joypad
+}
+
+#[test]
+fn opposing_directions_follow_socd_mode() {
+    for (socd_mode, expected) in [
+        (SocdMode::Neutral, 0x0f),
+        (SocdMode::LastWins, 0x0e),
+        (SocdMode::Allow, 0x0c),
+    ]
+    .iter()
+    {
+        let mut joypad = joypad_with_directions();
+        joypad.set_socd_mode(*socd_mode);
+        joypad.key_down(JoyPadKey::Left);
+        joypad.key_down(JoyPadKey::Right);
+        assert_eq!(joypad.get(0xff00) & 0x0f, *expected, "{:?}", socd_mode);
+    }
+}
+
+#[test]
+fn p1_reads_selected_lines() {
+    let mut joypad = JoyPad::new(Rc::new(RefCell::new(InterruptFlags::new())));
+    joypad.key_down(JoyPadKey::Left);
+    joypad.key_down(JoyPadKey::Start);
+    // No line selected, the select bits read back as written
+    joypad.set(0xff00, 0x30);
+    assert_eq!(joypad.get(0xff00), 0xff);
+    // Directions
+    joypad.set(0xff00, 0x20);
+    assert_eq!(joypad.get(0xff00), 0xed);
+    // Buttons
+    joypad.set(0xff00, 0x10);
+    assert_eq!(joypad.get(0xff00), 0xd7);
+    // Both lines
+    joypad.set(0xff00, 0x00);
+    assert_eq!(joypad.get(0xff00), 0xc5);
+}
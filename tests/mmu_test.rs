@@ -0,0 +1,204 @@
+mod common;
+
+use common::{cartridge_rom, rom_with_program};
+use rgb::cartridge::load_cartridge;
+use rgb::memory::mmu::{IoRegisterHandling, MMU};
+use rgb::memory::serial::serial_transfer_cycles;
+use rgb::memory::{Memory, OPEN_BUS};
+use rgb::ppu::{Mode, SpritePriority};
+
+#[test]
+fn unmapped_reads_return_open_bus() {
+    let mmu = MMU::new(None, rom_with_program(&[]));
+    // Unused io registers
+    assert_eq!(mmu.get(0xff03), OPEN_BUS);
+    assert_eq!(mmu.get(0xff08), OPEN_BUS);
+    assert_eq!(mmu.get(0xff4c), OPEN_BUS);
+    assert_eq!(mmu.get(0xff7f), OPEN_BUS);
+    // No external ram on a rom only cartridge
+    assert_eq!(mmu.get(0xa000), OPEN_BUS);
+}
+
+#[test]
+fn disabled_cartridge_ram_reads_open_bus() {
+    let cartridge = load_cartridge(cartridge_rom(0x01, 0x00, 0x02)).unwrap();
+    assert_eq!(cartridge.get(0xa000), OPEN_BUS);
+}
+
+#[test]
+fn implemented_io_register_count() {
+    let implemented = MMU::io_registers()
+        .into_iter()
+        .filter(|(_, handling)| *handling == IoRegisterHandling::Implemented)
+        .count();
+    assert_eq!(implemented, 28);
+    assert!(MMU::io_register_report().contains("stubbed: $ff10"));
+}
+
+#[test]
+fn load_region_renders_background_tile() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    mmu.simulate_boot_rom();
+    // Tile 1 is fully colored with color 3, tile map points the first tile to it
+    mmu.load_region(0x8010, &[0xff; 16]);
+    mmu.load_region(0x9800, &[0x01]);
+    for _ in 0..(456 / 4) {
+        mmu.tick(4);
+    }
+    let framebuffer = mmu.ppu.borrow().framebuffer;
+    assert_eq!(framebuffer[0][0], [8, 24, 32]);
+    assert_eq!(framebuffer[0][7], [8, 24, 32]);
+    assert_eq!(framebuffer[0][8], [254, 248, 208]);
+}
+
+#[test]
+fn internal_clock_serial_transfer_raises_interrupt() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    mmu.simulate_boot_rom();
+    mmu.set(0xff0f, 0x00);
+    mmu.set(0xff01, 0x42);
+    mmu.set(0xff02, 0x81);
+    // 8 bits at 512 cycles each
+    mmu.tick(4092);
+    assert_eq!(mmu.get(0xff0f) & 0x08, 0x00);
+    assert_eq!(mmu.get(0xff02) & 0x80, 0x80);
+    mmu.tick(4);
+    assert_eq!(mmu.get(0xff0f) & 0x08, 0x08);
+    assert_eq!(mmu.get(0xff02) & 0x80, 0x00);
+    assert_eq!(mmu.get(0xff01), 0xff);
+}
+
+#[test]
+fn echo_ram_ends_where_oam_begins() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    // The last echo address mirrors work ram
+    mmu.set(0xfdff, 0x12);
+    assert_eq!(mmu.get(0xddff), 0x12);
+    mmu.set(0xddff, 0x34);
+    assert_eq!(mmu.get(0xfdff), 0x34);
+    // The next one is the first oam byte
+    mmu.set(0xfe00, 0x56);
+    assert_eq!(mmu.ppu.borrow().get(0xfe00), 0x56);
+    assert_eq!(mmu.get(0xfe00), 0x56);
+    assert_eq!(mmu.get(0xde00), 0x00);
+}
+
+#[test]
+fn any_access_is_defined_for_every_mapper() {
+    // Rom only, MBC1+RAM and MBC3+TIMER+RAM+BATTERY with a small rom so bank writes go past its end
+    for cartridge_type in [0x00, 0x02, 0x10].iter() {
+        let mut mmu = MMU::new(None, cartridge_rom(*cartridge_type, 0x00, 0x01));
+        for (round, pattern) in [0x00u8, 0xff, 0x55, 0xaa, 0x0a, 0x7f].iter().enumerate() {
+            for address in 0x0000..=0xffffu16 {
+                let value = pattern ^ (address as u8).wrapping_mul(round as u8);
+                mmu.set(address, value);
+                mmu.get(address);
+            }
+            for address in 0x0000..=0xffffu16 {
+                mmu.get(address);
+            }
+        }
+    }
+}
+
+#[test]
+fn serial_transfer_cycles_per_clock() {
+    assert_eq!(serial_transfer_cycles(true, false), Some(4096));
+    assert_eq!(serial_transfer_cycles(true, true), Some(128));
+    assert_eq!(serial_transfer_cycles(false, false), None);
+    assert_eq!(serial_transfer_cycles(false, true), None);
+}
+
+#[test]
+fn opri_selects_the_sprite_priority_mode() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    // Only CGB has the register
+    assert_eq!(mmu.get(0xff6c), 0xff);
+    mmu.ppu.borrow_mut().set_cgb_mode(true);
+    assert_eq!(mmu.get(0xff6c), 0xfe);
+
+    // Sprite 0 comes first in OAM, sprite 1 has the smaller x position
+    mmu.set(0xfe00, 16);
+    mmu.set(0xfe01, 18);
+    mmu.set(0xfe04, 16);
+    mmu.set(0xfe05, 14);
+    let first_on_line = |mmu: &MMU| mmu.ppu.borrow().sprites_on_line(0)[0].index();
+    assert_eq!(first_on_line(&mmu), 0);
+
+    mmu.set(0xff6c, 0x01);
+    assert_eq!(mmu.get(0xff6c), 0xff);
+    assert_eq!(mmu.ppu.borrow().sprite_priority_mode(), SpritePriority::Dmg);
+    assert_eq!(first_on_line(&mmu), 1);
+}
+
+#[test]
+fn write_only_bits_read_as_one() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    mmu.simulate_boot_rom();
+    // NR13 is write only, NR11 only returns the duty
+    mmu.set(0xff13, 0x12);
+    assert_eq!(mmu.get(0xff13), 0xff);
+    mmu.set(0xff11, 0x80);
+    assert_eq!(mmu.get(0xff11), 0xbf);
+    // Unused bits of TAC, IF and STAT
+    mmu.set(0xff07, 0x05);
+    assert_eq!(mmu.get(0xff07), 0xfd);
+    mmu.set(0xff0f, 0x01);
+    assert_eq!(mmu.get(0xff0f), 0xe1);
+    assert_eq!(mmu.get(0xff41) & 0x80, 0x80);
+    // DMA returns the last source written
+    mmu.set(0xff46, 0xc1);
+    assert_eq!(mmu.get(0xff46), 0xc1);
+}
+
+#[test]
+fn gated_vram_reads_do_not_blank_rendering() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    mmu.simulate_boot_rom();
+    mmu.load_region(0x8010, &[0xff; 16]);
+    mmu.load_region(0x9800, &[0x01]);
+    mmu.set_access_gating(true);
+    // Into mode 3 of the first line
+    mmu.tick(84);
+    assert_eq!(mmu.ppu.borrow().mode, Mode::VRAMRead);
+    assert_eq!(mmu.get(0x8010), 0xff);
+    assert_eq!(mmu.get(0x9800), 0xff);
+    for _ in 0..(456 / 4) {
+        mmu.tick(4);
+    }
+    let framebuffer = mmu.ppu.borrow().framebuffer;
+    assert_eq!(framebuffer[0][0], [8, 24, 32]);
+    assert_eq!(framebuffer[0][8], [254, 248, 208]);
+}
+
+#[test]
+fn logo_region_writes_are_reported_and_ignored() {
+    // MBC1
+    let mut mmu = MMU::new(None, cartridge_rom(0x01, 0x00, 0x00));
+    let logo = mmu.get(0x0104);
+    mmu.set(0x0104, 0x12);
+    assert!(mmu.logo_writes().is_empty());
+
+    mmu.set_logo_write_diagnostic(true);
+    mmu.set(0x0104, 0x34);
+    mmu.set(0x0134, 0x56);
+    assert_eq!(mmu.logo_writes(), &[(0x0104, 0x34)]);
+    assert_eq!(mmu.get(0x0104), logo);
+}
+
+#[test]
+fn lcd_off_leaves_vram_and_oam_accessible() {
+    let mut mmu = MMU::new(None, rom_with_program(&[]));
+    mmu.set_access_gating(true);
+    mmu.set(0xff40, 0x00);
+    // A mode left behind, like after restoring a state, does not gate the accesses
+    mmu.ppu.borrow_mut().mode = Mode::VRAMRead;
+    mmu.set(0x8000, 0x12);
+    mmu.set(0xfe00, 0x34);
+    assert_eq!(mmu.get(0x8000), 0x12);
+    assert_eq!(mmu.get(0xfe00), 0x34);
+
+    mmu.set(0xff40, 0x80);
+    mmu.ppu.borrow_mut().mode = Mode::VRAMRead;
+    assert_eq!(mmu.get(0x8000), OPEN_BUS);
+}
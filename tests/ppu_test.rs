@@ -0,0 +1,472 @@
+use rgb::cpu::interrupt::InterruptFlags;
+use rgb::memory::Memory;
+use rgb::ppu::snapshot::PPUSnapshot;
+use rgb::ppu::{Mode, PPUFramebuffer, SpritePriority, PPU};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn new_ppu() -> PPU {
+    let mut ppu = PPU::new(Rc::new(RefCell::new(InterruptFlags::new())));
+    // Display and background enabled, tile data at 0x8000, tile map at 0x9800
+    ppu.set(0xff40, 0x91);
+    ppu
+}
+
+// Run the ppu for the given number of scanlines
+fn run_lines(ppu: &mut PPU, lines: u32) {
+    for _ in 0..(lines * 456 / 4) {
+        ppu.tick(4);
+    }
+}
+
+#[test]
+fn cgb_tile_attributes_apply_palette_and_flip() {
+    let mut ppu = new_ppu();
+    ppu.set_cgb_mode(true);
+    // Palette 3 with auto increment: color 0 black, color 1 red
+    ppu.set(0xff68, 0x80 | (3 * 8));
+    for byte in [0x00, 0x00, 0x1f, 0x00].iter() {
+        ppu.set(0xff69, *byte);
+    }
+    // Tile 1 only has the leftmost pixel set to color 1
+    ppu.set(0x8010, 0b1000_0000);
+    ppu.set(0x9800, 0x01);
+    // Attributes in bank 1, palette 3 and horizontal flip
+    ppu.video_ram[1][0x1800] = 0x03 | 0x20;
+
+    run_lines(&mut ppu, 1);
+    assert_eq!(ppu.framebuffer[0][0], [0, 0, 0]);
+    assert_eq!(ppu.framebuffer[0][7], [255, 0, 0]);
+}
+
+#[test]
+fn vram_bank_select_routes_cpu_access() {
+    let mut ppu = new_ppu();
+    // VBK does not exist on DMG, the writes stay in bank 0
+    ppu.set(0xff4f, 0x01);
+    assert_eq!(ppu.get(0xff4f), 0xff);
+    ppu.set(0x8000, 0x55);
+    assert_eq!(ppu.vram_bytes(0)[0], 0x55);
+    ppu.set(0x8000, 0x00);
+
+    ppu.set_cgb_mode(true);
+    ppu.set(0xff4f, 0x01);
+    ppu.set(0x8000, 0xaa);
+    assert_eq!(ppu.get(0xff4f), 0xff);
+
+    ppu.set(0xff4f, 0x00);
+    assert_eq!(ppu.get(0xff4f), 0xfe);
+    assert_eq!(ppu.get(0x8000), 0x00);
+    assert_eq!(ppu.tile_set[0][0][0][0], 0);
+
+    ppu.set(0xff4f, 0x01);
+    assert_eq!(ppu.get(0x8000), 0xaa);
+    assert_eq!(ppu.tile_set[1][0][0][0], 1);
+}
+
+// Write a sprite into OAM at the given screen position
+fn set_sprite(ppu: &mut PPU, index: u16, x: u8, y: u8) {
+    let address = 0xfe00 + index * 4;
+    ppu.set(address, y + 16);
+    ppu.set(address + 1, x + 8);
+    ppu.set(address + 2, 0x00);
+    ppu.set(address + 3, 0x00);
+}
+
+#[test]
+fn sprites_on_line_selects_intersecting_sprites() {
+    let mut ppu = new_ppu();
+    // Sprites 0 to 11 are on line 20 with decreasing x, the rest are away from the line
+    for index in 0..12 {
+        set_sprite(&mut ppu, index, 100 - index as u8, 15);
+    }
+    for index in 12..40 {
+        set_sprite(&mut ppu, index, 0, 100);
+    }
+
+    let indexes: Vec<usize> = ppu.sprites_on_line(20).iter().map(|s| s.index()).collect();
+    assert_eq!(indexes, vec![9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+    assert_eq!(ppu.sprites_on_line(14).len(), 0);
+    assert_eq!(ppu.sprites_on_line(100).len(), 10);
+}
+
+#[test]
+fn to_ppm_writes_header_and_pixels() {
+    let mut ppu = new_ppu();
+    ppu.framebuffer[0][0] = [0x12, 0x34, 0x56];
+    let header = b"P6\n160 144\n255\n";
+    // The frame being drawn is not exported until it is presented
+    assert_eq!(&ppu.to_ppm()[header.len()..header.len() + 3], &[0x00; 3]);
+    ppu.present_frame();
+    let ppm = ppu.to_ppm();
+    assert_eq!(&ppm[..header.len()], &header[..]);
+    assert_eq!(ppm.len() - header.len(), 160 * 144 * 3);
+    assert_eq!(&ppm[header.len()..header.len() + 3], &[0x12, 0x34, 0x56]);
+}
+
+// Run the ppu for the given number of dots
+fn run_dots(ppu: &mut PPU, dots: u32) {
+    for _ in 0..(dots / 4) {
+        ppu.tick(4);
+    }
+}
+
+#[test]
+fn lcd_reenable_starts_with_a_short_first_line() {
+    let mut ppu = new_ppu();
+    run_lines(&mut ppu, 10);
+    run_dots(&mut ppu, 200);
+    ppu.set(0xff40, 0x11);
+    ppu.set(0xff40, 0x91);
+    assert_eq!(ppu.get(0xff44), 0);
+    // No OAM search on the first line
+    run_dots(&mut ppu, 40);
+    assert_eq!(ppu.mode, Mode::HBlank);
+    run_dots(&mut ppu, 60);
+    assert_eq!(ppu.mode, Mode::VRAMRead);
+    // The first line is 452 dots long
+    run_dots(&mut ppu, 348);
+    assert_eq!(ppu.get(0xff44), 0);
+    run_dots(&mut ppu, 4);
+    assert_eq!(ppu.get(0xff44), 1);
+    assert_eq!(ppu.mode, Mode::OAMRead);
+}
+
+#[test]
+fn render_line_uses_current_scroll() {
+    let mut ppu = new_ppu();
+    ppu.set(0xff47, 0xe4);
+    // Tile 1 has the leftmost pixel of every row set to color 3
+    for row in 0..8 {
+        ppu.set(0x8010 + row * 2, 0x80);
+        ppu.set(0x8011 + row * 2, 0x80);
+    }
+    ppu.set(0x9800, 0x01);
+    ppu.render_line(0);
+    ppu.set(0xff43, 0x01);
+    ppu.render_line(1);
+    assert_ne!(ppu.framebuffer[0][0], ppu.framebuffer[0][1]);
+    // Scrolled by one pixel, the colored pixel is out of the line
+    assert_eq!(ppu.framebuffer[1][0], ppu.framebuffer[0][1]);
+}
+
+#[test]
+fn sprite_limit_can_be_lifted() {
+    let mut ppu = new_ppu();
+    // Sprites enabled and drawn with tile 1, which is fully colored with color 3
+    ppu.set(0xff40, 0x93);
+    ppu.set(0xff48, 0xe4);
+    for address in 0x8010..0x8020 {
+        ppu.set(address, 0xff);
+    }
+    for index in 0..15 {
+        set_sprite(&mut ppu, index, index as u8 * 8, 0);
+        ppu.set(0xfe00 + index * 4 + 2, 0x01);
+    }
+    let drawn_pixels = |ppu: &PPU| {
+        let background = ppu.framebuffer[0][159];
+        ppu.framebuffer[0]
+            .iter()
+            .filter(|pixel| **pixel != background)
+            .count()
+    };
+
+    ppu.render_line(0);
+    assert_eq!(drawn_pixels(&ppu), 10 * 8);
+    ppu.set_sprite_limit(None);
+    ppu.render_line(0);
+    assert_eq!(drawn_pixels(&ppu), 15 * 8);
+}
+
+#[test]
+fn tiles_written_but_not_drawn_are_reported() {
+    let mut ppu = new_ppu();
+    ppu.set_tile_usage_diagnostic(true);
+    // The tile map only references tile 0, tile 5 is never drawn
+    ppu.set(0x8000, 0xff);
+    ppu.set(0x8050, 0xff);
+    run_lines(&mut ppu, 145);
+    assert_eq!(ppu.unused_tiles(), vec![(0, 5)]);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn full_background_places_tiles_at_map_offset() {
+    let mut ppu = new_ppu();
+    ppu.set(0xff47, 0xe4);
+    // Tile 1 is fully colored with color 3, at row 3 and column 31 of the map. The scroll is
+    // ignored
+    for address in 0x8010..0x8020 {
+        ppu.set(address, 0xff);
+    }
+    ppu.set(0x9800 + 3 * 32 + 31, 0x01);
+    ppu.set(0xff42, 0x40);
+    let background = ppu.render_full_background();
+    assert_eq!(background.dimensions(), (256, 256));
+    assert_eq!(background.get_pixel(248, 24).0, [8, 24, 32]);
+    assert_eq!(background.get_pixel(255, 31).0, [8, 24, 32]);
+    assert_eq!(background.get_pixel(247, 24).0, [254, 248, 208]);
+    assert_eq!(background.get_pixel(248, 32).0, [254, 248, 208]);
+}
+
+#[test]
+fn sprite_priority_mode_picks_overlap_winner() {
+    for (sprite_priority, expected) in [
+        (SpritePriority::Dmg, [136, 192, 112]),
+        (SpritePriority::Cgb, [8, 24, 32]),
+    ]
+    .iter()
+    {
+        let mut ppu = new_ppu();
+        // Objects enabled, palette 0 draws color 3 black, palette 1 light gray
+        ppu.set(0xff40, 0x93);
+        ppu.set(0xff48, 0xe4);
+        ppu.set(0xff49, 0x54);
+        for address in 0x8010..0x8020 {
+            ppu.set(address, 0xff);
+        }
+        // Sprite 0 comes first in OAM, sprite 1 has the smaller x position
+        set_sprite(&mut ppu, 0, 10, 0);
+        set_sprite(&mut ppu, 1, 6, 0);
+        ppu.set(0xfe02, 0x01);
+        ppu.set(0xfe06, 0x01);
+        ppu.set(0xfe07, 0x10);
+        ppu.set_sprite_priority_mode(*sprite_priority);
+        ppu.render_line(0);
+        assert_eq!(ppu.framebuffer[0][12], *expected, "{:?}", sprite_priority);
+    }
+}
+
+#[test]
+fn front_buffer_updates_only_at_vblank() {
+    let mut ppu = new_ppu();
+    ppu.set(0xff47, 0xe4);
+    for address in 0x8000..0x8010 {
+        ppu.set(address, 0xff);
+    }
+    // Half way through the frame the lines are drawn but not presented
+    run_lines(&mut ppu, 72);
+    assert_eq!(ppu.framebuffer[0][0], [8, 24, 32]);
+    assert_eq!(ppu.front_buffer()[0][0], [0, 0, 0]);
+    run_lines(&mut ppu, 73);
+    assert_eq!(ppu.mode, Mode::VBlank);
+    assert_eq!(ppu.front_buffer()[..], ppu.framebuffer[..]);
+}
+
+#[test]
+fn vram_bytes_returns_each_bank() {
+    let mut ppu = new_ppu();
+    assert_eq!(ppu.vram_bytes(0).len(), 0x2000);
+    assert!(ppu.vram_bytes(1).is_empty());
+
+    ppu.set_cgb_mode(true);
+    ppu.set(0x8000, 0x11);
+    ppu.set(0xff4f, 0x01);
+    ppu.set(0x8000, 0x22);
+    assert_eq!(ppu.vram_bytes(0)[0], 0x11);
+    assert_eq!(ppu.vram_bytes(1)[0], 0x22);
+    assert!(ppu.vram_bytes(2).is_empty());
+}
+
+#[test]
+fn scroll_and_window_accessors_match_registers() {
+    let mut ppu = new_ppu();
+    ppu.set_scroll(0x12, 0x34);
+    assert_eq!(ppu.get(0xff43), 0x12);
+    assert_eq!(ppu.get(0xff42), 0x34);
+    ppu.set_window_position(0x56, 0x78);
+    assert_eq!(ppu.get(0xff4b), 0x56);
+    assert_eq!(ppu.get(0xff4a), 0x78);
+
+    ppu.set(0xff43, 0x9a);
+    assert_eq!(ppu.scroll(), (0x9a, 0x34));
+    ppu.set(0xff4a, 0xbc);
+    assert_eq!(ppu.window_position(), (0x56, 0xbc));
+}
+
+// Render every line of the screen with the current state
+fn render_frame(ppu: &mut PPU) -> PPUFramebuffer {
+    for ly in 0..144 {
+        ppu.render_line(ly);
+    }
+    ppu.framebuffer
+}
+
+#[test]
+fn restore_renders_the_captured_frame() {
+    let mut ppu = new_ppu();
+    ppu.set(0xff47, 0xe4);
+    ppu.set(0x8010, 0xff);
+    ppu.set(0x9800, 0x01);
+    let expected = render_frame(&mut ppu);
+    let snapshot = ppu.capture();
+    assert_eq!(
+        PPUSnapshot::from_bytes(&snapshot.to_bytes()),
+        Some(snapshot.clone())
+    );
+
+    ppu.set(0x8010, 0x00);
+    ppu.set(0xff47, 0x1b);
+    assert_ne!(render_frame(&mut ppu), expected);
+
+    ppu.restore(&snapshot);
+    assert_eq!(render_frame(&mut ppu), expected);
+    assert_eq!(ppu.capture(), snapshot);
+}
+
+#[test]
+fn sprite_8x16_lsb_mask_selects_the_even_tile() {
+    let mut ppu = new_ppu();
+    // Background off, 8x16 sprites on
+    ppu.set(0xff40, 0x86);
+    ppu.set(0x8000, 0xf0);
+    ppu.set(0x8010, 0x0f);
+    set_sprite(&mut ppu, 0, 0, 0);
+    ppu.set(0xfe02, 0x01);
+
+    ppu.render_line(0);
+    let left = ppu.framebuffer[0][0];
+    let right = ppu.framebuffer[0][7];
+    assert_ne!(left, right);
+
+    ppu.set_8x16_lsb_mask(false);
+    ppu.render_line(0);
+    assert_eq!(ppu.framebuffer[0][0], right);
+    assert_eq!(ppu.framebuffer[0][7], left);
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn framebuffer_ndarray_is_rows_by_columns_by_channels() {
+    let mut ppu = new_ppu();
+    ppu.framebuffer[10][20] = [0x12, 0x34, 0x56];
+    // The frame being drawn is not published yet
+    assert_eq!(ppu.framebuffer_ndarray()[[10, 20, 0]], 0x00);
+    ppu.present_frame();
+    let array = ppu.framebuffer_ndarray();
+    assert_eq!(array.shape(), &[144, 160, 3]);
+    assert_eq!(array[[10, 20, 0]], 0x12);
+    assert_eq!(array[[10, 20, 2]], 0x56);
+}
+
+#[test]
+fn framebuffer_hash_changes_with_a_single_pixel() {
+    let mut first = new_ppu();
+    let mut second = new_ppu();
+    for ppu in [&mut first, &mut second].iter_mut() {
+        ppu.set(0x8010, 0xff);
+        ppu.set(0x9800, 0x01);
+        render_frame(ppu);
+        ppu.present_frame();
+    }
+    assert_eq!(first.framebuffer_hash(), second.framebuffer_hash());
+
+    second.framebuffer[143][159] = [0x00, 0x00, 0x00];
+    second.present_frame();
+    assert_ne!(first.framebuffer_hash(), second.framebuffer_hash());
+}
+
+#[test]
+fn cgb_master_priority_draws_sprites_over_priority_tiles() {
+    let mut ppu = new_ppu();
+    ppu.set_cgb_mode(true);
+    // Background color 3 white, sprite color 1 red
+    ppu.set(0xff68, 0x80 | 6);
+    ppu.set(0xff69, 0xff);
+    ppu.set(0xff69, 0x7f);
+    ppu.set(0xff6a, 0x80 | 2);
+    ppu.set(0xff6b, 0x1f);
+    ppu.set(0xff6b, 0x00);
+    // Tile 1 in color 3 with the priority attribute, sprite tile 2 in color 1
+    ppu.set(0x8010, 0xff);
+    ppu.set(0x8011, 0xff);
+    ppu.set(0x9800, 0x01);
+    ppu.set(0xff4f, 0x01);
+    ppu.set(0x9800, 0x80);
+    ppu.set(0xff4f, 0x00);
+    ppu.set(0x8020, 0xff);
+    set_sprite(&mut ppu, 0, 0, 0);
+    ppu.set(0xfe02, 0x02);
+
+    ppu.set(0xff40, 0x93);
+    ppu.render_line(0);
+    let background = ppu.framebuffer[0][0];
+
+    ppu.set(0xff40, 0x92);
+    ppu.render_line(0);
+    assert_ne!(ppu.framebuffer[0][0], background);
+}
+
+#[test]
+fn oam_entries_resolve_screen_rectangles() {
+    let mut ppu = new_ppu();
+    // 8x16 sprites
+    ppu.set(0xff40, 0x95);
+    for (offset, value) in [0x20, 0x04, 0x31, 0b1011_0000].iter().enumerate() {
+        ppu.set(0xfe04 + offset as u16, *value);
+    }
+    let entries = ppu.oam_entries();
+    assert_eq!(entries.len(), 40);
+    let entry = entries[1];
+    assert_eq!((entry.x, entry.y), (-4, 16));
+    assert_eq!((entry.width, entry.height), (8, 16));
+    assert_eq!((entry.tile, entry.palette), (0x31, 1));
+    assert!(entry.x_flip && !entry.y_flip && entry.behind_bg);
+    assert!(entry.on_screen());
+    // A zeroed entry is hidden above and left of the screen
+    assert!(!entries[0].on_screen());
+}
+
+#[test]
+fn palette_smoothing_blends_a_single_frame_flip() {
+    let white = [254, 248, 208];
+    let black = [8, 24, 32];
+    let mut ppu = new_ppu();
+    ppu.set_palette_smoothing(true);
+    // The background is color 0, white and then black for a single frame
+    for bgp in [0x00, 0x03, 0x00].iter() {
+        ppu.set(0xff47, *bgp);
+        run_lines(&mut ppu, 154);
+    }
+    let pixel = ppu.framebuffer[0][0];
+    assert!(pixel != white && pixel != black, "{:?}", pixel);
+
+    // Once the palette stays the same for two frames it is shown as it is
+    run_lines(&mut ppu, 154);
+    assert_eq!(ppu.framebuffer[0][0], white);
+}
+
+#[test]
+fn resync_caches_decodes_bulk_loaded_sprites() {
+    let mut ppu = new_ppu();
+    // Background, sprites, color 1 of OBP0 light gray
+    ppu.set(0xff40, 0x93);
+    ppu.set(0xff48, 0xe4);
+    // Tile 1 with a first row of color 1, used by sprite 0 at the top left corner
+    ppu.video_ram[0][0x10] = 0xff;
+    ppu.oam[..4].copy_from_slice(&[16, 8, 0x01, 0x00]);
+    ppu.render_line(0);
+    assert_eq!(ppu.framebuffer[0][0], [254, 248, 208]);
+
+    ppu.resync_caches();
+    ppu.render_line(0);
+    assert_eq!(ppu.framebuffer[0][0], [136, 192, 112]);
+    assert_eq!(ppu.sprites_on_line(0).len(), 1);
+}
+
+#[test]
+fn bgp_is_latched_at_the_start_of_each_line() {
+    let white = [254, 248, 208];
+    let black = [8, 24, 32];
+    let mut ppu = new_ppu();
+    ppu.set(0xff47, 0x00);
+    // Into mode 3 of line 80, the line keeps the palette it started drawing with
+    run_dots(&mut ppu, 80 * 456 + 100);
+    assert_eq!(ppu.mode, Mode::VRAMRead);
+    ppu.set(0xff47, 0x03);
+    run_lines(&mut ppu, 64);
+    assert_eq!(ppu.framebuffer[79][0], white);
+    assert_eq!(ppu.framebuffer[80][0], white);
+    assert_eq!(ppu.framebuffer[81][0], black);
+    assert_eq!(ppu.framebuffer[143][0], black);
+}
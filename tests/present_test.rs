@@ -0,0 +1,29 @@
+use rgb::util::present::{frame_pacing, FramePacing, PresentMode};
+
+#[test]
+fn frame_pacing_follows_present_mode() {
+    // The Game Boy rate ignores the display rate
+    for display_rate in [60, 144, 0].iter() {
+        assert_eq!(
+            frame_pacing(PresentMode::FixedGbRate, *display_rate),
+            FramePacing {
+                max_fps: 60,
+                vsync: false
+            }
+        );
+    }
+    assert_eq!(
+        frame_pacing(PresentMode::SyncToDisplay, 144),
+        FramePacing {
+            max_fps: 144,
+            vsync: true
+        }
+    );
+    assert_eq!(
+        frame_pacing(PresentMode::SyncToDisplay, 0),
+        FramePacing {
+            max_fps: 60,
+            vsync: true
+        }
+    );
+}
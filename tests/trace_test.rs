@@ -0,0 +1,50 @@
+mod common;
+
+use common::rom_with_program;
+use rgb::debug::trace::{compare_trace, divergence_report, Divergence};
+
+#[test]
+fn compare_trace_reports_first_divergence() {
+    // ld a, $42; inc b; nop
+    let rom = rom_with_program(&[0x3e, 0x42, 0x04, 0x00]);
+    let mut reference = vec![
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100",
+        "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102",
+        "A:42 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0103",
+    ];
+    assert_eq!(compare_trace(rom.clone(), &reference), Ok(3));
+
+    // The zero flag is cleared by inc b, a reference keeping it set diverges at the third step
+    reference[2] = "A:42 F:90 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0103";
+    let divergence = compare_trace(rom, &reference).unwrap_err();
+    assert_eq!(
+        divergence,
+        Divergence {
+            step: 2,
+            pc: 0x0103,
+            field: String::from("F"),
+            expected: String::from("90"),
+            actual: String::from("10"),
+        }
+    );
+    assert_eq!(
+        divergence.to_string(),
+        "Step 2 at $0103: F is 10, expected 90"
+    );
+}
+
+#[test]
+fn divergence_report_shows_the_preceding_lines() {
+    let reference = ["A:01", "A:02", "A:03", "A:04"];
+    let divergence = Divergence {
+        step: 2,
+        pc: 0x0102,
+        field: String::from("A"),
+        expected: String::from("03"),
+        actual: String::from("42"),
+    };
+    assert_eq!(
+        divergence_report(&reference, &divergence, 1),
+        "         2: A:02\n>        3: A:03\nStep 2 at $0102: A is 42, expected 03\n"
+    );
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::rom_with_program;
+use rgb::cartridge::NINTENDO_LOGO;
+use rgb::util::{open_dropped_file, read_rom};
+use std::path::PathBuf;
+
+#[test]
+fn dropped_file_only_loads_valid_roms() {
+    let dir = std::env::temp_dir();
+    let rom_path = dir.join("rgb_dropped_rom.gb");
+    let text_path = dir.join("rgb_dropped_text.txt");
+    let garbage_path = dir.join("rgb_dropped_garbage.bin");
+    let no_logo_path = dir.join("rgb_dropped_no_logo.gb");
+    let mut rom = rom_with_program(&[0x00]);
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    std::fs::write(&rom_path, &rom).unwrap();
+    std::fs::write(&text_path, b"not a rom").unwrap();
+    // A rom sized file with a header the loader can not handle
+    std::fs::write(&garbage_path, vec![0x5a; 0x8000]).unwrap();
+    std::fs::write(&no_logo_path, rom_with_program(&[0x00])).unwrap();
+
+    let mut loaded: Vec<PathBuf> = Vec::new();
+    assert!(open_dropped_file(rom_path.clone(), |path| loaded.push(path)));
+    assert!(!open_dropped_file(text_path.clone(), |path| loaded.push(path)));
+    assert!(!open_dropped_file(garbage_path.clone(), |path| loaded.push(path)));
+    assert!(!open_dropped_file(no_logo_path.clone(), |path| loaded.push(path)));
+    assert_eq!(loaded, vec![rom_path.clone()]);
+
+    for path in [rom_path, text_path, garbage_path, no_logo_path].iter() {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn read_rom_reports_invalid_roms() {
+    let dir = std::env::temp_dir();
+    let rom_path = dir.join("rgb_read_rom.gb");
+    let text_path = dir.join("rgb_read_text.txt");
+    std::fs::write(&rom_path, rom_with_program(&[0x00])).unwrap();
+    std::fs::write(&text_path, b"not a rom").unwrap();
+
+    assert_eq!(read_rom(&rom_path), Ok(rom_with_program(&[0x00])));
+    assert!(read_rom(&text_path).is_err());
+    assert!(read_rom(&dir.join("rgb_missing_rom.gb")).is_err());
+
+    std::fs::remove_file(rom_path).unwrap();
+    std::fs::remove_file(text_path).unwrap();
+}